@@ -0,0 +1,271 @@
+//! Layout of the color cube bins.
+//!
+//! The unit cube is split into `resolution.x * resolution.y * resolution.z`
+//! equally sized bins. Along an axis with `r` bins, bin `i` covers
+//! `[i / r, (i + 1) / r)`; the last bin also includes 1.0, and values outside
+//! of [0, 1] (or NaN, which counts as 0) fall into the nearest bin. Bins are
+//! indexed as `(x * resolution.y + y) * resolution.z + z`.
+
+use bevy::math::{UVec3, Vec3};
+
+use crate::kernels::Pixel;
+
+/// Where a bin's instance is drawn inside the bin.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BinPosition {
+    /// The geometric center of the bin.
+    #[default]
+    Center,
+    /// The mean color of the pixels in the bin, or the center if it is empty.
+    CenterOfMass,
+}
+
+impl BinPosition {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "center" => Some(BinPosition::Center),
+            "center-of-mass" => Some(BinPosition::CenterOfMass),
+            _ => None,
+        }
+    }
+}
+
+/// What color a bin's instance is drawn with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BinColor {
+    /// The color at the center of the bin.
+    #[default]
+    Lattice,
+    /// The mean color of the pixels in the bin.
+    Mean,
+    /// The per channel median of the pixels in the bin.
+    Median,
+}
+
+impl BinColor {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "lattice" => Some(BinColor::Lattice),
+            "mean" => Some(BinColor::Mean),
+            "median" => Some(BinColor::Median),
+            _ => None,
+        }
+    }
+}
+
+pub fn num_bins(resolution: UVec3) -> usize {
+    resolution.x as usize * resolution.y as usize * resolution.z as usize
+}
+
+/// Size of a single bin along each axis.
+pub fn bin_width(resolution: UVec3) -> Vec3 {
+    resolution.as_vec3().recip()
+}
+
+/// Grid coordinates of the bin a color falls into.
+pub fn bin_coords(c: &Pixel, resolution: UVec3) -> UVec3 {
+    let coord = |x: f32, r: u32| ((x * r as f32).floor() as u32).min(r - 1);
+    UVec3::new(
+        coord(c[0], resolution.x),
+        coord(c[1], resolution.y),
+        coord(c[2], resolution.z),
+    )
+}
+
+pub fn bin_index(c: &Pixel, resolution: UVec3) -> usize {
+    coords_to_index(bin_coords(c, resolution), resolution)
+}
+
+pub fn coords_to_index(coords: UVec3, resolution: UVec3) -> usize {
+    (coords.x as usize * resolution.y as usize + coords.y as usize) * resolution.z as usize
+        + coords.z as usize
+}
+
+pub fn index_to_coords(index: usize, resolution: UVec3) -> UVec3 {
+    let (ry, rz) = (resolution.y as usize, resolution.z as usize);
+    UVec3::new(
+        (index / rz / ry) as u32,
+        (index / rz % ry) as u32,
+        (index % rz) as u32,
+    )
+}
+
+/// Lower and upper corner of a bin.
+pub fn bin_edges(coords: UVec3, resolution: UVec3) -> (Vec3, Vec3) {
+    let width = bin_width(resolution);
+    (
+        coords.as_vec3() * width,
+        (coords + UVec3::ONE).as_vec3() * width,
+    )
+}
+
+pub fn bin_center(coords: UVec3, resolution: UVec3) -> Vec3 {
+    (coords.as_vec3() + Vec3::splat(0.5)) * bin_width(resolution)
+}
+
+/// Mean of the colors summed into a bin. Out of range colors are binned to
+/// the nearest bin, so the mean is clamped to stay inside it.
+pub fn center_of_mass(sum: Vec3, count: u32, coords: UVec3, resolution: UVec3) -> Vec3 {
+    if count == 0 {
+        return bin_center(coords, resolution);
+    }
+    let (min, max) = bin_edges(coords, resolution);
+    (sum / count as f32).clamp(min, max)
+}
+
+/// Mean of the colors summed into a bin, kept displayable.
+pub fn mean_color(sum: Vec3, count: u32) -> Option<Vec3> {
+    (count > 0).then(|| (sum / count as f32).clamp(Vec3::ZERO, Vec3::ONE))
+}
+
+/// Adds every pixel's color to the sum of the bin it falls into.
+pub fn sum_pixels(src: &[Pixel], resolution: UVec3, sums: &mut [Vec3]) {
+    sum_colors(src, src, resolution, sums);
+}
+
+/// Adds every pixel's color in `src` to the sum of the bin its `coords` fall into.
+pub fn sum_colors(src: &[Pixel], coords: &[Pixel], resolution: UVec3, sums: &mut [Vec3]) {
+    for (c, x) in src.iter().zip(coords) {
+        sums[bin_index(x, resolution)] += Vec3::new(c[0], c[1], c[2]);
+    }
+}
+
+/// Adds the color of every pixel in `src` to the samples of the bin its
+/// `coords` fall into.
+pub fn sample_colors(
+    src: &[Pixel],
+    coords: &[Pixel],
+    resolution: UVec3,
+    samples: &mut [Vec<[f32; 3]>],
+) {
+    for (c, x) in src.iter().zip(coords) {
+        samples[bin_index(x, resolution)].push([c[0], c[1], c[2]]);
+    }
+}
+
+/// Per channel median of a bin's samples, zero if it is empty. Bins with an
+/// even count take the upper of the two middle values.
+pub fn median(samples: &[[f32; 3]]) -> Vec3 {
+    if samples.is_empty() {
+        return Vec3::ZERO;
+    }
+    let mut channel = Vec::with_capacity(samples.len());
+    let mut median = [0.0; 3];
+    for (k, m) in median.iter_mut().enumerate() {
+        channel.clear();
+        channel.extend(samples.iter().map(|c| c[k]));
+        let mid = channel.len() / 2;
+        *m = *channel.select_nth_unstable_by(mid, f32::total_cmp).1;
+    }
+    Vec3::from(median)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolution() -> UVec3 {
+        UVec3::new(4, 2, 8)
+    }
+
+    fn coords(x: f32) -> UVec3 {
+        bin_coords(&[x, x, x, 1.0], resolution())
+    }
+
+    #[test]
+    fn zero_is_in_first_bin() {
+        assert_eq!(coords(0.0), UVec3::ZERO);
+        assert_eq!(coords(-0.0), UVec3::ZERO);
+    }
+
+    #[test]
+    fn one_is_in_last_bin() {
+        assert_eq!(coords(1.0), resolution() - UVec3::ONE);
+    }
+
+    #[test]
+    fn out_of_range_is_in_nearest_bin() {
+        assert_eq!(coords(-0.5), UVec3::ZERO);
+        assert_eq!(coords(f32::NEG_INFINITY), UVec3::ZERO);
+        assert_eq!(coords(f32::NAN), UVec3::ZERO);
+        assert_eq!(coords(1.5), resolution() - UVec3::ONE);
+        assert_eq!(coords(f32::INFINITY), resolution() - UVec3::ONE);
+    }
+
+    #[test]
+    fn edges_belong_to_upper_bin() {
+        assert_eq!(coords(0.5), UVec3::new(2, 1, 4));
+        assert_eq!(coords(0.25), UVec3::new(1, 0, 2));
+        assert_eq!(coords(0.2499), UVec3::new(0, 0, 1));
+    }
+
+    #[test]
+    fn colors_are_inside_their_bin() {
+        for i in 0..=100 {
+            let x = i as f32 / 100.0;
+            let (min, max) = bin_edges(coords(x), resolution());
+            assert!(min.cmple(Vec3::splat(x)).all());
+            assert!(max.cmpge(Vec3::splat(x)).all());
+        }
+    }
+
+    #[test]
+    fn centers_are_inside_their_bin() {
+        for index in 0..num_bins(resolution()) {
+            let center = bin_center(index_to_coords(index, resolution()), resolution());
+            assert_eq!(
+                bin_index(&[center.x, center.y, center.z, 1.0], resolution()),
+                index
+            );
+        }
+    }
+
+    #[test]
+    fn index_round_trips() {
+        for index in 0..num_bins(resolution()) {
+            assert_eq!(
+                coords_to_index(index_to_coords(index, resolution()), resolution()),
+                index
+            );
+        }
+    }
+
+    #[test]
+    fn center_of_mass_stays_inside_bin() {
+        let resolution = UVec3::splat(2);
+        let src = [[0.1, 0.2, 0.3, 1.0], [0.3, 0.4, -1.0, 1.0]];
+        let mut sums = vec![Vec3::ZERO; num_bins(resolution)];
+        sum_pixels(&src, resolution, &mut sums);
+
+        let com = center_of_mass(sums[0], 2, UVec3::ZERO, resolution);
+        assert!((com - Vec3::new(0.2, 0.3, 0.0)).abs().max_element() < 1e-6);
+        let empty = center_of_mass(sums[1], 0, UVec3::new(0, 0, 1), resolution);
+        assert_eq!(empty, Vec3::new(0.25, 0.25, 0.75));
+    }
+
+    #[test]
+    fn medians_are_per_bin_and_channel() {
+        let resolution = UVec3::splat(2);
+        let src = [
+            [0.1, 0.4, 0.2, 1.0],
+            [0.3, 0.1, 0.3, 1.0],
+            [0.2, 0.2, 0.1, 1.0],
+            [0.9, 0.9, 0.9, 1.0],
+        ];
+        let mut samples = vec![vec![]; num_bins(resolution)];
+        sample_colors(&src, &src, resolution, &mut samples);
+        assert_eq!(samples[0].len(), 3);
+        assert_eq!(median(&samples[0]), Vec3::new(0.2, 0.2, 0.2));
+        assert_eq!(median(&samples[num_bins(resolution) - 1]), Vec3::splat(0.9));
+        assert_eq!(median(&samples[1]), Vec3::ZERO);
+    }
+
+    #[test]
+    fn mean_color_of_empty_bin_is_none() {
+        assert_eq!(mean_color(Vec3::ZERO, 0), None);
+        assert_eq!(
+            mean_color(Vec3::new(0.5, 1.0, 3.0), 2),
+            Some(Vec3::new(0.25, 0.5, 1.0))
+        );
+    }
+}
@@ -43,6 +43,7 @@ pub fn create_camera(mut commands: Commands) {
         });
 }
 
+#[allow(clippy::type_complexity)]
 pub fn move_camera(
     mut events: EventReader<CameraMoveEvent>,
     mut query: QuerySet<(
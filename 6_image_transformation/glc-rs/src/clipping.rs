@@ -0,0 +1,130 @@
+use bevy::prelude::*;
+
+/// Half space of the cube's unit space, keeping the points `p` with
+/// `normal.dot(p) <= offset`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ClipPlane {
+    pub normal: Vec3,
+    pub offset: f32,
+}
+
+impl ClipPlane {
+    /// Normalizes `normal`, scaling `offset` along. `None` for a zero normal.
+    pub fn new(normal: Vec3, offset: f32) -> Option<Self> {
+        let length = normal.length();
+        (length > f32::EPSILON).then(|| ClipPlane {
+            normal: normal / length,
+            offset: offset / length,
+        })
+    }
+
+    pub fn contains(&self, p: Vec3) -> bool {
+        self.normal.dot(p) <= self.offset
+    }
+}
+
+/// Layer of the cube's unit space, keeping the points `p` with
+/// `min <= normal.dot(p) <= max`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Slice {
+    pub normal: Vec3,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Slice {
+    /// Normalizes `normal`, scaling the bounds along. `None` for a zero normal.
+    pub fn new(normal: Vec3, min: f32, max: f32) -> Option<Self> {
+        let length = normal.length();
+        (length > f32::EPSILON).then(|| Slice {
+            normal: normal / length,
+            min: min.min(max) / length,
+            max: max.max(min) / length,
+        })
+    }
+
+    pub fn contains(&self, p: Vec3) -> bool {
+        let d = self.normal.dot(p);
+        self.min <= d && d <= self.max
+    }
+}
+
+/*
+ * Settings for hiding parts of the color cube
+ */
+#[derive(Clone, Debug, Default)]
+pub struct Clipping {
+    pub planes: Vec<ClipPlane>,
+    /// Only show a single layer, inside the clipping planes
+    pub slice: Option<Slice>,
+}
+
+impl Clipping {
+    pub fn is_enabled(&self) -> bool {
+        !self.planes.is_empty() || self.slice.is_some()
+    }
+
+    /// Whether an instance drawn at `p`, in the cube's unit space, is shown.
+    pub fn contains(&self, p: Vec3) -> bool {
+        self.planes.iter().all(|plane| plane.contains(p))
+            && self.slice.map_or(true, |slice| slice.contains(p))
+    }
+}
+
+/// Clips the instances of an entity by their position, when they are
+/// extracted for drawing. Segments are clipped by their start.
+#[derive(Component)]
+pub struct Clipped;
+
+#[derive(Clone, Debug)]
+pub struct SetClipPlanesEvent {
+    pub planes: Vec<ClipPlane>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SetSliceEvent {
+    pub slice: Option<Slice>,
+}
+
+pub fn set_clip_planes(
+    mut events: EventReader<SetClipPlanesEvent>,
+    mut clipping: ResMut<Clipping>,
+) {
+    let evts = events.iter().collect::<Vec<_>>();
+    if let Some(evt) = evts.into_iter().last() {
+        clipping.planes = evt.planes.clone();
+    }
+}
+
+pub fn set_slice(mut events: EventReader<SetSliceEvent>, mut clipping: ResMut<Clipping>) {
+    let evts = events.iter().collect::<Vec<_>>();
+    if let Some(evt) = evts.into_iter().last() {
+        clipping.slice = evt.slice;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn planes_and_slice_intersect() {
+        let clipping = Clipping {
+            planes: vec![ClipPlane::new(Vec3::new(-2.0, 0.0, 0.0), -1.0).unwrap()],
+            slice: Slice::new(Vec3::Z, 0.5, 0.4),
+        };
+        assert!(clipping.contains(Vec3::new(0.6, 0.0, 0.45)));
+        // Red below 0.5
+        assert!(!clipping.contains(Vec3::new(0.4, 0.0, 0.45)));
+        // Blue outside [0.4, 0.5]
+        assert!(!clipping.contains(Vec3::new(0.6, 0.0, 0.55)));
+        assert!(!clipping.contains(Vec3::new(0.6, 0.0, 0.35)));
+    }
+
+    #[test]
+    fn zero_normals_are_rejected() {
+        assert!(ClipPlane::new(Vec3::ZERO, 0.5).is_none());
+        assert!(Slice::new(Vec3::ZERO, 0.4, 0.5).is_none());
+        assert!(!Clipping::default().is_enabled());
+    }
+}
@@ -1,94 +1,728 @@
+use std::collections::BTreeMap;
+
 use bevy::prelude::*;
 
+use crate::binning::{self, BinColor, BinPosition};
+use crate::clipping::Clipped;
 use crate::image;
+use crate::kernels;
+use crate::layout::Layout;
+use crate::normalization::Normalization;
+use crate::parallel;
+use crate::processing;
 use crate::render::{InstanceData, InstancedMesh};
+use crate::smoothing::{DensityJob, Smoothing};
 
-#[derive(Component)]
+const LAYOUT_ANIMATION_SECS: f32 = 0.75;
+/// Most bins along an axis, which already makes two million instances.
+pub const MAX_RESOLUTION: u32 = 128;
+
+#[derive(Component, Clone, Copy, Debug)]
 pub struct ColorCube {
-    pub resolution: u32,
-    pub threshold: f32,
+    /// Number of bins along each axis of the layout's coordinates
+    pub resolution: UVec3,
+    pub layout: Layout,
+    pub normalization: Normalization,
+    pub position: BinPosition,
+    pub color: BinColor,
+    pub smoothing: Smoothing,
 }
 
-#[derive(Clone, Debug)]
-pub struct UpdateColorCubeEvent;
+impl ColorCube {
+    /// Whether the histogram has to accumulate the coordinate sums of each bin.
+    pub fn needs_sums(&self) -> bool {
+        self.position == BinPosition::CenterOfMass
+    }
 
-pub fn create_instance_data(resolution: u32) -> Vec<InstanceData> {
-    let mut data = vec![];
-    let step = 1.0 / (resolution - 1) as f32;
-
-    for xi in 0..resolution {
-        let x = xi as f32 * step;
-        for yi in 0..resolution {
-            let y = yi as f32 * step;
-            for zi in 0..resolution {
-                let z = zi as f32 * step;
-
-                data.push(InstanceData {
-                    position: Vec3::new(x, y, z),
-                    color: Color::rgb(x, y, z).as_rgba_f32(),
-                    scale: step,
-                });
+    /// Whether the histogram has to accumulate the color sums of each bin.
+    pub fn needs_color_sums(&self) -> bool {
+        self.color == BinColor::Mean
+    }
+
+    /// Whether the histogram has to keep the colors of the pixels in each bin.
+    pub fn needs_samples(&self) -> bool {
+        self.color == BinColor::Median
+    }
+}
+
+/// Marks entities drawn in the unit cube of the color cube, which all share
+/// its transform.
+#[derive(Component)]
+pub struct CubeSpace;
+
+/// Pixel counts per bin, accumulated across frames by `update_color_cube`.
+#[derive(Component)]
+pub struct Histogram {
+    pub counts: Vec<u32>,
+    /// Sum of the layout coordinates in each bin, only accumulated if
+    /// `ColorCube::needs_sums`
+    pub sums: Vec<Vec3>,
+    /// Sum of the colors in each bin, only accumulated if `ColorCube::needs_color_sums`
+    pub color_sums: Vec<Vec3>,
+    /// Colors of the pixels in each bin, only kept if `ColorCube::needs_samples`,
+    /// so that a region update only has to recompute the medians of its bins
+    pub samples: Vec<Vec<[f32; 3]>>,
+    /// Median color of each bin, taken from its samples
+    pub medians: Vec<Vec3>,
+    /// Counts smoothed with `ColorCube::smoothing`, which the bins are sized
+    /// from
+    pub density: Vec<f32>,
+}
+
+impl Histogram {
+    pub fn new(num_bins: usize) -> Self {
+        Histogram {
+            counts: vec![0; num_bins],
+            sums: vec![Vec3::ZERO; num_bins],
+            color_sums: vec![Vec3::ZERO; num_bins],
+            samples: vec![vec![]; num_bins],
+            medians: vec![Vec3::ZERO; num_bins],
+            density: vec![0.0; num_bins],
+        }
+    }
+
+    /// Empties the bins, releasing the samples if the cube doesn't keep them.
+    fn clear(&mut self, cube: &ColorCube) {
+        for count in self.counts.iter_mut() {
+            *count = 0;
+        }
+        for sum in self.sums.iter_mut() {
+            *sum = Vec3::ZERO;
+        }
+        for sum in self.color_sums.iter_mut() {
+            *sum = Vec3::ZERO;
+        }
+        for samples in self.samples.iter_mut() {
+            samples.clear();
+            if !cube.needs_samples() {
+                samples.shrink_to_fit();
             }
         }
     }
 
-    data
+    /// Bins the pixels of `src`, accumulating whatever the cube needs.
+    fn add_pixels(&mut self, cube: &ColorCube, src: &[kernels::Pixel]) {
+        let coords = cube.layout.convert(src);
+        parallel::bin_pixels(&coords, cube.resolution, &mut self.counts);
+        if cube.needs_sums() {
+            binning::sum_pixels(&coords, cube.resolution, &mut self.sums);
+        }
+        if cube.needs_color_sums() {
+            binning::sum_colors(src, &coords, cube.resolution, &mut self.color_sums);
+        }
+        if cube.needs_samples() {
+            binning::sample_colors(src, &coords, cube.resolution, &mut self.samples);
+        }
+    }
+
+    /// Moves the pixels of an edited region from the bins of their `removed`
+    /// colors to those of their `added` ones. Every touched bin is recorded in
+    /// `changes` along with the change of its count.
+    fn update_region(
+        &mut self,
+        cube: &ColorCube,
+        removed: &[kernels::Pixel],
+        added: &[kernels::Pixel],
+        changes: &mut BTreeMap<usize, i32>,
+    ) {
+        for c in removed.iter() {
+            let x = cube.layout.convert_pixel(c);
+            let i = binning::bin_index(&x, cube.resolution);
+            self.counts[i] = self.counts[i].saturating_sub(1);
+            if cube.needs_sums() {
+                self.sums[i] -= Vec3::new(x[0], x[1], x[2]);
+            }
+            if cube.needs_color_sums() {
+                self.color_sums[i] -= Vec3::new(c[0], c[1], c[2]);
+            }
+            if cube.needs_samples() {
+                // Compared bitwise, so that NaN samples are found as well
+                let bits = [c[0].to_bits(), c[1].to_bits(), c[2].to_bits()];
+                let samples = &mut self.samples[i];
+                if let Some(k) = samples.iter().position(|s| s.map(f32::to_bits) == bits) {
+                    samples.swap_remove(k);
+                }
+            }
+            *changes.entry(i).or_insert(0) -= 1;
+        }
+        for c in added.iter() {
+            let x = cube.layout.convert_pixel(c);
+            let i = binning::bin_index(&x, cube.resolution);
+            self.counts[i] += 1;
+            if cube.needs_sums() {
+                self.sums[i] += Vec3::new(x[0], x[1], x[2]);
+            }
+            if cube.needs_color_sums() {
+                self.color_sums[i] += Vec3::new(c[0], c[1], c[2]);
+            }
+            if cube.needs_samples() {
+                self.samples[i].push([c[0], c[1], c[2]]);
+            }
+            *changes.entry(i).or_insert(0) += 1;
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UpdateColorCubeEvent;
+
+#[derive(Clone, Debug)]
+pub struct SetNormalizationEvent {
+    pub normalization: Normalization,
+}
+
+#[derive(Clone, Debug)]
+pub struct SetCubeResolutionEvent {
+    pub resolution: UVec3,
+}
+
+#[derive(Clone, Debug)]
+pub struct SetBinPositionEvent {
+    pub position: BinPosition,
+}
+
+#[derive(Clone, Debug)]
+pub struct SetBinColorEvent {
+    pub color: BinColor,
+}
+
+#[derive(Clone, Debug)]
+pub struct SetSmoothingEvent {
+    pub smoothing: Smoothing,
+}
+
+#[derive(Clone, Debug)]
+pub struct SetLayoutEvent {
+    pub layout: Layout,
+}
+
+/// Moves the instances from where their lattice color was in the previous
+/// layout. Waits for the histogram to be rebinned in the new layout, so that
+/// the instances are already sized and colored for it.
+#[derive(Component)]
+pub struct LayoutAnimation {
+    from: Layout,
+    elapsed: f32,
+    started: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct SetCubeSizeEvent {
+    pub size: f32,
+}
+
+/// Sent once the histogram is complete, after a full pass or a region update.
+#[derive(Clone, Debug)]
+pub struct HistogramUpdatedEvent;
+
+/// Output pixels that changed value, before and after the change.
+#[derive(Clone, Debug)]
+pub struct UpdateColorCubeRegionEvent {
+    pub removed: Vec<kernels::Pixel>,
+    pub added: Vec<kernels::Pixel>,
+}
+
+/// Largest size an instance can have without overlapping its neighbours.
+pub fn max_instance_scale(resolution: UVec3) -> f32 {
+    binning::bin_width(resolution).min_element()
+}
+
+/// Color at the center of a bin, clamped to the displayable range.
+pub fn lattice_color(coords: UVec3, resolution: UVec3, layout: Layout) -> Vec3 {
+    let center = binning::bin_center(coords, resolution);
+    layout.to_rgb(center).clamp(Vec3::ZERO, Vec3::ONE)
+}
+
+pub fn create_instance_data(resolution: UVec3, layout: Layout) -> Vec<InstanceData> {
+    (0..binning::num_bins(resolution))
+        .map(|i| {
+            let coords = binning::index_to_coords(i, resolution);
+            let color = lattice_color(coords, resolution, layout);
+            InstanceData {
+                position: layout.position(binning::bin_center(coords, resolution)),
+                color: Color::rgb(color.x, color.y, color.z).as_rgba_f32(),
+                scale: Vec3::splat(max_instance_scale(resolution)),
+            }
+        })
+        .collect()
+}
+
+pub fn cube_transform(size: f32) -> Transform {
+    Transform {
+        translation: Vec3::new(-size / 2.0, -size / 2.0, -size / 2.0),
+        rotation: Quat::IDENTITY,
+        scale: Vec3::new(size, size, size),
+    }
 }
 
 pub fn create_color_cube(
     mut commands: Commands,
-    resolution: u32,
+    cube: ColorCube,
     mesh: Handle<Mesh>,
-    size: f32,
-    threshold: f32,
+    transform: Transform,
+    visibility: Visibility,
 ) {
-    let instance_data = create_instance_data(resolution);
+    let instance_data = create_instance_data(cube.resolution, cube.layout);
+    let num_bins = instance_data.len();
     commands.spawn_bundle((
-        Transform {
-            translation: Vec3::new(-size / 2.0, -size / 2.0, -size / 2.0),
-            rotation: Quat::IDENTITY,
-            scale: Vec3::new(size, size, size),
-        },
+        transform,
         GlobalTransform::identity(),
         mesh,
         InstancedMesh(instance_data),
-        ColorCube {
-            resolution,
-            threshold,
-        },
-        Visibility::default(),
+        Histogram::new(num_bins),
+        cube,
+        CubeSpace,
+        Clipped,
+        visibility,
         ComputedVisibility::default(),
     ));
 }
 
+/// Replaces the color cube with one of the requested resolution and bins the
+/// output image into it.
+pub fn set_cube_resolution(
+    mut events: EventReader<SetCubeResolutionEvent>,
+    mut commands: Commands,
+    query: Query<(Entity, &Handle<Mesh>, &Transform, &Visibility, &ColorCube)>,
+    mut out_events: EventWriter<UpdateColorCubeEvent>,
+) {
+    let evts = events.iter().collect::<Vec<_>>();
+    if let Some(evt) = evts.into_iter().last() {
+        if let Some((entity, mesh, transform, visibility, cube)) = query.iter().last() {
+            commands.entity(entity).despawn();
+            let cube = ColorCube {
+                resolution: evt
+                    .resolution
+                    .max(UVec3::ONE)
+                    .min(UVec3::splat(MAX_RESOLUTION)),
+                ..*cube
+            };
+            let visibility = Visibility {
+                is_visible: visibility.is_visible,
+            };
+            create_color_cube(commands, cube, mesh.clone(), *transform, visibility);
+            out_events.send(UpdateColorCubeEvent);
+        }
+    }
+}
+
+pub fn set_cube_size(
+    mut events: EventReader<SetCubeSizeEvent>,
+    mut query: Query<&mut Transform, With<CubeSpace>>,
+) {
+    let evts = events.iter().collect::<Vec<_>>();
+    if let Some(evt) = evts.into_iter().last() {
+        for mut transform in query.iter_mut() {
+            *transform = cube_transform(evt.size);
+        }
+    }
+}
+
+fn bin_position(histogram: &Histogram, cube: &ColorCube, i: usize, coords: UVec3) -> Vec3 {
+    let resolution = cube.resolution;
+    let position = match cube.position {
+        BinPosition::Center => binning::bin_center(coords, resolution),
+        BinPosition::CenterOfMass => {
+            binning::center_of_mass(histogram.sums[i], histogram.counts[i], coords, resolution)
+        }
+    };
+    cube.layout.position(position)
+}
+
+fn bin_color(histogram: &Histogram, cube: &ColorCube, i: usize, coords: UVec3) -> Vec3 {
+    let count = histogram.counts[i];
+    let color = match cube.color {
+        BinColor::Lattice => None,
+        BinColor::Mean => binning::mean_color(histogram.color_sums[i], count),
+        BinColor::Median => (count > 0).then(|| histogram.medians[i]),
+    };
+    color.unwrap_or_else(|| lattice_color(coords, cube.resolution, cube.layout))
+}
+
+/// Starts smoothing the counts into the density. Without smoothing the density
+/// is the counts, and the job is done right away.
+fn start_density(job: &mut DensityJob, histogram: &mut Histogram, cube: &ColorCube) {
+    let Histogram {
+        counts, density, ..
+    } = histogram;
+    let wrap_hue = cube.layout.wraps_hue();
+    job.start(cube.smoothing, counts, cube.resolution, wrap_hue, density);
+}
+
+/// Adds the smoothed change of the counts to the density, and returns the
+/// bins whose density or pixels changed. `None` when the change spreads over
+/// much of the cube, and the density has to be smoothed again.
+fn update_density_region(
+    histogram: &mut Histogram,
+    cube: &ColorCube,
+    changes: &BTreeMap<usize, i32>,
+) -> Option<Vec<usize>> {
+    let deltas = changes
+        .iter()
+        .filter(|(_, &d)| d != 0)
+        .map(|(&i, &d)| (i, d as f32))
+        .collect::<Vec<_>>();
+    let wrap_hue = cube.layout.wraps_hue();
+    let spread = cube.smoothing.spread(&deltas, cube.resolution, wrap_hue)?;
+    for &(i, d) in spread.iter() {
+        histogram.density[i] += d;
+    }
+    let mut bins = spread
+        .into_iter()
+        .map(|(i, _)| i)
+        .chain(changes.keys().copied())
+        .collect::<Vec<_>>();
+    bins.sort_unstable();
+    bins.dedup();
+    Some(bins)
+}
+
+/// Sizes, places and colors the instances of the given bins from their pixels.
+/// Bins whose size depends on the whole histogram are always all updated.
+fn update_bins(
+    mesh: &mut InstancedMesh,
+    histogram: &Histogram,
+    cube: &ColorCube,
+    num_pixels: u32,
+    bins: impl Iterator<Item = usize>,
+) {
+    let resolution = cube.resolution;
+    let max_scale = max_instance_scale(resolution);
+    let scaling = cube.normalization.scaling(&histogram.density, num_pixels);
+    let mut update = |i: usize| {
+        let coords = binning::index_to_coords(i, resolution);
+        mesh.0[i].scale = Vec3::splat(scaling.apply(histogram.density[i]) * max_scale);
+        mesh.0[i].position = bin_position(histogram, cube, i, coords);
+        let color = bin_color(histogram, cube, i, coords);
+        mesh.0[i].color = Color::rgb(color.x, color.y, color.z).as_rgba_f32();
+    };
+    if cube.normalization.is_local() {
+        bins.for_each(&mut update);
+    } else {
+        (0..histogram.counts.len()).for_each(&mut update);
+    }
+}
+
+/// Applies a new normalization to the existing counts, without re-binning.
+pub fn set_normalization(
+    mut events: EventReader<SetNormalizationEvent>,
+    image_query: Query<&image::Image, With<image::Output>>,
+    mut cube_query: Query<(&mut InstancedMesh, &Histogram, &mut ColorCube)>,
+) {
+    let evts = events.iter().collect::<Vec<_>>();
+    if let Some(evt) = evts.into_iter().last() {
+        if let Some(image) = image_query.iter().last() {
+            if let Some((mut mesh, histogram, mut cube)) = cube_query.iter_mut().last() {
+                cube.normalization = evt.normalization;
+                let num_pixels = image.width * image.height;
+                let bins = 0..histogram.counts.len();
+                update_bins(&mut mesh, histogram, &cube, num_pixels, bins);
+            }
+        }
+    }
+}
+
+/// Switches where instances are drawn. Center of mass needs per-bin color
+/// sums, so the histogram is rebuilt.
+pub fn set_bin_position(
+    mut events: EventReader<SetBinPositionEvent>,
+    mut query: Query<&mut ColorCube>,
+    mut out_events: EventWriter<UpdateColorCubeEvent>,
+) {
+    let evts = events.iter().collect::<Vec<_>>();
+    if let Some(evt) = evts.into_iter().last() {
+        if let Some(mut cube) = query.iter_mut().last() {
+            cube.position = evt.position;
+            out_events.send(UpdateColorCubeEvent);
+        }
+    }
+}
+
+/// Switches how instances are colored. Mean and median colors need more than
+/// the counts, so the histogram is rebuilt.
+pub fn set_bin_color(
+    mut events: EventReader<SetBinColorEvent>,
+    mut query: Query<&mut ColorCube>,
+    mut out_events: EventWriter<UpdateColorCubeEvent>,
+) {
+    let evts = events.iter().collect::<Vec<_>>();
+    if let Some(evt) = evts.into_iter().last() {
+        if let Some(mut cube) = query.iter_mut().last() {
+            cube.color = evt.color;
+            out_events.send(UpdateColorCubeEvent);
+        }
+    }
+}
+
+/// Switches the kernel the counts are smoothed with. The histograms are
+/// rebuilt, so that the input histogram of the comparison is smoothed alike.
+pub fn set_smoothing(
+    mut events: EventReader<SetSmoothingEvent>,
+    mut query: Query<&mut ColorCube>,
+    mut out_events: EventWriter<UpdateColorCubeEvent>,
+) {
+    let evts = events.iter().collect::<Vec<_>>();
+    if let Some(evt) = evts.into_iter().last() {
+        if let Some(mut cube) = query.iter_mut().last() {
+            cube.smoothing = evt.smoothing;
+            out_events.send(UpdateColorCubeEvent);
+        }
+    }
+}
+
+/// Rebins the histogram in another color space and animates the instances
+/// over to the new layout.
+pub fn set_layout(
+    mut events: EventReader<SetLayoutEvent>,
+    mut commands: Commands,
+    mut query: Query<(Entity, &mut ColorCube)>,
+    mut out_events: EventWriter<UpdateColorCubeEvent>,
+) {
+    let evts = events.iter().collect::<Vec<_>>();
+    if let Some(evt) = evts.into_iter().last() {
+        if let Some((entity, mut cube)) = query.iter_mut().last() {
+            if cube.layout == evt.layout {
+                return;
+            }
+            commands.entity(entity).insert(LayoutAnimation {
+                from: cube.layout,
+                elapsed: 0.0,
+                started: false,
+            });
+            cube.layout = evt.layout;
+            out_events.send(UpdateColorCubeEvent);
+        }
+    }
+}
+
+pub fn animate_layout(
+    mut events: EventReader<HistogramUpdatedEvent>,
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(
+        Entity,
+        &mut InstancedMesh,
+        &Histogram,
+        &ColorCube,
+        &mut LayoutAnimation,
+    )>,
+) {
+    let updated = events.iter().count() > 0;
+    for (entity, mut mesh, histogram, cube, mut animation) in query.iter_mut() {
+        if !animation.started {
+            if !updated {
+                continue;
+            }
+            animation.started = true;
+        } else {
+            animation.elapsed += time.delta_seconds();
+        }
+        let t = (animation.elapsed / LAYOUT_ANIMATION_SECS).min(1.0);
+        let t = t * t * (3.0 - 2.0 * t);
+        let from = animation.from;
+        for (i, instance) in mesh.0.iter_mut().enumerate() {
+            let coords = binning::index_to_coords(i, cube.resolution);
+            let color = lattice_color(coords, cube.resolution, cube.layout);
+            let start = from.position(from.coords(color));
+            instance.position = start.lerp(bin_position(histogram, cube, i, coords), t);
+        }
+        if t >= 1.0 {
+            commands.entity(entity).remove::<LayoutAnimation>();
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn update_color_cube(
     mut events: EventReader<UpdateColorCubeEvent>,
+    mut region_events: EventReader<UpdateColorCubeRegionEvent>,
+    mut cancel_events: EventReader<image::TransformStartedEvent>,
+    mut job: Local<processing::Job>,
+    mut median_job: Local<processing::Job>,
+    mut density_job: Local<DensityJob>,
+    settings: Res<processing::ProcessingSettings>,
+    budget: Res<processing::FrameBudget>,
     image_query: Query<&image::Image, With<image::Output>>,
-    mut cube_query: Query<(&mut InstancedMesh, &ColorCube)>,
+    mut cube_query: Query<(&mut InstancedMesh, &mut Histogram, &ColorCube)>,
+    mut out_progress_events: EventWriter<processing::ProcessingProgressEvent>,
+    mut out_events: EventWriter<HistogramUpdatedEvent>,
 ) {
+    // The output image is being rewritten, so any partial histogram is stale
+    if cancel_events.iter().count() > 0 {
+        job.cancel();
+        median_job.cancel();
+        density_job.cancel();
+    }
+
     let evts = events.iter().collect::<Vec<_>>();
-    if let Some(_evt) = evts.into_iter().last() {
-        if let Some(image) = image_query.iter().last() {
-            let num_pixels = (image.width * image.height) as f32;
-            if let Some((mut mesh, cube)) = cube_query.iter_mut().last() {
-                for d in mesh.0.iter_mut() {
-                    d.scale = 0.0;
+    let regions = region_events.iter().collect::<Vec<_>>();
+    if let Some(image) = image_query.iter().last() {
+        if let Some((mut mesh, mut histogram, cube)) = cube_query.iter_mut().last() {
+            let num_pixels = image.width * image.height;
+            // Changed pixels may already have been binned by a pass in flight
+            if evts.into_iter().last().is_some() || (job.is_active() && !regions.is_empty()) {
+                histogram.clear(cube);
+                median_job.cancel();
+                density_job.cancel();
+                job.start(image.data.len());
+            } else if !regions.is_empty() {
+                let mut changes = BTreeMap::new();
+                for evt in regions {
+                    histogram.update_region(cube, &evt.removed, &evt.added, &mut changes);
                 }
-                let r = cube.resolution as usize;
-                let r2 = r * r;
-                let step = 1.0 / (r - 1) as f32;
-                for c in image.data.iter() {
-                    let xi = ((c.r() * cube.resolution as f32).floor() as usize).clamp(0, r - 1);
-                    let yi = ((c.g() * cube.resolution as f32).floor() as usize).clamp(0, r - 1);
-                    let zi = ((c.b() * cube.resolution as f32).floor() as usize).clamp(0, r - 1);
-                    let idx = xi * r2 + yi * r + zi;
-                    mesh.0[idx].scale += step;
+                // Medians can't be updated incrementally, but only the touched bins changed
+                if cube.needs_samples() {
+                    for &i in changes.keys() {
+                        histogram.medians[i] = binning::median(&histogram.samples[i]);
+                    }
                 }
-                let threshold = cube.threshold * num_pixels;
-                for d in mesh.0.iter_mut() {
-                    d.scale = (d.scale / threshold).clamp(0.0, step);
+                // A smoothing pass in flight may already have read the old counts
+                let bins = if density_job.is_active() {
+                    None
+                } else {
+                    update_density_region(&mut histogram, cube, &changes)
+                };
+                match bins {
+                    Some(bins) => {
+                        update_bins(&mut mesh, &histogram, cube, num_pixels, bins.into_iter());
+                        if !job.is_active() && !median_job.is_active() {
+                            out_events.send(HistogramUpdatedEvent);
+                        }
+                    }
+                    // Left to the pass below, unless the counts are still being binned
+                    None if !job.is_active() && !median_job.is_active() => {
+                        start_density(&mut density_job, &mut histogram, cube);
+                    }
+                    None => {}
                 }
             }
+
+            let mut finished = false;
+            if job.is_active() {
+                finished = job.run(&settings, &budget, |range| {
+                    histogram.add_pixels(cube, &image.data[range]);
+                });
+                out_progress_events.send(job.progress(processing::ProcessingStage::Histogram));
+                // The medians are taken in a pass of their own, over the bins
+                if finished && cube.needs_samples() {
+                    median_job.start(histogram.counts.len());
+                    finished = false;
+                }
+            } else if median_job.is_active() {
+                // Chunks of bins holding about as many pixels as the binning's chunks
+                let chunk_size =
+                    settings.chunk_size * histogram.counts.len() / num_pixels.max(1) as usize;
+                let Histogram {
+                    samples, medians, ..
+                } = &mut *histogram;
+                finished = median_job.run_chunks(chunk_size, &budget, |range| {
+                    for i in range {
+                        medians[i] = binning::median(&samples[i]);
+                    }
+                });
+                out_progress_events.send(median_job.progress(processing::ProcessingStage::Medians));
+            }
+
+            // The density is smoothed in a pass of its own, once the counts are complete
+            if finished {
+                start_density(&mut density_job, &mut histogram, cube);
+            }
+            if density_job.is_active() {
+                let chunk_size = settings.chunk_size;
+                finished = density_job.run(&mut histogram.density, chunk_size, &budget);
+                out_progress_events.send(density_job.progress());
+            }
+
+            if finished {
+                let bins = 0..histogram.counts.len();
+                update_bins(&mut mesh, &histogram, cube, num_pixels, bins);
+                out_events.send(HistogramUpdatedEvent);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_cube(position: BinPosition, color: BinColor) -> ColorCube {
+        ColorCube {
+            resolution: UVec3::splat(4),
+            layout: Layout::Rgb,
+            normalization: Default::default(),
+            position,
+            color,
+            smoothing: Default::default(),
         }
     }
+
+    /// Bins `before`, moves `region` over to the colors of `after`, and checks
+    /// the result against binning `after` from scratch.
+    fn assert_region_update_matches_rebin(
+        cube: &ColorCube,
+        before: &[kernels::Pixel],
+        after: &[kernels::Pixel],
+        region: &[usize],
+    ) {
+        let num_bins = binning::num_bins(cube.resolution);
+        let mut histogram = Histogram::new(num_bins);
+        histogram.add_pixels(cube, before);
+        let counts_before = histogram.counts.clone();
+        let removed = region.iter().map(|&i| before[i]).collect::<Vec<_>>();
+        let added = region.iter().map(|&i| after[i]).collect::<Vec<_>>();
+        let mut changes = BTreeMap::new();
+        histogram.update_region(cube, &removed, &added, &mut changes);
+
+        let mut expected = Histogram::new(num_bins);
+        expected.add_pixels(cube, after);
+        assert_eq!(histogram.counts, expected.counts);
+        for (a, b) in histogram.sums.iter().zip(&expected.sums) {
+            assert!(a.abs_diff_eq(*b, 1e-4), "{a} {b}");
+        }
+        for (a, b) in histogram.color_sums.iter().zip(&expected.color_sums) {
+            assert!(a.abs_diff_eq(*b, 1e-4), "{a} {b}");
+        }
+        let bits = |samples: &[[f32; 3]]| {
+            let mut bits = samples
+                .iter()
+                .map(|s| s.map(f32::to_bits))
+                .collect::<Vec<_>>();
+            bits.sort_unstable();
+            bits
+        };
+        for (a, b) in histogram.samples.iter().zip(&expected.samples) {
+            assert_eq!(bits(a), bits(b));
+        }
+        for (i, (&after, &before)) in expected.counts.iter().zip(&counts_before).enumerate() {
+            let change = after as i32 - before as i32;
+            assert_eq!(changes.get(&i).copied().unwrap_or(0), change);
+        }
+    }
+
+    #[test]
+    fn region_updates_match_a_full_rebin() {
+        let before = (0..64)
+            .map(|i| {
+                let f = i as f32 / 64.0;
+                [f, (f * 7.0).fract(), (f * 13.0).fract(), 1.0]
+            })
+            .collect::<Vec<_>>();
+        // A 3x3 region of the 8x8 image
+        let region = (2..5)
+            .flat_map(|y| (3..6).map(move |x| y * 8 + x))
+            .collect::<Vec<_>>();
+        let mut after = before.clone();
+        for &i in region.iter() {
+            after[i] = [1.0 - before[i][1], before[i][0], 0.5, 1.0];
+        }
+        let cube = new_cube(BinPosition::CenterOfMass, BinColor::Mean);
+        assert_region_update_matches_rebin(&cube, &before, &after, &region);
+
+        // Samples that aren't equal to themselves are still removed
+        let mut before = before;
+        before[region[0]] = [f32::NAN, 0.2, 0.3, 1.0];
+        let cube = new_cube(BinPosition::Center, BinColor::Median);
+        assert_region_update_matches_rebin(&cube, &before, &after, &region);
+    }
 }
@@ -0,0 +1,210 @@
+use bevy::prelude::*;
+
+use crate::binning;
+use crate::clipping::Clipped;
+use crate::color_cube::{self, ColorCube, CubeSpace};
+use crate::image;
+use crate::processing;
+use crate::proxy;
+use crate::render::{InstanceData, InstancedMesh, Translucent};
+
+const GHOST_ALPHA: f32 = 0.2;
+/// Displacements shorter than this fraction of a bin are not drawn.
+const MIN_DISPLACEMENT: f32 = 0.25;
+
+/*
+ * Settings for comparing the input histogram with the output
+ */
+#[derive(Default)]
+pub struct ComparisonSettings {
+    pub show_input: bool,
+    pub show_vectors: bool,
+}
+
+/// Input histogram, drawn translucent over the color cube.
+#[derive(Component)]
+pub struct InputGhost;
+
+/// Segments from each input bin to where the transformation moved its pixels.
+#[derive(Component)]
+pub struct DisplacementVectors;
+
+#[derive(Clone, Debug)]
+pub struct SetComparisonEvent {
+    pub show_input: bool,
+    pub show_vectors: bool,
+}
+
+/// Input pixel counts per bin, and the sum of the positions their output
+/// colors are drawn at.
+#[derive(Default)]
+pub struct Displacements {
+    counts: Vec<u32>,
+    ends: Vec<Vec3>,
+}
+
+pub fn create_comparison(
+    commands: &mut Commands,
+    mesh: Handle<Mesh>,
+    segment_mesh: Handle<Mesh>,
+    transform: Transform,
+) {
+    commands.spawn_bundle((
+        transform,
+        GlobalTransform::identity(),
+        mesh,
+        InstancedMesh(vec![]),
+        InputGhost,
+        Translucent,
+        CubeSpace,
+        Clipped,
+        Visibility { is_visible: false },
+        ComputedVisibility::default(),
+    ));
+    commands.spawn_bundle((
+        transform,
+        GlobalTransform::identity(),
+        segment_mesh,
+        InstancedMesh(vec![]),
+        DisplacementVectors,
+        CubeSpace,
+        Clipped,
+        Visibility { is_visible: false },
+        ComputedVisibility::default(),
+    ));
+}
+
+pub fn set_comparison(
+    mut events: EventReader<SetComparisonEvent>,
+    mut comparison: ResMut<ComparisonSettings>,
+    mut ghost_query: Query<&mut Visibility, (With<InputGhost>, Without<DisplacementVectors>)>,
+    mut vectors_query: Query<&mut Visibility, (With<DisplacementVectors>, Without<InputGhost>)>,
+) {
+    let evts = events.iter().collect::<Vec<_>>();
+    if let Some(evt) = evts.into_iter().last() {
+        comparison.show_input = evt.show_input;
+        comparison.show_vectors = evt.show_vectors;
+        for mut visibility in ghost_query.iter_mut() {
+            visibility.is_visible = evt.show_input;
+        }
+        for mut visibility in vectors_query.iter_mut() {
+            visibility.is_visible = evt.show_vectors;
+        }
+    }
+}
+
+/// Bins the input pixels next to their transformed output, once the output
+/// is complete, and lays out the ghost histogram and displacement vectors.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn update_comparison(
+    mut events: EventReader<color_cube::UpdateColorCubeEvent>,
+    mut region_events: EventReader<color_cube::UpdateColorCubeRegionEvent>,
+    mut cancel_events: EventReader<image::TransformStartedEvent>,
+    mut job: Local<processing::Job>,
+    mut displacements: Local<Displacements>,
+    comparison: Res<ComparisonSettings>,
+    settings: Res<processing::ProcessingSettings>,
+    budget: Res<processing::FrameBudget>,
+    input_query: Query<
+        (&image::Image, &proxy::Proxy),
+        (With<image::Input>, Without<image::Output>),
+    >,
+    output_query: Query<&image::Image, With<image::Output>>,
+    cube_query: Query<&ColorCube>,
+    mut ghost_query: Query<&mut InstancedMesh, (With<InputGhost>, Without<DisplacementVectors>)>,
+    mut vectors_query: Query<&mut InstancedMesh, (With<DisplacementVectors>, Without<InputGhost>)>,
+    mut out_progress_events: EventWriter<processing::ProcessingProgressEvent>,
+) {
+    // The output image is being rewritten, so it can't be paired with the input
+    if cancel_events.iter().count() > 0 {
+        job.cancel();
+    }
+    let changed = events.iter().count() + region_events.iter().count() > 0;
+    if !comparison.show_input && !comparison.show_vectors {
+        job.cancel();
+        return;
+    }
+
+    if let Some((input, proxy)) = input_query.iter().last() {
+        if let Some(output) = output_query.iter().last() {
+            if let Some(cube) = cube_query.iter().last() {
+                // The output may have been computed from the proxy
+                let input = proxy.select(input, input.data.len() != output.data.len());
+                if input.data.len() != output.data.len() {
+                    return;
+                }
+
+                let resolution = cube.resolution;
+                let layout = cube.layout;
+                let num_bins = binning::num_bins(resolution);
+                if changed || comparison.is_changed() {
+                    displacements.counts = vec![0; num_bins];
+                    displacements.ends = vec![Vec3::ZERO; num_bins];
+                    job.start(output.data.len());
+                }
+                if !job.is_active() {
+                    return;
+                }
+
+                let Displacements { counts, ends } = &mut *displacements;
+                let finished = job.run(&settings, &budget, |range| {
+                    let src = &input.data[range.clone()];
+                    for (c, out) in src.iter().zip(output.data[range].iter()) {
+                        let i = binning::bin_index(&layout.convert_pixel(c), resolution);
+                        counts[i] += 1;
+                        let rgb = Vec3::new(out[0], out[1], out[2]);
+                        ends[i] += layout.position(layout.coords(rgb));
+                    }
+                });
+                out_progress_events.send(job.progress(processing::ProcessingStage::Comparison));
+                if !finished {
+                    return;
+                }
+
+                let num_pixels = output.width * output.height;
+                let density = cube
+                    .smoothing
+                    .density(counts, resolution, layout.wraps_hue());
+                let scaling = cube.normalization.scaling(&density, num_pixels);
+                let max_scale = color_cube::max_instance_scale(resolution);
+                let bin = |i: usize| {
+                    let coords = binning::index_to_coords(i, resolution);
+                    let start = layout.position(binning::bin_center(coords, resolution));
+                    let color = color_cube::lattice_color(coords, resolution, layout);
+                    (start, color)
+                };
+
+                if let Some(mut mesh) = ghost_query.iter_mut().last() {
+                    mesh.0 = (0..num_bins)
+                        .map(|i| {
+                            let (position, color) = bin(i);
+                            InstanceData {
+                                position,
+                                scale: Vec3::splat(scaling.apply(density[i]) * max_scale),
+                                color: Color::rgba(color.x, color.y, color.z, GHOST_ALPHA)
+                                    .as_rgba_f32(),
+                            }
+                        })
+                        .collect();
+                }
+
+                if let Some(mut mesh) = vectors_query.iter_mut().last() {
+                    mesh.0 = (0..num_bins)
+                        .filter(|&i| counts[i] > 0)
+                        .filter_map(|i| {
+                            let (start, color) = bin(i);
+                            let end = ends[i] / counts[i] as f32;
+                            (end.distance(start) >= MIN_DISPLACEMENT * max_scale).then(|| {
+                                InstanceData {
+                                    position: start,
+                                    scale: end - start,
+                                    color: Color::rgb(color.x, color.y, color.z).as_rgba_f32(),
+                                }
+                            })
+                        })
+                        .collect();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,257 @@
+//! Standard RGB gamuts, and their outlines drawn in the color cube.
+//!
+//! The outlines are the edges of each gamut's RGB cube, converted to the
+//! (possibly out of range) sRGB colors the cube's layouts place.
+
+use bevy::prelude::*;
+
+use crate::color_cube::{ColorCube, CubeSpace};
+use crate::layout;
+use crate::render::{self, InstanceData, InstancedMesh};
+
+/// Points along each edge of an outline.
+const EDGE_SEGMENTS: usize = 32;
+
+/// RGB color space, given by the CIE xy chromaticities of its primaries and
+/// white point.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RgbGamut {
+    pub red: [f32; 2],
+    pub green: [f32; 2],
+    pub blue: [f32; 2],
+    pub white: [f32; 2],
+}
+
+const D65: [f32; 2] = [0.3127, 0.3290];
+
+pub const SRGB: RgbGamut = RgbGamut {
+    red: [0.64, 0.33],
+    green: [0.30, 0.60],
+    blue: [0.15, 0.06],
+    white: D65,
+};
+
+pub const DISPLAY_P3: RgbGamut = RgbGamut {
+    red: [0.680, 0.320],
+    green: [0.265, 0.690],
+    blue: [0.150, 0.060],
+    white: D65,
+};
+
+pub const REC_2020: RgbGamut = RgbGamut {
+    red: [0.708, 0.292],
+    green: [0.170, 0.797],
+    blue: [0.131, 0.046],
+    white: D65,
+};
+
+pub const ADOBE_RGB: RgbGamut = RgbGamut {
+    red: [0.64, 0.33],
+    green: [0.21, 0.71],
+    blue: [0.15, 0.06],
+    white: D65,
+};
+
+/// XYZ of a chromaticity, with Y = 1.
+fn xy_to_xyz([x, y]: [f32; 2]) -> Vec3 {
+    Vec3::new(x / y, 1.0, (1.0 - x - y) / y)
+}
+
+impl RgbGamut {
+    /// Linear RGB to CIE XYZ, scaled so that white has Y = 1.
+    pub fn to_xyz(self) -> Mat3 {
+        let primaries = Mat3::from_cols(
+            xy_to_xyz(self.red),
+            xy_to_xyz(self.green),
+            xy_to_xyz(self.blue),
+        );
+        let scale = primaries.inverse() * xy_to_xyz(self.white);
+        primaries * Mat3::from_diagonal(scale)
+    }
+
+    /// Volume of the gamut in CIE XYZ.
+    pub fn xyz_volume(self) -> f32 {
+        self.to_xyz().determinant().abs()
+    }
+
+    /// Linear RGB of this gamut to linear sRGB, out of [0, 1] for the colors
+    /// outside of sRGB.
+    pub fn to_srgb(self) -> Mat3 {
+        SRGB.to_xyz().inverse() * self.to_xyz()
+    }
+
+    /// Edges of the gamut's RGB cube, as pairs of points for a line list, in
+    /// sRGB colors. The edges are sampled evenly in sRGB encoded values.
+    pub fn outline(self) -> Vec<Vec3> {
+        let to_srgb = self.to_srgb();
+        let corners =
+            (0..8).map(|i| Vec3::new((i & 1) as f32, ((i >> 1) & 1) as f32, (i >> 2) as f32));
+        let mut points = vec![];
+        for (i, start) in corners.enumerate() {
+            for axis in 0..3 {
+                if i & (1 << axis) != 0 {
+                    continue;
+                }
+                let color = |t: f32| {
+                    let mut c = start;
+                    c[axis] = t;
+                    layout::from_linear(to_srgb * layout::to_linear(c))
+                };
+                for k in 0..EDGE_SEGMENTS {
+                    points.push(color(k as f32 / EDGE_SEGMENTS as f32));
+                    points.push(color((k + 1) as f32 / EDGE_SEGMENTS as f32));
+                }
+            }
+        }
+        points
+    }
+}
+
+/// Gamut outlines that can be shown in the view.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReferenceGamut {
+    Srgb,
+    DisplayP3,
+    Rec2020,
+    AdobeRgb,
+}
+
+impl ReferenceGamut {
+    pub const ALL: [ReferenceGamut; 4] = [
+        ReferenceGamut::Srgb,
+        ReferenceGamut::DisplayP3,
+        ReferenceGamut::Rec2020,
+        ReferenceGamut::AdobeRgb,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "srgb" => Some(ReferenceGamut::Srgb),
+            "display-p3" => Some(ReferenceGamut::DisplayP3),
+            "rec2020" => Some(ReferenceGamut::Rec2020),
+            "adobe-rgb" => Some(ReferenceGamut::AdobeRgb),
+            _ => None,
+        }
+    }
+
+    pub fn gamut(self) -> RgbGamut {
+        match self {
+            ReferenceGamut::Srgb => SRGB,
+            ReferenceGamut::DisplayP3 => DISPLAY_P3,
+            ReferenceGamut::Rec2020 => REC_2020,
+            ReferenceGamut::AdobeRgb => ADOBE_RGB,
+        }
+    }
+
+    fn outline_color(self) -> Color {
+        match self {
+            ReferenceGamut::Srgb => Color::WHITE,
+            ReferenceGamut::DisplayP3 => Color::ORANGE,
+            ReferenceGamut::Rec2020 => Color::FUCHSIA,
+            ReferenceGamut::AdobeRgb => Color::CYAN,
+        }
+    }
+}
+
+/// Outline of a reference gamut, in the cube's layout.
+#[derive(Component)]
+pub struct GamutOutline(pub ReferenceGamut);
+
+#[derive(Clone, Debug)]
+pub struct SetReferenceGamutEvent {
+    pub gamut: ReferenceGamut,
+    pub visible: bool,
+}
+
+pub fn create_gamut_outlines(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    transform: Transform,
+) {
+    for gamut in ReferenceGamut::ALL {
+        // Laid out once the cube is there
+        let mesh = meshes.add(render::line_mesh(vec![Vec3::ZERO; 2], vec![0, 1]));
+        commands.spawn_bundle((
+            transform,
+            GlobalTransform::identity(),
+            mesh,
+            InstancedMesh(vec![InstanceData {
+                position: Vec3::ZERO,
+                scale: Vec3::ONE,
+                color: gamut.outline_color().as_rgba_f32(),
+            }]),
+            GamutOutline(gamut),
+            CubeSpace,
+            Visibility { is_visible: false },
+            ComputedVisibility::default(),
+        ));
+    }
+}
+
+pub fn set_reference_gamut(
+    mut events: EventReader<SetReferenceGamutEvent>,
+    mut query: Query<(&GamutOutline, &mut Visibility)>,
+) {
+    for evt in events.iter() {
+        for (outline, mut visibility) in query.iter_mut() {
+            if outline.0 == evt.gamut {
+                visibility.is_visible = evt.visible;
+            }
+        }
+    }
+}
+
+/// Lays the outlines out again when the cube's layout changes.
+pub fn update_gamut_outlines(
+    cube_query: Query<&ColorCube, Changed<ColorCube>>,
+    query: Query<(&GamutOutline, &Handle<Mesh>)>,
+    mut meshes: ResMut<Assets<Mesh>>,
+) {
+    if let Some(cube) = cube_query.iter().last() {
+        let layout = cube.layout;
+        for (outline, handle) in query.iter() {
+            let points = outline
+                .0
+                .gamut()
+                .outline()
+                .into_iter()
+                .map(|c| layout.position(layout.coords(c)))
+                .collect::<Vec<_>>();
+            let indices = (0..points.len() as u32).collect();
+            if let Some(mesh) = meshes.get_mut(handle) {
+                *mesh = render::line_mesh(points, indices);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout;
+
+    #[test]
+    fn srgb_matrix_matches_the_standard() {
+        let m = SRGB.to_xyz();
+        let expected = layout::srgb_to_xyz();
+        for (a, b) in m.to_cols_array().iter().zip(expected.to_cols_array()) {
+            assert!((a - b).abs() < 1e-3, "{m} != {expected}");
+        }
+        assert!(REC_2020.xyz_volume() > DISPLAY_P3.xyz_volume());
+        assert!(DISPLAY_P3.xyz_volume() > SRGB.xyz_volume());
+    }
+
+    #[test]
+    fn wider_gamuts_are_outlined_outside_of_srgb() {
+        let inside =
+            |c: &Vec3| c.cmpge(Vec3::splat(-1e-3)).all() && c.cmple(Vec3::splat(1.001)).all();
+        let srgb = SRGB.outline();
+        // Twelve edges
+        assert_eq!(srgb.len(), 12 * 2 * EDGE_SEGMENTS);
+        assert!(srgb.iter().all(inside));
+        // Pure P3 green is beyond sRGB green, but the white point is shared
+        let p3 = DISPLAY_P3.to_srgb();
+        assert!(!inside(&(p3 * Vec3::Y)));
+        assert!((p3 * Vec3::ONE - Vec3::ONE).abs().max_element() < 1e-3);
+    }
+}
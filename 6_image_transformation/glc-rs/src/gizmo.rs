@@ -0,0 +1,283 @@
+//! Reference marks around the color cube: its bounding box, the red, green
+//! and blue axes, markers at the corners of the sRGB cube and ticks along the
+//! axes.
+//!
+//! The axes are the edges of the sRGB cube from black to each primary, so
+//! they bend in the layouts that don't keep them straight. None of the marks
+//! are clipped with the bins.
+
+use bevy::prelude::*;
+
+use crate::color_cube::{ColorCube, CubeSpace};
+use crate::layout::Layout;
+use crate::render::{InstanceData, InstancedMesh};
+
+/// Segments each axis is drawn with.
+const AXIS_SEGMENTS: usize = 16;
+/// Ticks along each axis, at even steps of the encoded value.
+const TICKS: usize = 10;
+const TICK_LENGTH: f32 = 0.02;
+/// Size of the corner markers, in the cube's unit space.
+const CORNER_SCALE: f32 = 0.04;
+/// Size of the frame, relative to the cube. Larger than it so that it
+/// encloses the corner markers, and stays clear of the axes in the RGB layout.
+const FRAME_SCALE: f32 = 1.05;
+const FRAME_COLOR: Color = Color::rgb(0.6, 0.6, 0.6);
+
+const PRIMARIES: [Vec3; 3] = [Vec3::X, Vec3::Y, Vec3::Z];
+
+/// Marks that can be shown around the cube, one entity each.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GizmoPart {
+    Frame,
+    Axes,
+    Corners,
+    Ticks,
+}
+
+/*
+ * Settings for the reference marks around the color cube
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GizmoSettings {
+    pub frame: bool,
+    pub axes: bool,
+    pub corners: bool,
+    pub ticks: bool,
+}
+
+impl Default for GizmoSettings {
+    fn default() -> Self {
+        GizmoSettings {
+            frame: true,
+            axes: true,
+            corners: true,
+            ticks: false,
+        }
+    }
+}
+
+impl GizmoSettings {
+    pub fn shows(&self, part: GizmoPart) -> bool {
+        match part {
+            GizmoPart::Frame => self.frame,
+            GizmoPart::Axes => self.axes,
+            GizmoPart::Corners => self.corners,
+            GizmoPart::Ticks => self.ticks,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SetGizmoEvent {
+    pub settings: GizmoSettings,
+}
+
+fn color_position(rgb: Vec3, layout: Layout) -> Vec3 {
+    layout.position(layout.coords(rgb))
+}
+
+/// Black, the primaries, the secondaries and white: the corners of the sRGB
+/// cube, with red in bit 0, green in bit 1 and blue in bit 2.
+pub fn corner_colors() -> [Vec3; 8] {
+    [0, 1, 2, 3, 4, 5, 6, 7]
+        .map(|i| Vec3::new((i & 1) as f32, ((i >> 1) & 1) as f32, (i >> 2) as f32))
+}
+
+/// Points along the axis from black to `primary`, in the cube's unit space.
+pub fn axis_points(primary: Vec3, layout: Layout) -> Vec<Vec3> {
+    (0..=AXIS_SEGMENTS)
+        .map(|k| color_position(primary * k as f32 / AXIS_SEGMENTS as f32, layout))
+        .collect()
+}
+
+/// Ticks across the axis to `primary`, as start points and the vectors to
+/// their ends. The middle tick is twice as long.
+pub fn ticks(primary: Vec3, layout: Layout) -> Vec<(Vec3, Vec3)> {
+    let step = 1.0 / TICKS as f32;
+    (1..TICKS)
+        .map(|k| {
+            let t = k as f32 * step;
+            let center = color_position(primary * t, layout);
+            let tangent = (color_position(primary * (t + step / 2.0), layout)
+                - color_position(primary * (t - step / 2.0), layout))
+            .normalize_or_zero();
+            let reference = if tangent.y.abs() > 0.9 {
+                Vec3::X
+            } else {
+                Vec3::Y
+            };
+            let length = if 2 * k == TICKS {
+                2.0 * TICK_LENGTH
+            } else {
+                TICK_LENGTH
+            };
+            let across = tangent.cross(reference).normalize_or_zero() * length;
+            (center - across / 2.0, across)
+        })
+        .collect()
+}
+
+fn segments(points: &[Vec3], color: Vec3) -> impl Iterator<Item = InstanceData> + '_ {
+    points.windows(2).map(move |pair| InstanceData {
+        position: pair[0],
+        scale: pair[1] - pair[0],
+        color: Color::rgb(color.x, color.y, color.z).as_rgba_f32(),
+    })
+}
+
+/// Instances of one part, laid out for `layout`.
+fn part_instances(part: GizmoPart, layout: Layout) -> Vec<InstanceData> {
+    let color = |c: Vec3| Color::rgb(c.x, c.y, c.z).as_rgba_f32();
+    match part {
+        // The wire cube is centered at its origin
+        GizmoPart::Frame => vec![InstanceData {
+            position: Vec3::splat(0.5),
+            scale: Vec3::splat(FRAME_SCALE),
+            color: FRAME_COLOR.as_rgba_f32(),
+        }],
+        GizmoPart::Axes => PRIMARIES
+            .iter()
+            .flat_map(|&primary| {
+                segments(&axis_points(primary, layout), primary).collect::<Vec<_>>()
+            })
+            .collect(),
+        GizmoPart::Corners => corner_colors()
+            .into_iter()
+            .map(|c| InstanceData {
+                position: color_position(c, layout),
+                scale: Vec3::splat(CORNER_SCALE),
+                color: color(c),
+            })
+            .collect(),
+        GizmoPart::Ticks => PRIMARIES
+            .iter()
+            .flat_map(|&primary| {
+                ticks(primary, layout)
+                    .into_iter()
+                    .map(move |(position, scale)| InstanceData {
+                        position,
+                        scale,
+                        color: color(primary),
+                    })
+            })
+            .collect(),
+    }
+}
+
+pub fn create_gizmo(
+    commands: &mut Commands,
+    cube_mesh: Handle<Mesh>,
+    wire_mesh: Handle<Mesh>,
+    segment_mesh: Handle<Mesh>,
+    transform: Transform,
+) {
+    let settings = GizmoSettings::default();
+    let parts = [
+        (GizmoPart::Frame, wire_mesh),
+        (GizmoPart::Axes, segment_mesh.clone()),
+        (GizmoPart::Corners, cube_mesh),
+        (GizmoPart::Ticks, segment_mesh),
+    ];
+    for (part, mesh) in parts {
+        commands.spawn_bundle((
+            transform,
+            GlobalTransform::identity(),
+            mesh,
+            // Laid out once the cube is there
+            InstancedMesh(vec![]),
+            part,
+            CubeSpace,
+            Visibility {
+                is_visible: settings.shows(part),
+            },
+            ComputedVisibility::default(),
+        ));
+    }
+}
+
+pub fn set_gizmo(
+    mut events: EventReader<SetGizmoEvent>,
+    mut settings: ResMut<GizmoSettings>,
+    mut query: Query<(&GizmoPart, &mut Visibility)>,
+) {
+    let evts = events.iter().collect::<Vec<_>>();
+    if let Some(evt) = evts.into_iter().last() {
+        *settings = evt.settings;
+        for (part, mut visibility) in query.iter_mut() {
+            visibility.is_visible = settings.shows(*part);
+        }
+    }
+}
+
+/// Lays the marks out again when the cube's layout changes.
+pub fn update_gizmo(
+    mut last: Local<Option<Layout>>,
+    cube_query: Query<&ColorCube>,
+    mut query: Query<(&GizmoPart, &mut InstancedMesh)>,
+) {
+    if let Some(cube) = cube_query.iter().last() {
+        if *last == Some(cube.layout) {
+            return;
+        }
+        *last = Some(cube.layout);
+        for (part, mut mesh) in query.iter_mut() {
+            mesh.0 = part_instances(*part, cube.layout);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUTS: [Layout; 5] = [
+        Layout::Rgb,
+        Layout::Hsv,
+        Layout::Hsl,
+        Layout::Lab,
+        Layout::Oklab,
+    ];
+
+    #[test]
+    fn axes_run_from_black_to_the_primaries() {
+        for layout in LAYOUTS {
+            let black = color_position(Vec3::ZERO, layout);
+            for primary in PRIMARIES {
+                let points = axis_points(primary, layout);
+                assert_eq!(points.len(), AXIS_SEGMENTS + 1);
+                assert!(points[0].distance(black) < 1e-4, "{layout:?}");
+                let end = color_position(primary, layout);
+                assert!(points[AXIS_SEGMENTS].distance(end) < 1e-4, "{layout:?}");
+            }
+        }
+        // Along the edges of the unit cube in the RGB layout
+        assert_eq!(axis_points(Vec3::Y, Layout::Rgb)[AXIS_SEGMENTS], Vec3::Y);
+        let corners = corner_colors();
+        assert_eq!(corners[0], Vec3::ZERO);
+        assert_eq!(corners[7], Vec3::ONE);
+        assert_eq!(corners[5], Vec3::new(1.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn ticks_cross_their_axis() {
+        for layout in LAYOUTS {
+            for primary in PRIMARIES {
+                let ticks = ticks(primary, layout);
+                assert_eq!(ticks.len(), TICKS - 1);
+                for (k, (start, across)) in ticks.into_iter().enumerate() {
+                    let t = (k + 1) as f32 / TICKS as f32;
+                    let center = start + across / 2.0;
+                    assert!(center.distance(color_position(primary * t, layout)) < 1e-4);
+                    assert!(
+                        across.length() >= TICK_LENGTH * 0.99,
+                        "{layout:?} {primary}"
+                    );
+                }
+            }
+        }
+        // Across the red axis in the RGB layout
+        let (_, across) = ticks(Vec3::X, Layout::Rgb)[0];
+        assert!(across.x.abs() < 1e-6);
+    }
+}
@@ -0,0 +1,526 @@
+//! Convex hull of the colors of an image, and how much of a gamut they use.
+//!
+//! The colors are first binned on a coarse grid, and only the mean colors of
+//! the bins on the boundary of the occupied ones are kept, since the others
+//! can't be on the hull. The hull of the output is drawn translucent in the
+//! cube, taken of the colors' positions in its layout since the polar layouts
+//! bend the RGB hull's faces. Both images get their hull volumes in RGB and
+//! CIELAB, along with their coverage of the reference gamuts.
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use wasm_bindgen::prelude::*;
+
+use crate::color_cube::{self, ColorCube, CubeSpace};
+use crate::gamut::{self, RgbGamut};
+use crate::image::{Image, Input, Output};
+use crate::kernels::Pixel;
+use crate::layout;
+use crate::proxy::Proxy;
+use crate::render::{self, InstanceData, InstancedMesh, Translucent};
+
+/// Bins per axis of the grid the colors are reduced on.
+const HULL_RESOLUTION: usize = 32;
+const HULL_ALPHA: f32 = 0.2;
+const EDGE_ALPHA: f32 = 0.5;
+
+/// Convex polyhedron, with its faces wound counterclockwise seen from outside.
+#[derive(Clone, Debug)]
+pub struct ConvexHull {
+    pub points: Vec<Vec3>,
+    pub faces: Vec<[usize; 3]>,
+}
+
+impl ConvexHull {
+    /// Hull of the points. `None` if they are all on a plane.
+    pub fn new(points: &[Vec3]) -> Option<Self> {
+        let (min, max) = points.iter().fold(
+            (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+            |(min, max), &p| (min.min(p), max.max(p)),
+        );
+        let eps = 1e-5 * (max - min).length();
+        let farthest = |distance: &dyn Fn(Vec3) -> f32| {
+            (0..points.len())
+                .map(|i| (i, distance(points[i])))
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .filter(|(_, d)| *d > eps)
+                .map(|(i, _)| i)
+        };
+
+        // Initial tetrahedron, as large as easily found
+        let a = (0..points.len()).min_by(|&i, &j| points[i].x.total_cmp(&points[j].x))?;
+        let pa = points[a];
+        let b = farthest(&|p| p.distance(pa))?;
+        let ab = (points[b] - pa).normalize();
+        let c = farthest(&|p| (p - pa).cross(ab).length())?;
+        let normal = ab.cross(points[c] - pa).normalize();
+        let d = farthest(&|p| (p - pa).dot(normal).abs())?;
+
+        let tetrahedron = if (points[d] - pa).dot(normal) > 0.0 {
+            [[a, c, b], [a, b, d], [b, c, d], [c, a, d]]
+        } else {
+            [[a, b, c], [a, d, b], [b, d, c], [c, d, a]]
+        };
+        let mut faces = tetrahedron
+            .iter()
+            .map(|&v| HullFace::new(points, v))
+            .collect::<Vec<_>>();
+        // Each directed edge belongs to one face, and its reverse to the face
+        // across it
+        let mut edges = HashMap::new();
+        for (k, face) in faces.iter().enumerate() {
+            for edge in face.edges() {
+                edges.insert(edge, k);
+            }
+        }
+        HullFace::assign(&mut faces, points, 0..points.len(), eps);
+
+        // Quickhull: grow the hull to the farthest point outside of a face
+        while let Some(seed) = faces
+            .iter()
+            .position(|f| !f.removed && !f.outside.is_empty())
+        {
+            let face = &faces[seed];
+            let i = *face
+                .outside
+                .iter()
+                .max_by(|&&i, &&j| {
+                    face.distance(points[i])
+                        .total_cmp(&face.distance(points[j]))
+                })
+                .unwrap();
+            let p = points[i];
+
+            // The faces the point sees are searched across the edges of the
+            // seed face, so that they stay connected. As in `assign`, points
+            // within `eps` of a face are behind it.
+            faces[seed].removed = true;
+            let mut visible = vec![seed];
+            let mut horizon = vec![];
+            let mut next = 0;
+            while next < visible.len() {
+                for (u, v) in faces[visible[next]].edges() {
+                    let neighbour = edges[&(v, u)];
+                    if faces[neighbour].removed {
+                        continue;
+                    }
+                    if faces[neighbour].distance(p) > eps {
+                        faces[neighbour].removed = true;
+                        visible.push(neighbour);
+                    } else {
+                        horizon.push((u, v));
+                    }
+                }
+                next += 1;
+            }
+
+            let mut orphans = vec![];
+            for &k in visible.iter() {
+                for edge in faces[k].edges() {
+                    edges.remove(&edge);
+                }
+                orphans.append(&mut faces[k].outside);
+            }
+            let first_new = faces.len();
+            for (u, v) in horizon {
+                let face = HullFace::new(points, [u, v, i]);
+                for edge in face.edges() {
+                    edges.insert(edge, faces.len());
+                }
+                faces.push(face);
+            }
+            orphans.retain(|&j| j != i);
+            HullFace::assign(&mut faces[first_new..], points, orphans, eps);
+        }
+        Some(ConvexHull {
+            points: points.to_vec(),
+            faces: faces
+                .into_iter()
+                .filter(|f| !f.removed)
+                .map(|f| f.vertices)
+                .collect(),
+        })
+    }
+
+    pub fn volume(&self) -> f32 {
+        let origin = self.points[self.faces[0][0]];
+        self.faces
+            .iter()
+            .map(|f| {
+                let [a, b, c] = f.map(|i| self.points[i] - origin);
+                a.dot(b.cross(c))
+            })
+            .sum::<f32>()
+            / 6.0
+    }
+
+    /// Each edge once.
+    pub fn edges(&self) -> Vec<[usize; 2]> {
+        self.faces
+            .iter()
+            .flat_map(|f| [[f[0], f[1]], [f[1], f[2]], [f[2], f[0]]])
+            .filter(|[u, v]| u < v)
+            .collect()
+    }
+}
+
+/// Face of a hull being built, with the points still outside of it.
+struct HullFace {
+    vertices: [usize; 3],
+    normal: Vec3,
+    offset: f32,
+    outside: Vec<usize>,
+    /// Seen by a point the hull has grown to
+    removed: bool,
+}
+
+impl HullFace {
+    fn new(points: &[Vec3], vertices: [usize; 3]) -> Self {
+        let [a, b, c] = vertices.map(|i| points[i]);
+        let normal = (b - a).cross(c - a).normalize_or_zero();
+        HullFace {
+            vertices,
+            normal,
+            offset: normal.dot(a),
+            outside: vec![],
+            removed: false,
+        }
+    }
+
+    fn edges(&self) -> [(usize, usize); 3] {
+        let [u, v, w] = self.vertices;
+        [(u, v), (v, w), (w, u)]
+    }
+
+    /// Signed distance of `p` above the face.
+    fn distance(&self, p: Vec3) -> f32 {
+        self.normal.dot(p) - self.offset
+    }
+
+    /// Gives each point to the first face it is outside of, if any.
+    fn assign(
+        faces: &mut [HullFace],
+        points: &[Vec3],
+        indices: impl IntoIterator<Item = usize>,
+        eps: f32,
+    ) {
+        for i in indices {
+            if let Some(face) = faces.iter_mut().find(|f| f.distance(points[i]) > eps) {
+                face.outside.push(i);
+            }
+        }
+    }
+}
+
+/// Mean colors of the occupied bins of a grid over the RGB cube that have an
+/// empty neighbor, or are on its sides.
+pub fn boundary_colors(colors: &[Pixel]) -> Vec<Vec3> {
+    let n = HULL_RESOLUTION;
+    let index = |x: usize, y: usize, z: usize| (x * n + y) * n + z;
+    let mut sums = vec![Vec3::ZERO; n * n * n];
+    let mut counts = vec![0u32; n * n * n];
+    for c in colors {
+        let rgb = Vec3::new(c[0], c[1], c[2]).clamp(Vec3::ZERO, Vec3::ONE);
+        let [x, y, z] = (rgb * n as f32).to_array().map(|x| (x as usize).min(n - 1));
+        sums[index(x, y, z)] += rgb;
+        counts[index(x, y, z)] += 1;
+    }
+
+    let occupied = |x: usize, y: usize, z: usize| counts[index(x, y, z)] > 0;
+    let mut boundary = vec![];
+    for x in 0..n {
+        for y in 0..n {
+            for z in 0..n {
+                if !occupied(x, y, z) {
+                    continue;
+                }
+                let inside = x > 0
+                    && y > 0
+                    && z > 0
+                    && x < n - 1
+                    && y < n - 1
+                    && z < n - 1
+                    && occupied(x - 1, y, z)
+                    && occupied(x + 1, y, z)
+                    && occupied(x, y - 1, z)
+                    && occupied(x, y + 1, z)
+                    && occupied(x, y, z - 1)
+                    && occupied(x, y, z + 1);
+                if !inside {
+                    let i = index(x, y, z);
+                    boundary.push(sums[i] / counts[i] as f32);
+                }
+            }
+        }
+    }
+    boundary
+}
+
+/// Volumes of the hull of an image's colors, as returned to JavaScript by
+/// `Glc::gamut_metrics`.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct GamutMetrics {
+    /// Volume in RGB, where the whole cube is 1
+    pub rgb_volume: f32,
+    /// Volume in CIELAB, in cubic Lab units
+    pub lab_volume: f32,
+    /// Share of the sRGB gamut, 0-100
+    pub srgb_coverage: f32,
+    /// Share of the Display P3 gamut, 0-100
+    pub p3_coverage: f32,
+    /// Share of the Rec. 2020 gamut, 0-100
+    pub rec2020_coverage: f32,
+}
+
+impl GamutMetrics {
+    /// Coverages compare volumes in CIE XYZ, where the gamuts are
+    /// parallelepipeds. The colors are in sRGB, so their hull is within all
+    /// of them.
+    pub fn new(colors: &[Vec3]) -> Self {
+        let volume = |points: Vec<Vec3>| ConvexHull::new(&points).map_or(0.0, |h| h.volume());
+        let linear_volume = volume(colors.iter().map(|&c| layout::to_linear(c)).collect());
+        let xyz_volume = linear_volume * gamut::SRGB.xyz_volume();
+        let coverage = |gamut: RgbGamut| (100.0 * xyz_volume / gamut.xyz_volume()).min(100.0);
+        GamutMetrics {
+            rgb_volume: volume(colors.to_vec()),
+            lab_volume: volume(colors.iter().map(|&c| layout::rgb_to_lab(c)).collect()),
+            srgb_coverage: coverage(gamut::SRGB),
+            p3_coverage: coverage(gamut::DISPLAY_P3),
+            rec2020_coverage: coverage(gamut::REC_2020),
+        }
+    }
+}
+
+/*
+ * Settings for the gamut hull of the images
+ */
+#[derive(Default)]
+pub struct HullSettings {
+    pub enabled: bool,
+}
+
+/*
+ * Gamut metrics of the images, once the hull is enabled
+ */
+#[derive(Default)]
+pub struct HullMetrics {
+    pub input: Option<GamutMetrics>,
+    pub output: Option<GamutMetrics>,
+}
+
+/// Faces of the hull of the output colors.
+#[derive(Component)]
+pub struct GamutHull;
+
+#[derive(Component)]
+pub struct GamutHullEdges;
+
+#[derive(Clone, Debug)]
+pub struct SetGamutHullEvent {
+    pub enabled: bool,
+}
+
+pub fn create_gamut_hull(commands: &mut Commands, meshes: &mut Assets<Mesh>, transform: Transform) {
+    // Replaced by the hull once there is one
+    let faces = meshes.add(render::triangle_mesh(vec![Vec3::ZERO; 3], vec![0, 1, 2]));
+    let edges = meshes.add(render::line_mesh(vec![Vec3::ZERO; 2], vec![0, 1]));
+    commands.spawn_bundle((
+        transform,
+        GlobalTransform::identity(),
+        faces,
+        InstancedMesh(vec![]),
+        GamutHull,
+        Translucent,
+        CubeSpace,
+        Visibility { is_visible: false },
+        ComputedVisibility::default(),
+    ));
+    commands.spawn_bundle((
+        transform,
+        GlobalTransform::identity(),
+        edges,
+        InstancedMesh(vec![]),
+        GamutHullEdges,
+        CubeSpace,
+        Visibility { is_visible: false },
+        ComputedVisibility::default(),
+    ));
+}
+
+#[allow(clippy::type_complexity)]
+pub fn set_gamut_hull(
+    mut events: EventReader<SetGamutHullEvent>,
+    mut settings: ResMut<HullSettings>,
+    mut metrics: ResMut<HullMetrics>,
+    mut query: Query<&mut Visibility, Or<(With<GamutHull>, With<GamutHullEdges>)>>,
+) {
+    let evts = events.iter().collect::<Vec<_>>();
+    if let Some(evt) = evts.into_iter().last() {
+        settings.enabled = evt.enabled;
+        if !evt.enabled {
+            *metrics = HullMetrics::default();
+        }
+        for mut visibility in query.iter_mut() {
+            visibility.is_visible = evt.enabled;
+        }
+    }
+}
+
+/// Computes the hulls again once the output is complete, or the cube's
+/// layout changes, and draws the output's in the cube.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn update_gamut_hull(
+    mut events: EventReader<color_cube::UpdateColorCubeEvent>,
+    settings: Res<HullSettings>,
+    mut metrics: ResMut<HullMetrics>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    input_query: Query<(&Image, &Proxy), (With<Input>, Without<Output>)>,
+    output_query: Query<&Image, With<Output>>,
+    cube_query: Query<&ColorCube>,
+    mut faces_query: Query<(&Handle<Mesh>, &mut InstancedMesh), With<GamutHull>>,
+    mut edges_query: Query<
+        (&Handle<Mesh>, &mut InstancedMesh),
+        (With<GamutHullEdges>, Without<GamutHull>),
+    >,
+) {
+    let updated = events.iter().count() > 0;
+    if !settings.enabled || !(updated || settings.is_changed()) {
+        return;
+    }
+    let (output, cube) = match (output_query.iter().last(), cube_query.iter().last()) {
+        (Some(output), Some(cube)) => (output, cube),
+        _ => return,
+    };
+
+    let colors = boundary_colors(&output.data);
+    metrics.output = Some(GamutMetrics::new(&colors));
+    metrics.input = input_query.iter().last().map(|(input, proxy)| {
+        // Compared at the resolution of the output
+        let input = proxy.select(input, input.data.len() != output.data.len());
+        GamutMetrics::new(&boundary_colors(&input.data))
+    });
+
+    let positions = colors
+        .iter()
+        .map(|&c| cube.layout.position(cube.layout.coords(c)))
+        .collect::<Vec<_>>();
+    let hull = ConvexHull::new(&positions);
+    let instances = |alpha: f32| match hull {
+        Some(_) => vec![InstanceData {
+            position: Vec3::ZERO,
+            scale: Vec3::ONE,
+            color: Color::rgba(1.0, 1.0, 1.0, alpha).as_rgba_f32(),
+        }],
+        None => vec![],
+    };
+    if let Some((handle, mut mesh)) = faces_query.iter_mut().last() {
+        if let (Some(hull), Some(faces)) = (&hull, meshes.get_mut(handle)) {
+            let indices = hull.faces.iter().flatten().map(|&i| i as u32).collect();
+            *faces = render::triangle_mesh(hull.points.clone(), indices);
+        }
+        mesh.0 = instances(HULL_ALPHA);
+    }
+    if let Some((handle, mut mesh)) = edges_query.iter_mut().last() {
+        if let (Some(hull), Some(edges)) = (&hull, meshes.get_mut(handle)) {
+            let indices = hull.edges().iter().flatten().map(|&i| i as u32).collect();
+            *edges = render::line_mesh(hull.points.clone(), indices);
+        }
+        mesh.0 = instances(EDGE_ALPHA);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn cube_corners() -> Vec<Vec3> {
+        (0..8)
+            .map(|i| Vec3::new((i & 1) as f32, ((i >> 1) & 1) as f32, (i >> 2) as f32))
+            .collect()
+    }
+
+    #[test]
+    fn hull_of_a_cube_encloses_its_volume() {
+        let mut points = cube_corners();
+        points.extend([Vec3::splat(0.5), Vec3::new(0.2, 0.7, 0.4)]);
+        let hull = ConvexHull::new(&points).unwrap();
+        assert!((hull.volume() - 1.0).abs() < 1e-5, "{}", hull.volume());
+        // Six sides of two triangles, and each of their 18 edges once
+        assert_eq!(hull.faces.len(), 12);
+        assert_eq!(hull.edges().len(), 18);
+        assert!(hull.faces.iter().flatten().all(|&i| i < 8));
+    }
+
+    #[test]
+    fn hull_of_points_on_a_sphere_is_closed() {
+        // Random directions from a linear congruential generator, off the origin
+        let (n, r, center) = (2000, 0.5, Vec3::splat(0.5));
+        let mut state = 12345u64;
+        let mut random = || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 40) as f32 / (1 << 24) as f32 * 2.0 - 1.0
+        };
+        let mut points = vec![];
+        while points.len() < n {
+            let v = Vec3::new(random(), random(), random());
+            if (0.1..=1.0).contains(&v.length()) {
+                points.push(center + r * v.normalize());
+            }
+        }
+        let hull = ConvexHull::new(&points).unwrap();
+        let expected = 4.0 / 3.0 * std::f32::consts::PI * r * r * r;
+        assert!(
+            (hull.volume() - expected).abs() < 0.02 * expected,
+            "{} {expected}",
+            hull.volume()
+        );
+
+        // Every edge is shared by two faces wound against each other, and
+        // the surface is a sphere
+        let edges = hull
+            .faces
+            .iter()
+            .flat_map(|f| [(f[0], f[1]), (f[1], f[2]), (f[2], f[0])])
+            .collect::<HashSet<_>>();
+        assert_eq!(edges.len(), 3 * hull.faces.len());
+        assert!(edges.iter().all(|(u, v)| edges.contains(&(*v, *u))));
+        let vertices = hull.faces.iter().flatten().collect::<HashSet<_>>();
+        assert_eq!(vertices.len() + hull.faces.len(), edges.len() / 2 + 2);
+    }
+
+    #[test]
+    fn flat_colors_have_no_hull() {
+        let grays = [Vec3::ZERO, Vec3::splat(0.5), Vec3::ONE];
+        assert!(ConvexHull::new(&grays).is_none());
+        let metrics = GamutMetrics::new(&grays);
+        assert_eq!(metrics.rgb_volume, 0.0);
+        assert_eq!(metrics.srgb_coverage, 0.0);
+    }
+
+    #[test]
+    fn full_rgb_cube_covers_srgb() {
+        // One color per bin of the grid
+        let n = HULL_RESOLUTION;
+        let colors = (0..n * n * n)
+            .map(|i| {
+                let c = Vec3::new((i / n / n) as f32, (i / n % n) as f32, (i % n) as f32)
+                    / (n - 1) as f32;
+                [c.x, c.y, c.z, 1.0]
+            })
+            .collect::<Vec<_>>();
+        let boundary = boundary_colors(&colors);
+        // The center of the grid is left out
+        assert!(boundary.len() < colors.len());
+        let metrics = GamutMetrics::new(&boundary);
+        assert!((metrics.rgb_volume - 1.0).abs() < 1e-3);
+        assert!((metrics.srgb_coverage - 100.0).abs() < 0.1);
+        assert!(metrics.p3_coverage < metrics.srgb_coverage);
+        assert!(metrics.rec2020_coverage < metrics.p3_coverage);
+    }
+}
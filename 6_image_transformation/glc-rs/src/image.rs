@@ -1,40 +1,70 @@
+use std::ops::Range;
+
 use bevy::prelude::*;
+use bevy::utils::Instant;
 
 use crate::color_cube;
+use crate::kernels;
+use crate::keyer::{self, Keyed, Keyer};
+use crate::parallel;
+use crate::picking::{self, BinIndices, HoveredBin};
+use crate::processing;
+use crate::proxy;
+use crate::qualifier::{self, Qualifier};
+use crate::selection::{self, Selection, SelectionSource};
+use crate::utils;
 use wasm_bindgen::{Clamped, JsCast};
 use web_sys::ImageData;
 
-#[derive(Component)]
+#[derive(Component, Default)]
 pub struct Image {
     pub width: u32,
     pub height: u32,
-    pub data: Vec<Color>,
+    /// Non-linear sRGB pixels, stored as RGBA
+    pub data: Vec<kernels::Pixel>,
 }
 
-impl Default for Image {
-    fn default() -> Self {
-        Image {
-            width: 0,
-            height: 0,
-            data: vec![],
-        }
+/// Rectangle of pixels, in image coordinates.
+#[derive(Clone, Copy, Debug)]
+pub struct PixelRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl PixelRect {
+    pub fn columns(&self) -> Range<usize> {
+        self.x as usize..(self.x + self.width) as usize
+    }
+
+    pub fn rows(&self) -> Range<usize> {
+        self.y as usize..(self.y + self.height) as usize
+    }
+
+    pub fn fits(&self, width: u32, height: u32) -> bool {
+        self.x as u64 + self.width as u64 <= width as u64
+            && self.y as u64 + self.height as u64 <= height as u64
+    }
+
+    /// Ranges of pixel indices covered by the rectangle, one per row, in an
+    /// image that is `image_width` pixels wide.
+    pub fn spans(&self, image_width: u32) -> impl Iterator<Item = Range<usize>> {
+        let width = image_width as usize;
+        let columns = self.columns();
+        self.rows()
+            .map(move |y| y * width + columns.start..y * width + columns.end)
     }
 }
 
 #[derive(Component)]
 pub struct Input;
 
-#[derive(Component)]
+#[derive(Component, Default)]
 pub struct Output {
     pub canvas_id: Option<String>,
 }
 
-impl Default for Output {
-    fn default() -> Self {
-        Output { canvas_id: None }
-    }
-}
-
 #[derive(Component)]
 pub struct ColorTransformation {
     pub rotation: Quat,
@@ -52,11 +82,36 @@ impl Default for ColorTransformation {
 pub struct SetInputImageEvent {
     pub width: u32,
     pub height: u32,
-    pub data: Vec<Color>,
+    pub data: Vec<kernels::Pixel>,
+}
+
+/// Replaces a rectangle of the input image, keeping the rest.
+#[derive(Clone, Debug)]
+pub struct SetInputRegionEvent {
+    pub rect: PixelRect,
+    pub data: Vec<kernels::Pixel>,
+}
+
+/// Sent whenever input pixels change. `region` is `None` for a whole new image.
+#[derive(Clone, Debug)]
+pub struct InputChangedEvent {
+    pub region: Option<PixelRect>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TransformImageEvent {
+    /// Interactive requests run on the proxy, when there is one.
+    pub interactive: bool,
 }
 
 #[derive(Clone, Debug)]
-pub struct TransformImageEvent;
+pub struct TransformRegionEvent {
+    pub rect: PixelRect,
+}
+
+/// Sent when a full pass starts rewriting the output image.
+#[derive(Clone, Debug)]
+pub struct TransformStartedEvent;
 
 #[derive(Clone, Debug)]
 pub struct SetColorTransformationEvent {
@@ -73,6 +128,7 @@ pub struct RenderRequest;
 
 pub fn set_input_image(
     mut events: EventReader<SetInputImageEvent>,
+    mut out_changed_events: EventWriter<InputChangedEvent>,
     mut out_image_events: EventWriter<TransformImageEvent>,
     mut query: Query<&mut Image, With<Input>>,
 ) {
@@ -82,7 +138,34 @@ pub fn set_input_image(
             image.width = evt.width;
             image.height = evt.height;
             image.data = evt.data.clone();
-            out_image_events.send(TransformImageEvent);
+            out_changed_events.send(InputChangedEvent { region: None });
+            out_image_events.send(TransformImageEvent { interactive: false });
+        }
+    }
+}
+
+pub fn set_input_region(
+    mut events: EventReader<SetInputRegionEvent>,
+    mut out_changed_events: EventWriter<InputChangedEvent>,
+    mut out_region_events: EventWriter<TransformRegionEvent>,
+    mut query: Query<&mut Image, With<Input>>,
+) {
+    if let Some(mut image) = query.iter_mut().last() {
+        // Unlike whole images, every region in the queue has to be applied
+        for evt in events.iter() {
+            let rect = evt.rect;
+            if !rect.fits(image.width, image.height)
+                || evt.data.len() != rect.width as usize * rect.height as usize
+            {
+                utils::log(&format!("Ignoring input region {rect:?} that does not fit"));
+                continue;
+            }
+            let width = image.width;
+            for (span, row) in rect.spans(width).zip(evt.data.chunks(rect.width as usize)) {
+                image.data[span].copy_from_slice(row);
+            }
+            out_changed_events.send(InputChangedEvent { region: Some(rect) });
+            out_region_events.send(TransformRegionEvent { rect });
         }
     }
 }
@@ -110,48 +193,124 @@ pub fn set_color_transformation(
     if let Some(evt) = evts.into_iter().last() {
         if let Some(mut xform) = query.iter_mut().last() {
             xform.rotation = evt.rotation;
-            out_events.send(TransformImageEvent);
+            out_events.send(TransformImageEvent { interactive: true });
         }
     }
 }
 
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 pub fn transform_image(
     mut events: EventReader<TransformImageEvent>,
-    input_query: Query<&Image, (With<Input>, Without<Output>)>,
-    mut output_query: Query<(&mut Image, &ColorTransformation), With<Output>>,
+    mut region_events: EventReader<TransformRegionEvent>,
+    mut job: Local<processing::Job>,
+    mut use_proxy: Local<bool>,
+    settings: Res<processing::ProcessingSettings>,
+    budget: Res<processing::FrameBudget>,
+    proxy_settings: Res<proxy::ProxySettings>,
+    mut proxy_state: ResMut<proxy::ProxyState>,
+    input_query: Query<(&Image, &proxy::Proxy), (With<Input>, Without<Output>)>,
+    mut output_query: Query<(&mut Image, &ColorTransformation, &Qualifier, &Keyer), With<Output>>,
+    mut out_started_events: EventWriter<TransformStartedEvent>,
+    mut out_key_events: EventWriter<keyer::KeyImageEvent>,
     mut out_cube_events: EventWriter<color_cube::UpdateColorCubeEvent>,
+    mut out_cube_region_events: EventWriter<color_cube::UpdateColorCubeRegionEvent>,
     mut out_render_events: EventWriter<RenderRequest>,
+    mut out_progress_events: EventWriter<processing::ProcessingProgressEvent>,
 ) {
     let evts = events.iter().collect::<Vec<_>>();
-    if let Some(_evt) = evts.into_iter().last() {
-        if let Some(input) = input_query.iter().last() {
-            if let Some((mut output, xform)) = output_query.iter_mut().last() {
+    let regions = region_events.iter().collect::<Vec<_>>();
+    if let Some((input, proxy)) = input_query.iter().last() {
+        if let Some((mut output, xform, qualifier, keyer)) = output_query.iter_mut().last() {
+            let restart = match evts.into_iter().last() {
+                Some(evt) => Some(evt.interactive),
+                // Regions can't be patched into a pass in flight or into a proxy preview,
+                // nor into a key, whose matte is filtered across pixels
+                None if !regions.is_empty()
+                    && (job.is_active() || *use_proxy || keyer.is_enabled()) =>
+                {
+                    Some(false)
+                }
+                None => None,
+            };
+
+            // A new request restarts the job, dropping any work in progress
+            if let Some(interactive) = restart {
+                *use_proxy = interactive && !proxy_settings.force_full_quality && proxy.0.is_some();
+                // A full resolution pass is the refinement a proxy preview waits for
+                proxy_state.pending_refine = *use_proxy;
+                if *use_proxy {
+                    proxy_state.last_interaction = Instant::now();
+                }
+
+                let input = proxy.select(input, *use_proxy);
                 output.width = input.width;
                 output.height = input.height;
-                output.data = input
-                    .data
-                    .iter()
-                    .map(|c| {
-                        let p = Vec3::new(c.r(), c.g(), c.b());
-                        let p = xform.rotation.mul_vec3(p - Vec3::new(0.5, 0.5, 0.5))
-                            + Vec3::new(0.5, 0.5, 0.5);
-                        Color::rgba(p.x, p.y, p.z, c.a())
-                    })
-                    .collect();
-                out_cube_events.send(color_cube::UpdateColorCubeEvent);
+                output.data.resize(input.data.len(), [0.0; 4]);
+                job.start(input.data.len());
+                out_started_events.send(TransformStartedEvent);
+            } else if !regions.is_empty() {
+                let rotation = Mat3::from_quat(xform.rotation);
+                let mut removed = vec![];
+                let mut added = vec![];
+                for evt in regions {
+                    for span in evt.rect.spans(output.width) {
+                        removed.extend_from_slice(&output.data[span.clone()]);
+                        qualifier::transform(
+                            qualifier.region.as_ref(),
+                            &rotation,
+                            &input.data[span.clone()],
+                            &mut output.data[span.clone()],
+                        );
+                        added.extend_from_slice(&output.data[span]);
+                    }
+                }
+                out_cube_region_events
+                    .send(color_cube::UpdateColorCubeRegionEvent { removed, added });
                 out_render_events.send(RenderRequest);
             }
+            if !job.is_active() {
+                return;
+            }
+
+            let input = proxy.select(input, *use_proxy);
+            let rotation = Mat3::from_quat(xform.rotation);
+            let finished = job.run(&settings, &budget, |range| {
+                qualifier::transform(
+                    qualifier.region.as_ref(),
+                    &rotation,
+                    &input.data[range.clone()],
+                    &mut output.data[range],
+                );
+            });
+            out_progress_events.send(job.progress(processing::ProcessingStage::Transform));
+            if finished {
+                out_cube_events.send(color_cube::UpdateColorCubeEvent);
+                // Keying filters the matte across pixels, so it waits for the whole image
+                if keyer.is_enabled() {
+                    out_key_events.send(keyer::KeyImageEvent);
+                } else {
+                    out_render_events.send(RenderRequest);
+                }
+            }
         }
     }
 }
 
+/// Draws the output image, or the qualifier's matte, to its canvas, dimming
+/// the pixels outside the color selection if there is one, and outside the
+/// hovered bin.
+#[allow(clippy::type_complexity)]
 pub fn render_image(
     mut events: EventReader<RenderRequest>,
-    query: Query<(&Image, &Output)>,
+    query: Query<(&Image, &Output, &Selection, &Qualifier, &Keyer, &Keyed)>,
+    input_query: Query<(&Image, &proxy::Proxy), (With<Input>, Without<Output>)>,
+    cube_query: Query<&color_cube::ColorCube>,
+    hovered: Res<HoveredBin>,
+    mut bin_indices: ResMut<BinIndices>,
 ) {
     let evts = events.iter().collect::<Vec<_>>();
     if let Some(_evt) = evts.into_iter().last() {
-        if let Some((image, output)) = query.iter().last() {
+        if let Some((image, output, selection, qualifier, keyer, keyed)) = query.iter().last() {
             if output.canvas_id.is_none() || image.width == 0 || image.height == 0 {
                 return;
             }
@@ -180,18 +339,39 @@ pub fn render_image(
             canvas.set_width(src_width);
             canvas.set_height(src_height);
 
-            let data = image
-                .data
-                .iter()
-                .flat_map(|c| c.as_rgba_f32().into_iter())
-                .map(|x| (x * 255.0).floor() as u8)
-                .collect::<Vec<_>>();
+            let input = input_query.iter().last().and_then(|(input, proxy)| {
+                // The output may have been computed from the proxy
+                let input = proxy.select(input, input.data.len() != image.data.len());
+                (input.data.len() == image.data.len()).then_some(&input.data)
+            });
+
+            let mut data = vec![0; image.data.len() * 4];
+            match (&qualifier.region, input) {
+                (Some(region), Some(input)) if qualifier.show_matte => {
+                    parallel::to_rgba8(&qualifier::matte_pixels(region, input), &mut data)
+                }
+                _ => parallel::to_rgba8(&keyer::displayed(keyer, keyed, &image.data), &mut data),
+            }
+            if let (Some(volume), Some(cube)) = (&selection.volume, cube_query.iter().last()) {
+                let colors = match selection.source {
+                    SelectionSource::Input => input,
+                    SelectionSource::Output => Some(&image.data),
+                };
+                if let Some(colors) = colors {
+                    selection::dim_unselected(&mut data, colors, volume, cube.layout);
+                }
+            }
+            // The cube bins the output, so its pixels are the ones of the bin
+            if let (Some(bin), Some(cube)) = (hovered.0, cube_query.iter().last()) {
+                picking::dim_other_bins(&mut data, bin_indices.get(&image.data, cube), bin);
+            }
             let clamped_data = Clamped(&data[..]);
 
-            let image_data =
-                ImageData::new_with_u8_clamped_array(clamped_data, src_width).unwrap();
+            let image_data = ImageData::new_with_u8_clamped_array(clamped_data, src_width).unwrap();
 
             context.put_image_data(&image_data, 0.0, 0.0).unwrap();
+            // Replace rather than blend, so that keyed alpha isn't applied twice
+            context.set_global_composite_operation("copy").unwrap();
             context
                 .draw_image_with_html_canvas_element_and_sw_and_sh_and_dx_and_dy_and_dw_and_dh(
                     &canvas,
@@ -205,6 +385,9 @@ pub fn render_image(
                     dst_height as f64,
                 )
                 .unwrap();
+            context
+                .set_global_composite_operation("source-over")
+                .unwrap();
 
             let image_data = context
                 .get_image_data(0.0, 0.0, dst_width as f64, dst_height as f64)
@@ -216,3 +399,36 @@ pub fn render_image(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> PixelRect {
+        PixelRect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn regions_have_to_fit_the_image() {
+        assert!(rect(0, 0, 8, 6).fits(8, 6));
+        assert!(rect(5, 4, 3, 2).fits(8, 6));
+        assert!(rect(8, 6, 0, 0).fits(8, 6));
+        assert!(!rect(6, 0, 3, 1).fits(8, 6));
+        assert!(!rect(0, 5, 1, 2).fits(8, 6));
+        // Sides that would wrap around in u32 don't sneak back in
+        assert!(!rect(u32::MAX, 0, 2, 1).fits(8, 6));
+        assert!(!rect(0, 1, 1, u32::MAX).fits(8, 6));
+    }
+
+    #[test]
+    fn spans_cover_the_rows_of_a_region() {
+        let spans = rect(2, 1, 3, 2).spans(8).collect::<Vec<_>>();
+        assert_eq!(spans, vec![10..13, 18..21]);
+        assert_eq!(rect(0, 0, 0, 2).spans(8).flatten().count(), 0);
+    }
+}
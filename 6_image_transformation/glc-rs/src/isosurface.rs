@@ -0,0 +1,319 @@
+//! Isosurfaces of the histogram density, drawn instead of the bins.
+//!
+//! The density is sampled at the bin centers, padded with empty bins so that
+//! the surfaces close at the sides of the cube, and contoured by marching
+//! cubes. Each cell is split into six tetrahedra around its diagonal, which
+//! avoids the ambiguous cases of the cube table and keeps the surfaces
+//! watertight. Nested levels are drawn as translucent shells.
+
+use bevy::prelude::*;
+
+use crate::binning;
+use crate::color_cube::{ColorCube, CubeSpace, Histogram, HistogramUpdatedEvent};
+use crate::layout::Layout;
+use crate::render::{self, InstanceData, InstancedMesh, Translucent};
+
+/// Number of nested levels that can be drawn.
+pub const MAX_LEVELS: usize = 4;
+const MIN_ALPHA: f32 = 0.15;
+const MAX_ALPHA: f32 = 0.6;
+
+/// Cells split into tetrahedra sharing the diagonal from corner 0 to 7. The
+/// corners are numbered by their offsets, x in bit 0, y in bit 1 and z in bit 2.
+const TETRAHEDRA: [[usize; 4]; 6] = [
+    [0, 1, 3, 7],
+    [0, 3, 2, 7],
+    [0, 2, 6, 7],
+    [0, 6, 4, 7],
+    [0, 4, 5, 7],
+    [0, 5, 1, 7],
+];
+
+/// Scalar field sampled on a regular grid, x major.
+pub struct DensityGrid {
+    pub size: UVec3,
+    pub values: Vec<f32>,
+}
+
+impl DensityGrid {
+    /// Histogram density relative to the densest bin.
+    pub fn from_density(density: &[f32], resolution: UVec3) -> Self {
+        let mut values = density.to_vec();
+        let max = values.iter().copied().fold(0.0, f32::max);
+        if max > 0.0 {
+            for v in values.iter_mut() {
+                *v /= max;
+            }
+        }
+        DensityGrid {
+            size: resolution,
+            values,
+        }
+    }
+
+    fn get(&self, p: IVec3) -> f32 {
+        let size = self.size.as_ivec3();
+        if p.cmplt(IVec3::ZERO).any() || p.cmpge(size).any() {
+            return 0.0;
+        }
+        self.values[binning::coords_to_index(p.as_uvec3(), self.size)]
+    }
+}
+
+/// Triangles of the surface where the density crosses `level`, in the
+/// layout's coordinates normalized to [0, 1], wound counterclockwise seen
+/// from the lower densities.
+pub fn extract(grid: &DensityGrid, level: f32) -> Vec<[Vec3; 3]> {
+    let size = grid.size.as_ivec3();
+    let bin_width = binning::bin_width(grid.size);
+    // Sample i is the center of bin i, with a ring of empty samples around
+    let position = |p: IVec3| (p.as_vec3() + Vec3::splat(0.5)) * bin_width;
+    let mut triangles = vec![];
+    for x in -1..size.x {
+        for y in -1..size.y {
+            for z in -1..size.z {
+                let origin = IVec3::new(x, y, z);
+                let corners = [0, 1, 2, 3, 4, 5, 6, 7]
+                    .map(|i| origin + IVec3::new(i & 1, (i >> 1) & 1, (i >> 2) & 1));
+                let values = corners.map(|p| grid.get(p));
+                if values.iter().all(|&v| v <= level) || values.iter().all(|&v| v > level) {
+                    continue;
+                }
+                let points = corners.map(position);
+                for tetrahedron in TETRAHEDRA {
+                    let p = tetrahedron.map(|i| points[i]);
+                    let v = tetrahedron.map(|i| values[i]);
+                    march_tetrahedron(p, v, level, &mut triangles);
+                }
+            }
+        }
+    }
+    triangles
+}
+
+fn march_tetrahedron(p: [Vec3; 4], v: [f32; 4], level: f32, triangles: &mut Vec<[Vec3; 3]>) {
+    let (inside, outside): (Vec<usize>, Vec<usize>) = (0..4).partition(|&i| v[i] > level);
+    let crossing = |a: usize, b: usize| {
+        let t = (level - v[a]) / (v[b] - v[a]);
+        p[a].lerp(p[b], t)
+    };
+    let mut emit = |mut triangle: [Vec3; 3]| {
+        // Face the lower densities
+        let centroid =
+            |indices: &[usize]| indices.iter().map(|&i| &p[i]).sum::<Vec3>() / indices.len() as f32;
+        let towards_inside = centroid(&inside) - centroid(&outside);
+        let normal = (triangle[1] - triangle[0]).cross(triangle[2] - triangle[0]);
+        if normal.dot(towards_inside) > 0.0 {
+            triangle.swap(1, 2);
+        }
+        triangles.push(triangle);
+    };
+    match (inside.as_slice(), outside.as_slice()) {
+        (&[a], &[b, c, d]) | (&[b, c, d], &[a]) => {
+            emit([crossing(a, b), crossing(a, c), crossing(a, d)]);
+        }
+        (&[a, b], &[c, d]) => {
+            let quad = [
+                crossing(a, c),
+                crossing(a, d),
+                crossing(b, d),
+                crossing(b, c),
+            ];
+            emit([quad[0], quad[1], quad[2]]);
+            emit([quad[0], quad[2], quad[3]]);
+        }
+        _ => {}
+    }
+}
+
+/*
+ * Settings for the isosurfaces of the histogram
+ */
+pub struct IsosurfaceSettings {
+    /// Densities relative to the fullest bin, from the outermost shell in
+    pub levels: Vec<f32>,
+}
+
+impl Default for IsosurfaceSettings {
+    fn default() -> Self {
+        IsosurfaceSettings {
+            levels: vec![0.02, 0.1, 0.4],
+        }
+    }
+}
+
+/// Shell of one level, the `n`th from the outside.
+#[derive(Component)]
+pub struct Isosurface(pub usize);
+
+#[derive(Clone, Debug)]
+pub struct SetIsosurfaceLevelsEvent {
+    pub levels: Vec<f32>,
+}
+
+pub fn create_isosurfaces(
+    commands: &mut Commands,
+    meshes: &mut Assets<Mesh>,
+    transform: Transform,
+) {
+    for n in 0..MAX_LEVELS {
+        // Replaced once the histogram is complete
+        let mesh = meshes.add(render::triangle_mesh(vec![Vec3::ZERO; 3], vec![0, 1, 2]));
+        commands.spawn_bundle((
+            transform,
+            GlobalTransform::identity(),
+            mesh,
+            InstancedMesh(vec![]),
+            Isosurface(n),
+            Translucent,
+            CubeSpace,
+            Visibility { is_visible: false },
+            ComputedVisibility::default(),
+        ));
+    }
+}
+
+pub fn set_isosurface_levels(
+    mut events: EventReader<SetIsosurfaceLevelsEvent>,
+    mut settings: ResMut<IsosurfaceSettings>,
+) {
+    let evts = events.iter().collect::<Vec<_>>();
+    if let Some(evt) = evts.into_iter().last() {
+        let mut levels = evt.levels.clone();
+        levels.retain(|level| *level > 0.0 && *level < 1.0);
+        levels.sort_by(f32::total_cmp);
+        levels.truncate(MAX_LEVELS);
+        settings.levels = levels;
+    }
+}
+
+/// Mesh of the shell in the cube's unit space. The polar layouts can turn
+/// triangles inside out, so both of their sides are drawn.
+fn shell_mesh(triangles: &[[Vec3; 3]], layout: Layout) -> Mesh {
+    let points = triangles
+        .iter()
+        .flatten()
+        .map(|&coords| layout.position(coords))
+        .collect::<Vec<_>>();
+    let indices = (0..points.len() as u32 / 3)
+        .flat_map(|i| {
+            let [a, b, c] = [3 * i, 3 * i + 1, 3 * i + 2];
+            [a, b, c, a, c, b]
+        })
+        .collect();
+    render::triangle_mesh(points, indices)
+}
+
+/// Extracts the shells again once the histogram is complete, or the levels
+/// change. Only done while they are shown.
+pub fn update_isosurfaces(
+    mut events: EventReader<HistogramUpdatedEvent>,
+    mut was_shown: Local<bool>,
+    settings: Res<IsosurfaceSettings>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    cube_query: Query<(&ColorCube, &Histogram)>,
+    mut query: Query<(&Isosurface, &Handle<Mesh>, &mut InstancedMesh, &Visibility)>,
+) {
+    let updated = events.iter().count() > 0;
+    let shown = query
+        .iter()
+        .any(|(_, _, _, visibility)| visibility.is_visible);
+    let appeared = shown && !*was_shown;
+    *was_shown = shown;
+    if !shown || !(updated || appeared || settings.is_changed()) {
+        return;
+    }
+    let (cube, histogram) = match cube_query.iter().last() {
+        Some(cube) => cube,
+        None => return,
+    };
+
+    let grid = DensityGrid::from_density(&histogram.density, cube.resolution);
+    let count = settings.levels.len();
+    for (shell, handle, mut instances, _) in query.iter_mut() {
+        let triangles = match settings.levels.get(shell.0) {
+            Some(&level) => extract(&grid, level),
+            None => vec![],
+        };
+        if triangles.is_empty() {
+            instances.0 = vec![];
+            continue;
+        }
+        if let Some(mesh) = meshes.get_mut(handle) {
+            *mesh = shell_mesh(&triangles, cube.layout);
+        }
+        // Inner shells are more opaque
+        let t = if count > 1 {
+            shell.0 as f32 / (count - 1) as f32
+        } else {
+            0.0
+        };
+        let alpha = MIN_ALPHA + (MAX_ALPHA - MIN_ALPHA) * t;
+        instances.0 = vec![InstanceData {
+            position: Vec3::ZERO,
+            scale: Vec3::ONE,
+            color: Color::rgba(1.0, 1.0, 1.0, alpha).as_rgba_f32(),
+        }];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Signed volume enclosed by the triangles, positive if they face out.
+    fn volume(triangles: &[[Vec3; 3]]) -> f32 {
+        triangles
+            .iter()
+            .map(|[a, b, c]| a.dot(b.cross(*c)) / 6.0)
+            .sum()
+    }
+
+    #[test]
+    fn empty_histogram_has_no_surface() {
+        let resolution = UVec3::splat(4);
+        let grid = DensityGrid::from_density(&[0.0; 64], resolution);
+        assert!(extract(&grid, 0.1).is_empty());
+    }
+
+    #[test]
+    fn nested_levels_enclose_shrinking_volumes() {
+        let resolution = UVec3::splat(8);
+        let center = Vec3::splat(0.5);
+        let density = (0..512)
+            .map(|i| {
+                let p = binning::bin_center(binning::index_to_coords(i, resolution), resolution);
+                1000.0 * (-(p - center).length_squared() * 20.0).exp()
+            })
+            .collect::<Vec<_>>();
+        let grid = DensityGrid::from_density(&density, resolution);
+        let outer = extract(&grid, 0.1);
+        let inner = extract(&grid, 0.5);
+        assert!(!outer.is_empty() && !inner.is_empty());
+        let (outer, inner) = (volume(&outer), volume(&inner));
+        assert!(outer > inner && inner > 0.0, "{outer} {inner}");
+        // The shells stay inside the cube
+        assert!(outer < 1.0);
+    }
+
+    #[test]
+    fn single_bin_is_closed() {
+        let resolution = UVec3::splat(3);
+        let mut density = vec![0.0; 27];
+        density[binning::coords_to_index(UVec3::splat(2), resolution)] = 10.0;
+        let grid = DensityGrid::from_density(&density, resolution);
+        let triangles = extract(&grid, 0.5);
+        // Every edge is shared by exactly two triangles, in opposite directions
+        let key = |p: Vec3| (p * 1e4).round().as_ivec3().to_array();
+        let mut edges = std::collections::HashMap::new();
+        for t in &triangles {
+            for (a, b) in [(t[0], t[1]), (t[1], t[2]), (t[2], t[0])] {
+                *edges.entry((key(a), key(b))).or_insert(0) += 1;
+            }
+        }
+        assert!(edges
+            .iter()
+            .all(|(&(a, b), &n)| n == 1 && edges.get(&(b, a)) == Some(&1)));
+        assert!(volume(&triangles) > 0.0);
+    }
+}
@@ -0,0 +1,628 @@
+//! Per-pixel kernels shared by the image processing systems.
+//!
+//! Every kernel has a scalar implementation and, with the `simd` feature, a
+//! vectorized one built on `simd128` (wasm), SSE2 (x86_64) or NEON (aarch64).
+//! Both evaluate the same operations in the same order so that their results
+//! are bit-identical. The tests compare the scalar kernels with the portable
+//! vectors on every build, and with the native ones under `--features simd`.
+
+use bevy::math::{Mat3, UVec3};
+
+pub type Pixel = [f32; 4];
+
+/// Applies `rotation` around the center of the unit cube to every pixel.
+/// Alpha is copied unchanged.
+pub fn transform(rotation: &Mat3, src: &[Pixel], dst: &mut [Pixel]) {
+    #[cfg(feature = "simd")]
+    simd::transform(rotation, src, dst);
+    #[cfg(not(feature = "simd"))]
+    scalar::transform(rotation, src, dst);
+}
+
+/// Like `transform`, but moves each pixel only `matte(pixel)` of the way to
+/// its transformed color, from 0 leaving it unchanged to 1 fully transformed.
+pub fn transform_blended(
+    rotation: &Mat3,
+    src: &[Pixel],
+    dst: &mut [Pixel],
+    matte: &impl Fn(&Pixel) -> f32,
+) {
+    #[cfg(feature = "simd")]
+    simd::transform_blended(rotation, src, dst, matte);
+    #[cfg(not(feature = "simd"))]
+    scalar::transform_blended(rotation, src, dst, matte);
+}
+
+/// Quantizes pixels to 8 bits per channel, as expected by `ImageData`.
+pub fn to_rgba8(src: &[Pixel], dst: &mut [u8]) {
+    #[cfg(feature = "simd")]
+    simd::to_rgba8(src, dst);
+    #[cfg(not(feature = "simd"))]
+    scalar::to_rgba8(src, dst);
+}
+
+/// Counts pixels into the bins laid out by the `binning` module.
+pub fn bin_pixels(src: &[Pixel], resolution: UVec3, counts: &mut [u32]) {
+    #[cfg(feature = "simd")]
+    simd::bin_pixels(src, resolution, counts);
+    #[cfg(not(feature = "simd"))]
+    scalar::bin_pixels(src, resolution, counts);
+}
+
+// With SIMD enabled the scalar kernels are only used as a reference by the tests
+#[cfg_attr(feature = "simd", allow(dead_code))]
+pub mod scalar {
+    use super::Pixel;
+    use crate::binning::bin_index;
+    use bevy::math::{Mat3, UVec3};
+
+    pub fn transform(rotation: &Mat3, src: &[Pixel], dst: &mut [Pixel]) {
+        let (cx, cy, cz) = (rotation.x_axis, rotation.y_axis, rotation.z_axis);
+        for (c, out) in src.iter().zip(dst.iter_mut()) {
+            let x = c[0] - 0.5;
+            let y = c[1] - 0.5;
+            let z = c[2] - 0.5;
+            *out = [
+                cx.x * x + cy.x * y + cz.x * z + 0.5,
+                cx.y * x + cy.y * y + cz.y * z + 0.5,
+                cx.z * x + cy.z * y + cz.z * z + 0.5,
+                c[3],
+            ];
+        }
+    }
+
+    pub fn transform_blended(
+        rotation: &Mat3,
+        src: &[Pixel],
+        dst: &mut [Pixel],
+        matte: &impl Fn(&Pixel) -> f32,
+    ) {
+        transform(rotation, src, dst);
+        for (c, out) in src.iter().zip(dst.iter_mut()) {
+            let m = matte(c);
+            for i in 0..3 {
+                out[i] = c[i] + (out[i] - c[i]) * m;
+            }
+        }
+    }
+
+    pub fn to_rgba8(src: &[Pixel], dst: &mut [u8]) {
+        for (c, out) in src.iter().zip(dst.chunks_exact_mut(4)) {
+            for (x, o) in c.iter().zip(out.iter_mut()) {
+                *o = (x * 255.0).floor() as u8;
+            }
+        }
+    }
+
+    pub fn bin_pixels(src: &[Pixel], resolution: UVec3, counts: &mut [u32]) {
+        for c in src.iter() {
+            counts[bin_index(c, resolution)] += 1;
+        }
+    }
+}
+
+/// The vectorized kernels, written once over four lane vectors. The portable
+/// fallback is always built, so that the tests can compare it with the scalar
+/// kernels on any target.
+#[cfg_attr(not(feature = "simd"), allow(dead_code))]
+pub mod vector {
+    use super::Pixel;
+    use crate::binning::coords_to_index;
+    use bevy::math::{Mat3, UVec3};
+
+    /// Four lane float vector. `clamp_to` maps NaN to zero and `trunc`
+    /// rounds toward zero, which matches `floor() as uN` for the clamped range.
+    pub trait F32x4: Copy {
+        fn splat(x: f32) -> Self;
+        fn load(x: [f32; 4]) -> Self;
+        fn store(self) -> [f32; 4];
+        fn add(self, other: Self) -> Self;
+        fn sub(self, other: Self) -> Self;
+        fn mul(self, other: Self) -> Self;
+        fn clamp_to(self, max: Self) -> Self;
+        fn trunc(self) -> [i32; 4];
+    }
+
+    pub fn transform<V: F32x4>(rotation: &Mat3, src: &[Pixel], dst: &mut [Pixel]) {
+        let (cx, cy, cz) = (rotation.x_axis, rotation.y_axis, rotation.z_axis);
+        let cx = V::load([cx.x, cx.y, cx.z, 0.0]);
+        let cy = V::load([cy.x, cy.y, cy.z, 0.0]);
+        let cz = V::load([cz.x, cz.y, cz.z, 0.0]);
+        let center = V::splat(0.5);
+        for (c, out) in src.iter().zip(dst.iter_mut()) {
+            let x = V::splat(c[0] - 0.5);
+            let y = V::splat(c[1] - 0.5);
+            let z = V::splat(c[2] - 0.5);
+            let p = cx.mul(x).add(cy.mul(y)).add(cz.mul(z)).add(center);
+            let mut p = p.store();
+            p[3] = c[3];
+            *out = p;
+        }
+    }
+
+    pub fn transform_blended<V: F32x4>(
+        rotation: &Mat3,
+        src: &[Pixel],
+        dst: &mut [Pixel],
+        matte: &impl Fn(&Pixel) -> f32,
+    ) {
+        let (cx, cy, cz) = (rotation.x_axis, rotation.y_axis, rotation.z_axis);
+        let cx = V::load([cx.x, cx.y, cx.z, 0.0]);
+        let cy = V::load([cy.x, cy.y, cy.z, 0.0]);
+        let cz = V::load([cz.x, cz.y, cz.z, 0.0]);
+        let center = V::splat(0.5);
+        for (c, out) in src.iter().zip(dst.iter_mut()) {
+            let x = V::splat(c[0] - 0.5);
+            let y = V::splat(c[1] - 0.5);
+            let z = V::splat(c[2] - 0.5);
+            let p = cx.mul(x).add(cy.mul(y)).add(cz.mul(z)).add(center);
+            let src = V::load(*c);
+            let p = src.add(p.sub(src).mul(V::splat(matte(c))));
+            let mut p = p.store();
+            p[3] = c[3];
+            *out = p;
+        }
+    }
+
+    pub fn to_rgba8<V: F32x4>(src: &[Pixel], dst: &mut [u8]) {
+        let scale = V::splat(255.0);
+        let max = V::splat(255.0);
+        for (c, out) in src.iter().zip(dst.chunks_exact_mut(4)) {
+            let q = V::load(*c).mul(scale).clamp_to(max).trunc();
+            for (x, o) in q.iter().zip(out.iter_mut()) {
+                *o = *x as u8;
+            }
+        }
+    }
+
+    pub fn bin_pixels<V: F32x4>(src: &[Pixel], resolution: UVec3, counts: &mut [u32]) {
+        let r = resolution.as_vec3();
+        let scale = V::load([r.x, r.y, r.z, 1.0]);
+        let max = V::load([r.x - 1.0, r.y - 1.0, r.z - 1.0, 0.0]);
+        for c in src.iter() {
+            let [xi, yi, zi, _] = V::load(*c).mul(scale).clamp_to(max).trunc();
+            let coords = UVec3::new(xi as u32, yi as u32, zi as u32);
+            counts[coords_to_index(coords, resolution)] += 1;
+        }
+    }
+
+    #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+    pub mod wasm32 {
+        use super::F32x4;
+        use core::arch::wasm32::*;
+
+        #[derive(Clone, Copy)]
+        pub struct Lanes(v128);
+
+        impl F32x4 for Lanes {
+            #[inline]
+            fn splat(x: f32) -> Self {
+                Lanes(f32x4_splat(x))
+            }
+
+            #[inline]
+            fn load(x: [f32; 4]) -> Self {
+                Lanes(f32x4(x[0], x[1], x[2], x[3]))
+            }
+
+            #[inline]
+            fn store(self) -> [f32; 4] {
+                [
+                    f32x4_extract_lane::<0>(self.0),
+                    f32x4_extract_lane::<1>(self.0),
+                    f32x4_extract_lane::<2>(self.0),
+                    f32x4_extract_lane::<3>(self.0),
+                ]
+            }
+
+            #[inline]
+            fn add(self, other: Self) -> Self {
+                Lanes(f32x4_add(self.0, other.0))
+            }
+
+            #[inline]
+            fn sub(self, other: Self) -> Self {
+                Lanes(f32x4_sub(self.0, other.0))
+            }
+
+            #[inline]
+            fn mul(self, other: Self) -> Self {
+                Lanes(f32x4_mul(self.0, other.0))
+            }
+
+            #[inline]
+            fn clamp_to(self, max: Self) -> Self {
+                // Pseudo-min/max pick the second operand only if it compares
+                // strictly, so a NaN lane falls back to zero
+                let x = f32x4_pmax(f32x4_splat(0.0), self.0);
+                Lanes(f32x4_pmin(max.0, x))
+            }
+
+            #[inline]
+            fn trunc(self) -> [i32; 4] {
+                let x = i32x4_trunc_sat_f32x4(self.0);
+                [
+                    i32x4_extract_lane::<0>(x),
+                    i32x4_extract_lane::<1>(x),
+                    i32x4_extract_lane::<2>(x),
+                    i32x4_extract_lane::<3>(x),
+                ]
+            }
+        }
+    }
+
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    pub mod x86_64 {
+        use super::F32x4;
+        use core::arch::x86_64::*;
+
+        #[derive(Clone, Copy)]
+        pub struct Lanes(__m128);
+
+        // SSE2 is part of the x86_64 baseline, so these intrinsics are always available
+        impl F32x4 for Lanes {
+            #[inline]
+            fn splat(x: f32) -> Self {
+                Lanes(unsafe { _mm_set1_ps(x) })
+            }
+
+            #[inline]
+            fn load(x: [f32; 4]) -> Self {
+                Lanes(unsafe { _mm_loadu_ps(x.as_ptr()) })
+            }
+
+            #[inline]
+            fn store(self) -> [f32; 4] {
+                let mut x = [0.0; 4];
+                unsafe { _mm_storeu_ps(x.as_mut_ptr(), self.0) };
+                x
+            }
+
+            #[inline]
+            fn add(self, other: Self) -> Self {
+                Lanes(unsafe { _mm_add_ps(self.0, other.0) })
+            }
+
+            #[inline]
+            fn sub(self, other: Self) -> Self {
+                Lanes(unsafe { _mm_sub_ps(self.0, other.0) })
+            }
+
+            #[inline]
+            fn mul(self, other: Self) -> Self {
+                Lanes(unsafe { _mm_mul_ps(self.0, other.0) })
+            }
+
+            #[inline]
+            fn clamp_to(self, max: Self) -> Self {
+                // maxps returns the second operand when either one is NaN
+                Lanes(unsafe { _mm_min_ps(_mm_max_ps(self.0, _mm_setzero_ps()), max.0) })
+            }
+
+            #[inline]
+            fn trunc(self) -> [i32; 4] {
+                let mut x = [0; 4];
+                unsafe {
+                    _mm_storeu_si128(x.as_mut_ptr() as *mut __m128i, _mm_cvttps_epi32(self.0))
+                };
+                x
+            }
+        }
+    }
+
+    #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+    pub mod aarch64 {
+        use super::F32x4;
+        use core::arch::aarch64::*;
+
+        #[derive(Clone, Copy)]
+        pub struct Lanes(float32x4_t);
+
+        // NEON is part of the aarch64 baseline, so these intrinsics are always available
+        impl F32x4 for Lanes {
+            #[inline]
+            fn splat(x: f32) -> Self {
+                Lanes(unsafe { vdupq_n_f32(x) })
+            }
+
+            #[inline]
+            fn load(x: [f32; 4]) -> Self {
+                Lanes(unsafe { vld1q_f32(x.as_ptr()) })
+            }
+
+            #[inline]
+            fn store(self) -> [f32; 4] {
+                let mut x = [0.0; 4];
+                unsafe { vst1q_f32(x.as_mut_ptr(), self.0) };
+                x
+            }
+
+            #[inline]
+            fn add(self, other: Self) -> Self {
+                Lanes(unsafe { vaddq_f32(self.0, other.0) })
+            }
+
+            #[inline]
+            fn sub(self, other: Self) -> Self {
+                Lanes(unsafe { vsubq_f32(self.0, other.0) })
+            }
+
+            #[inline]
+            fn mul(self, other: Self) -> Self {
+                Lanes(unsafe { vmulq_f32(self.0, other.0) })
+            }
+
+            #[inline]
+            fn clamp_to(self, max: Self) -> Self {
+                // maxnm returns the numeric operand when the other one is NaN
+                Lanes(unsafe { vminq_f32(vmaxnmq_f32(self.0, vdupq_n_f32(0.0)), max.0) })
+            }
+
+            #[inline]
+            fn trunc(self) -> [i32; 4] {
+                let mut x = [0; 4];
+                unsafe { vst1q_s32(x.as_mut_ptr(), vcvtq_s32_f32(self.0)) };
+                x
+            }
+        }
+    }
+
+    /// Plain arrays, for targets without vectors, and as the portable
+    /// reference in the tests.
+    #[cfg_attr(feature = "simd", allow(dead_code))]
+    pub mod fallback {
+        use super::F32x4;
+
+        #[derive(Clone, Copy)]
+        pub struct Lanes([f32; 4]);
+
+        impl F32x4 for Lanes {
+            #[inline]
+            fn splat(x: f32) -> Self {
+                Lanes([x; 4])
+            }
+
+            #[inline]
+            fn load(x: [f32; 4]) -> Self {
+                Lanes(x)
+            }
+
+            #[inline]
+            fn store(self) -> [f32; 4] {
+                self.0
+            }
+
+            #[inline]
+            fn add(self, other: Self) -> Self {
+                let mut x = self.0;
+                for (a, b) in x.iter_mut().zip(other.0) {
+                    *a += b;
+                }
+                Lanes(x)
+            }
+
+            #[inline]
+            fn sub(self, other: Self) -> Self {
+                let mut x = self.0;
+                for (a, b) in x.iter_mut().zip(other.0) {
+                    *a -= b;
+                }
+                Lanes(x)
+            }
+
+            #[inline]
+            fn mul(self, other: Self) -> Self {
+                let mut x = self.0;
+                for (a, b) in x.iter_mut().zip(other.0) {
+                    *a *= b;
+                }
+                Lanes(x)
+            }
+
+            #[inline]
+            fn clamp_to(self, max: Self) -> Self {
+                let mut x = self.0;
+                for (a, b) in x.iter_mut().zip(max.0) {
+                    *a = if *a > 0.0 { a.min(b) } else { 0.0 };
+                }
+                Lanes(x)
+            }
+
+            #[inline]
+            fn trunc(self) -> [i32; 4] {
+                self.0.map(|x| x as i32)
+            }
+        }
+    }
+}
+
+/// The vectorized kernels on the platform's vectors: `simd128` (wasm), SSE2
+/// (x86_64) or NEON (aarch64).
+#[cfg(feature = "simd")]
+pub mod simd {
+    use super::{vector, Pixel};
+    use bevy::math::{Mat3, UVec3};
+
+    #[cfg(target_arch = "aarch64")]
+    pub use super::vector::aarch64::Lanes;
+    #[cfg(not(any(
+        all(target_arch = "wasm32", target_feature = "simd128"),
+        target_arch = "x86_64",
+        target_arch = "aarch64"
+    )))]
+    pub use super::vector::fallback::Lanes;
+    #[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+    pub use super::vector::wasm32::Lanes;
+    #[cfg(target_arch = "x86_64")]
+    pub use super::vector::x86_64::Lanes;
+
+    pub fn transform(rotation: &Mat3, src: &[Pixel], dst: &mut [Pixel]) {
+        vector::transform::<Lanes>(rotation, src, dst);
+    }
+
+    pub fn transform_blended(
+        rotation: &Mat3,
+        src: &[Pixel],
+        dst: &mut [Pixel],
+        matte: &impl Fn(&Pixel) -> f32,
+    ) {
+        vector::transform_blended::<Lanes>(rotation, src, dst, matte);
+    }
+
+    pub fn to_rgba8(src: &[Pixel], dst: &mut [u8]) {
+        vector::to_rgba8::<Lanes>(src, dst);
+    }
+
+    pub fn bin_pixels(src: &[Pixel], resolution: UVec3, counts: &mut [u32]) {
+        vector::bin_pixels::<Lanes>(src, resolution, counts);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::vector::F32x4;
+    use super::*;
+    use bevy::math::{Quat, Vec3};
+
+    /// Deterministic pixels covering the unit cube, its boundaries and a few
+    /// out of range values.
+    fn test_pixels() -> Vec<Pixel> {
+        let mut state = 0x2545_f491_u32;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as f32 / u32::MAX as f32
+        };
+        let mut pixels = (0..4096)
+            .map(|_| [next(), next(), next(), next()])
+            .collect::<Vec<_>>();
+        pixels.extend_from_slice(&[
+            [0.0, 0.0, 0.0, 0.0],
+            [1.0, 1.0, 1.0, 1.0],
+            [-0.0, 0.5, 1.0 - f32::EPSILON, 0.25],
+            [-0.25, 1.25, 2.0, 1.0],
+            [-1e9, 1e9, 0.999_999, 0.5],
+        ]);
+        pixels
+    }
+
+    /// A quarter turn around the blue axis, with exact coefficients.
+    fn quarter_turn() -> Mat3 {
+        Mat3::from_cols(Vec3::Y, -Vec3::X, Vec3::Z)
+    }
+
+    #[test]
+    fn scalar_transform_rotates_around_the_center() {
+        let src = [[1.0, 0.5, 0.25, 0.7], [0.5, 0.5, 0.5, 0.0]];
+        let mut dst = [[0.0; 4]; 2];
+        scalar::transform(&Mat3::IDENTITY, &src, &mut dst);
+        assert_eq!(dst, src);
+        scalar::transform(&quarter_turn(), &src, &mut dst);
+        assert_eq!(dst, [[0.5, 1.0, 0.25, 0.7], [0.5, 0.5, 0.5, 0.0]]);
+    }
+
+    #[test]
+    fn scalar_blend_follows_the_matte() {
+        let src = [
+            [1.0, 0.5, 0.25, 0.0],
+            [1.0, 0.5, 0.25, 1.0],
+            [1.0, 0.5, 0.25, 0.5],
+        ];
+        let mut dst = [[0.0; 4]; 3];
+        // Each pixel's alpha as its matte
+        scalar::transform_blended(&quarter_turn(), &src, &mut dst, &|c: &Pixel| c[3]);
+        assert_eq!(
+            dst,
+            [
+                [1.0, 0.5, 0.25, 0.0],
+                [0.5, 1.0, 0.25, 1.0],
+                [0.75, 0.75, 0.25, 0.5]
+            ]
+        );
+    }
+
+    #[test]
+    fn scalar_to_rgba8_floors_and_saturates() {
+        let src = [
+            [0.0, 0.5, 1.0, 254.5 / 255.0],
+            [-1.0, 2.0, f32::NAN, 1.0 / 255.0],
+        ];
+        let mut dst = [0; 8];
+        scalar::to_rgba8(&src, &mut dst);
+        assert_eq!(dst, [0, 127, 255, 254, 0, 255, 0, 1]);
+    }
+
+    #[test]
+    fn scalar_bin_pixels_counts_each_pixel_once() {
+        let resolution = UVec3::splat(2);
+        let src = [
+            [0.0, 0.0, 0.0, 1.0],
+            [1.0, 1.0, 1.0, 1.0],
+            [0.75, 0.25, 0.9, 0.0],
+            [-1.0, 0.1, 0.4, 1.0],
+        ];
+        let mut counts = [0; 8];
+        scalar::bin_pixels(&src, resolution, &mut counts);
+        assert_eq!(counts, [2, 0, 0, 0, 0, 1, 0, 1]);
+    }
+
+    /// Runs the vectorized kernels on `V` and the scalar ones on the same
+    /// pixels, and checks that every result is bit-identical.
+    fn assert_vectors_match_scalar<V: F32x4>() {
+        let mut pixels = test_pixels();
+
+        for angle in [0.0f32, 17.0, 90.0, 123.4, 270.0] {
+            let rotation = Mat3::from_quat(Quat::from_axis_angle(
+                Vec3::new(1.0, 1.0, 1.0).normalize(),
+                angle.to_radians(),
+            ));
+            let mut expected = vec![[0.0; 4]; pixels.len()];
+            let mut actual = vec![[0.0; 4]; pixels.len()];
+            scalar::transform(&rotation, &pixels, &mut expected);
+            vector::transform::<V>(&rotation, &pixels, &mut actual);
+            for (e, a) in expected.iter().zip(actual.iter()) {
+                assert_eq!(e.map(f32::to_bits), a.map(f32::to_bits));
+            }
+
+            let matte = |c: &Pixel| c[3].clamp(0.0, 1.0);
+            scalar::transform_blended(&rotation, &pixels, &mut expected, &matte);
+            vector::transform_blended::<V>(&rotation, &pixels, &mut actual, &matte);
+            for (e, a) in expected.iter().zip(actual.iter()) {
+                assert_eq!(e.map(f32::to_bits), a.map(f32::to_bits));
+            }
+        }
+
+        pixels.push([f32::NAN, f32::INFINITY, f32::NEG_INFINITY, 254.5 / 255.0]);
+        let mut expected = vec![0; pixels.len() * 4];
+        let mut actual = vec![0; pixels.len() * 4];
+        scalar::to_rgba8(&pixels, &mut expected);
+        vector::to_rgba8::<V>(&pixels, &mut actual);
+        assert_eq!(expected, actual);
+
+        for resolution in [
+            UVec3::splat(1),
+            UVec3::splat(2),
+            UVec3::new(7, 3, 5),
+            UVec3::splat(32),
+        ] {
+            let num_bins = (resolution.x * resolution.y * resolution.z) as usize;
+            let mut expected = vec![0; num_bins];
+            let mut actual = vec![0; num_bins];
+            scalar::bin_pixels(&pixels, resolution, &mut expected);
+            vector::bin_pixels::<V>(&pixels, resolution, &mut actual);
+            assert_eq!(expected, actual);
+        }
+    }
+
+    #[test]
+    fn portable_vectors_match_scalar() {
+        assert_vectors_match_scalar::<vector::fallback::Lanes>();
+    }
+
+    #[cfg(feature = "simd")]
+    #[test]
+    fn native_vectors_match_scalar() {
+        assert_vectors_match_scalar::<simd::Lanes>();
+    }
+}
@@ -0,0 +1,792 @@
+//! Chroma keying, which turns a region of colors into transparency.
+//!
+//! The key is a qualifier region of the input colors, typically around the
+//! green or blue of a screen, or the color volume selected in the cube. Its
+//! matte is inverted into alpha, then choked
+//! (eroded, or dilated for negative values) and softened with a blur, both in
+//! pixels of the full resolution image. Spill suppression pulls the key's
+//! dominant channel of the transformed colors down to the other two. The
+//! result is kept next to the output image in `Keyed`, optionally
+//! premultiplied by alpha or composited over a background, and only drawn.
+//! The output image keeps the transformed colors, which the color cube and
+//! the other analyses bin.
+//!
+//! Keying waits for the whole transformed image, since the filters spread
+//! the matte across pixels, then runs as its own stage across frames.
+
+use std::borrow::Cow;
+use std::ops::Range;
+
+use bevy::prelude::*;
+
+use crate::color_cube::ColorCube;
+use crate::image::{
+    Image, Input, Output, RenderRequest, TransformImageEvent, TransformStartedEvent,
+};
+use crate::kernels::Pixel;
+use crate::layout::{self, Layout};
+use crate::processing::{
+    self, FrameBudget, ProcessingProgressEvent, ProcessingSettings, ProcessingStage,
+};
+use crate::proxy::Proxy;
+use crate::qualifier::{QualifierRegion, QualifierSpace};
+use crate::selection::{ColorVolume, Selection};
+
+/// Largest choke and softness radius, in pixels.
+const MAX_RADIUS: f32 = 64.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MatteSettings {
+    /// How much of the spill is suppressed, from 0 to 1
+    pub spill: f32,
+    /// Pixels the matte is eroded by, or dilated by if negative
+    pub choke: f32,
+    /// Radius of the blur softening the edge of the matte, in pixels
+    pub softness: f32,
+    /// Store colors premultiplied by alpha
+    pub premultiply: bool,
+}
+
+impl Default for MatteSettings {
+    fn default() -> Self {
+        MatteSettings {
+            spill: 0.5,
+            choke: 0.0,
+            softness: 1.0,
+            premultiply: false,
+        }
+    }
+}
+
+/// What the keyed image is composited over.
+#[derive(Clone, Debug)]
+pub enum KeyBackground {
+    /// Nothing, the output keeps its alpha
+    None,
+    Color(Vec3),
+    /// Stretched over the output
+    Image {
+        width: u32,
+        height: u32,
+        data: Vec<Pixel>,
+    },
+}
+
+/// Colors keyed into transparency.
+#[derive(Clone, Debug)]
+pub enum KeyVolume {
+    /// A qualifier region of the input colors, with its soft edge
+    Region(QualifierRegion),
+    /// The color volume selected in the cube, tested against the input colors
+    Selection,
+}
+
+/// Keys the output image. Kept next to the output image.
+#[derive(Component, Clone, Debug)]
+pub struct Keyer {
+    pub key: Option<KeyVolume>,
+    pub matte: MatteSettings,
+    pub background: KeyBackground,
+}
+
+impl Default for Keyer {
+    fn default() -> Self {
+        Keyer {
+            key: None,
+            matte: MatteSettings::default(),
+            background: KeyBackground::None,
+        }
+    }
+}
+
+impl Keyer {
+    pub fn is_enabled(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// Whether the keyed image holds premultiplied colors with their alpha.
+    pub fn is_premultiplied(&self) -> bool {
+        self.is_enabled()
+            && self.matte.premultiply
+            && matches!(self.background, KeyBackground::None)
+    }
+}
+
+/// The output image once keyed, kept next to it. Empty until the key of the
+/// current output is complete.
+#[derive(Component, Default)]
+pub struct Keyed(pub Vec<Pixel>);
+
+impl Keyed {
+    /// Whether this is the complete key of the `output` pixels.
+    pub fn keys(&self, keyer: &Keyer, output: &[Pixel]) -> bool {
+        keyer.is_enabled() && self.0.len() == output.len()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SetKeyEvent {
+    pub key: Option<KeyVolume>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SetKeyMatteEvent {
+    pub matte: MatteSettings,
+}
+
+#[derive(Clone, Debug)]
+pub struct SetKeyBackgroundEvent {
+    pub background: KeyBackground,
+}
+
+/// Sent when the output image is transformed and ready to be keyed.
+#[derive(Clone, Debug)]
+pub struct KeyImageEvent;
+
+/// Alpha of a pixel, before choking and softening. Keying by a selection
+/// needs the selected volume and the layout its colors are drawn in.
+pub fn key_alpha(key: &KeyVolume, selection: Option<(&ColorVolume, Layout)>, c: &Pixel) -> f32 {
+    let rgb = Vec3::new(c[0], c[1], c[2]);
+    match (key, selection) {
+        (KeyVolume::Region(region), _) => 1.0 - region.matte(rgb),
+        (KeyVolume::Selection, Some((volume, layout))) => {
+            let selected = volume.contains(layout.position(layout.coords(rgb)));
+            if selected {
+                0.0
+            } else {
+                1.0
+            }
+        }
+        (KeyVolume::Selection, None) => 1.0,
+    }
+}
+
+/// Minimum (or maximum) over `radius` values on each side of every value of
+/// `line`, the window shrinking at the ends. Uses the van Herk/Gil-Werman
+/// algorithm, which takes three comparisons per value whatever the radius.
+pub fn min_filter_line(line: &[f32], radius: usize, max: bool, out: &mut [f32]) {
+    let pick = |a: f32, b: f32| if max { a.max(b) } else { a.min(b) };
+    let neutral = if max {
+        f32::NEG_INFINITY
+    } else {
+        f32::INFINITY
+    };
+    let window = 2 * radius + 1;
+    // Padded with neutral values, so that no window needs special casing
+    let len = line.len() + 2 * radius;
+    let get = |i: usize| {
+        i.checked_sub(radius)
+            .and_then(|i| line.get(i))
+            .copied()
+            .unwrap_or(neutral)
+    };
+    // Running picks from the start of each block of `window` values, and
+    // from the end of it
+    let mut prefix = vec![neutral; len];
+    let mut suffix = vec![neutral; len];
+    for i in 0..len {
+        prefix[i] = if i % window == 0 {
+            get(i)
+        } else {
+            pick(prefix[i - 1], get(i))
+        };
+    }
+    for i in (0..len).rev() {
+        suffix[i] = if i % window == window - 1 || i == len - 1 {
+            get(i)
+        } else {
+            pick(suffix[i + 1], get(i))
+        };
+    }
+    // Every window spans the end of one block and the start of the next
+    for (x, o) in out.iter_mut().enumerate() {
+        *o = pick(suffix[x], prefix[x + window - 1]);
+    }
+}
+
+/// Box blur over `radius` values on each side of every value of `line`,
+/// shrinking the box at the ends.
+pub fn box_blur_line(line: &[f32], radius: usize, out: &mut [f32]) {
+    let len = line.len();
+    let mut sum = 0.0;
+    let mut start = 0;
+    let mut end = 0;
+    for (i, o) in out.iter_mut().enumerate() {
+        while end < (i + radius + 1).min(len) {
+            sum += line[end];
+            end += 1;
+        }
+        while start < i.saturating_sub(radius) {
+            sum -= line[start];
+            start += 1;
+        }
+        *o = sum / (end - start) as f32;
+    }
+}
+
+/// Pulls the channel the key color is strongest in down towards the larger
+/// of the other two, by `amount`.
+pub fn suppress_spill(key: Vec3, amount: f32, c: &mut Pixel) {
+    let channel = if key.x >= key.y && key.x >= key.z {
+        0
+    } else if key.y >= key.z {
+        1
+    } else {
+        2
+    };
+    let limit = (0..3)
+        .filter(|&i| i != channel)
+        .map(|i| c[i])
+        .fold(f32::MIN, f32::max);
+    if c[channel] > limit {
+        c[channel] -= amount * (c[channel] - limit);
+    }
+}
+
+/// Stages of keying, in order. The choke and softness filters are separable,
+/// and run over the rows of the matte, then over its columns.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum KeyPass {
+    Matte,
+    ChokeRows,
+    ChokeColumns,
+    SoftenRows,
+    SoftenColumns,
+    Composite,
+}
+
+/// Keying of the output image, spread across frames a chunk of pixels or of
+/// lines at a time.
+#[derive(Default)]
+pub struct KeyJob {
+    job: processing::Job,
+    /// `None` when there is nothing to key
+    pass: Option<KeyPass>,
+    width: usize,
+    height: usize,
+    /// Pixels the matte is eroded by, or dilated by if negative
+    choke: i32,
+    softness: usize,
+    alpha: Vec<f32>,
+    /// Sum and count of the keyed input colors, for a selection's key color
+    keyed_sum: Vec3,
+    keyed_count: u32,
+    key_color: Option<Vec3>,
+}
+
+impl KeyJob {
+    /// Starts keying an image `width` pixels wide. `scale` converts the full
+    /// resolution radii to the image's.
+    pub fn start(&mut self, keyer: &Keyer, width: u32, height: u32, scale: f32) {
+        let key = match &keyer.key {
+            Some(key) => key,
+            None => return self.cancel(),
+        };
+        let matte = &keyer.matte;
+        self.width = width as usize;
+        self.height = height as usize;
+        self.choke = (matte.choke.clamp(-MAX_RADIUS, MAX_RADIUS) * scale).round() as i32;
+        self.softness = (matte.softness.clamp(0.0, MAX_RADIUS) * scale).round() as usize;
+        self.alpha = vec![0.0; self.width * self.height];
+        self.keyed_sum = Vec3::ZERO;
+        self.keyed_count = 0;
+        self.key_color = match key {
+            KeyVolume::Region(region) => Some(match region.space {
+                QualifierSpace::Rgb => region.center,
+                QualifierSpace::Hsl => layout::hsl_to_rgb(region.center),
+            }),
+            // The mean of the keyed pixels, once they are known
+            KeyVolume::Selection => None,
+        };
+        self.begin(KeyPass::Matte);
+    }
+
+    pub fn cancel(&mut self) {
+        self.pass = None;
+        self.job.cancel();
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.pass.is_some()
+    }
+
+    pub fn progress(&self) -> ProcessingProgressEvent {
+        self.job.progress(ProcessingStage::Keying)
+    }
+
+    /// Starts `pass`, or the first pass after it that has something to do.
+    fn begin(&mut self, pass: KeyPass) {
+        let skipped = match pass {
+            KeyPass::ChokeRows | KeyPass::ChokeColumns => self.choke == 0,
+            KeyPass::SoftenRows | KeyPass::SoftenColumns => self.softness == 0,
+            _ => false,
+        };
+        if skipped {
+            return self.begin(next_pass(pass).unwrap());
+        }
+        self.pass = Some(pass);
+        self.job.start(match pass {
+            KeyPass::ChokeRows | KeyPass::SoftenRows => self.height,
+            KeyPass::ChokeColumns | KeyPass::SoftenColumns => self.width,
+            KeyPass::Matte | KeyPass::Composite => self.width * self.height,
+        });
+    }
+
+    /// Keys the transformed `dst` pixels with the matte of the `src` pixels
+    /// they came from, until the frame budget runs out. `chunk_size` is the
+    /// number of pixels to process between checks of the budget. Returns
+    /// true once the image is keyed.
+    pub fn run(
+        &mut self,
+        keyer: &Keyer,
+        selection: Option<(&ColorVolume, Layout)>,
+        src: &[Pixel],
+        dst: &mut [Pixel],
+        chunk_size: usize,
+        budget: &FrameBudget,
+    ) -> bool {
+        let key = match &keyer.key {
+            Some(key) => key,
+            None => return false,
+        };
+        if src.len() != self.alpha.len() || dst.len() != self.alpha.len() {
+            self.cancel();
+            return false;
+        }
+        while let Some(pass) = self.pass {
+            let (width, height) = (self.width, self.height);
+            let line_len = match pass {
+                KeyPass::ChokeRows | KeyPass::SoftenRows => width,
+                KeyPass::ChokeColumns | KeyPass::SoftenColumns => height,
+                KeyPass::Matte | KeyPass::Composite => 1,
+            };
+            let choke = self.choke;
+            let softness = self.softness;
+            let key_color = self.key_color;
+            let KeyJob {
+                job,
+                alpha,
+                keyed_sum,
+                keyed_count,
+                ..
+            } = self;
+            let chunk = chunk_size / line_len.max(1);
+            let finished = job.run_chunks(chunk, budget, |range| match pass {
+                KeyPass::Matte => {
+                    for i in range {
+                        let a = key_alpha(key, selection, &src[i]);
+                        alpha[i] = a;
+                        if a < 0.5 {
+                            *keyed_sum += Vec3::new(src[i][0], src[i][1], src[i][2]);
+                            *keyed_count += 1;
+                        }
+                    }
+                }
+                KeyPass::ChokeRows | KeyPass::ChokeColumns => {
+                    let radius = choke.unsigned_abs() as usize;
+                    let columns = pass == KeyPass::ChokeColumns;
+                    filter_lines(alpha, width, range, columns, |line, out| {
+                        min_filter_line(line, radius, choke < 0, out)
+                    });
+                }
+                KeyPass::SoftenRows | KeyPass::SoftenColumns => {
+                    let columns = pass == KeyPass::SoftenColumns;
+                    filter_lines(alpha, width, range, columns, |line, out| {
+                        box_blur_line(line, softness, out)
+                    });
+                }
+                KeyPass::Composite => {
+                    for i in range {
+                        composite(keyer, key_color, i, width, height, alpha[i], &mut dst[i]);
+                    }
+                }
+            });
+            if !finished {
+                return false;
+            }
+            if pass == KeyPass::Matte && matches!(key, KeyVolume::Selection) {
+                self.key_color =
+                    (self.keyed_count > 0).then(|| self.keyed_sum / self.keyed_count as f32);
+            }
+            match next_pass(pass) {
+                Some(next) => self.begin(next),
+                None => {
+                    self.pass = None;
+                    return true;
+                }
+            }
+            if budget.exhausted() {
+                return false;
+            }
+        }
+        false
+    }
+}
+
+fn next_pass(pass: KeyPass) -> Option<KeyPass> {
+    match pass {
+        KeyPass::Matte => Some(KeyPass::ChokeRows),
+        KeyPass::ChokeRows => Some(KeyPass::ChokeColumns),
+        KeyPass::ChokeColumns => Some(KeyPass::SoftenRows),
+        KeyPass::SoftenRows => Some(KeyPass::SoftenColumns),
+        KeyPass::SoftenColumns => Some(KeyPass::Composite),
+        KeyPass::Composite => None,
+    }
+}
+
+/// Filters the given rows, or columns, of an image `width` pixels wide in
+/// place.
+fn filter_lines(
+    values: &mut [f32],
+    width: usize,
+    lines: Range<usize>,
+    columns: bool,
+    f: impl Fn(&[f32], &mut [f32]),
+) {
+    let height = values.len() / width.max(1);
+    let len = if columns { height } else { width };
+    let mut line = vec![0.0; len];
+    let mut out = vec![0.0; len];
+    for l in lines {
+        let index = |k: usize| {
+            if columns {
+                k * width + l
+            } else {
+                l * width + k
+            }
+        };
+        for (k, v) in line.iter_mut().enumerate() {
+            *v = values[index(k)];
+        }
+        f(&line, &mut out);
+        for (k, v) in out.iter().enumerate() {
+            values[index(k)] = *v;
+        }
+    }
+}
+
+/// Applies the spill suppression and the matte to pixel `i` of an image
+/// `width` by `height` pixels.
+fn composite(
+    keyer: &Keyer,
+    key_color: Option<Vec3>,
+    i: usize,
+    width: usize,
+    height: usize,
+    alpha: f32,
+    c: &mut Pixel,
+) {
+    let matte = &keyer.matte;
+    if let Some(key_color) = key_color {
+        suppress_spill(key_color, matte.spill, c);
+    }
+    let a = alpha * c[3];
+    let background = match &keyer.background {
+        KeyBackground::None => None,
+        KeyBackground::Color(color) => Some(*color),
+        KeyBackground::Image {
+            width: bw,
+            height: bh,
+            data,
+        } => {
+            // Nearest pixel of the stretched background
+            let x = (i % width) * *bw as usize / width.max(1);
+            let y = (i / width) * *bh as usize / height.max(1);
+            data.get(y * *bw as usize + x)
+                .map(|b| Vec3::new(b[0], b[1], b[2]))
+        }
+    };
+    match background {
+        Some(b) => {
+            for k in 0..3 {
+                c[k] = c[k] * a + b[k] * (1.0 - a);
+            }
+            c[3] = 1.0;
+        }
+        None if matte.premultiply => {
+            for x in &mut c[..3] {
+                *x *= a;
+            }
+            c[3] = a;
+        }
+        None => c[3] = a,
+    }
+}
+
+/// Keys the output image once it's transformed, spread across frames like the
+/// transform itself. The canvas follows once it's done.
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn key_image(
+    mut events: EventReader<KeyImageEvent>,
+    mut cancel_events: EventReader<TransformStartedEvent>,
+    mut key_job: Local<KeyJob>,
+    mut keying: Local<Vec<Pixel>>,
+    settings: Res<ProcessingSettings>,
+    budget: Res<FrameBudget>,
+    input_query: Query<(&Image, &Proxy), (With<Input>, Without<Output>)>,
+    mut output_query: Query<(&Image, &mut Keyed, &Keyer, &Selection), With<Output>>,
+    cube_query: Query<&ColorCube>,
+    mut out_render_events: EventWriter<RenderRequest>,
+    mut out_progress_events: EventWriter<ProcessingProgressEvent>,
+) {
+    let cancel = cancel_events.iter().count() > 0;
+    let start = events.iter().count() > 0;
+    if let Some((input, proxy)) = input_query.iter().last() {
+        if let Some((output, mut keyed, keyer, selection)) = output_query.iter_mut().last() {
+            // The output image is being rewritten, so the key is stale
+            if cancel {
+                key_job.cancel();
+                keyed.0.clear();
+            }
+            if start {
+                // Radii are given in pixels of the full resolution image
+                let scale = output.width as f32 / input.width.max(1) as f32;
+                key_job.start(keyer, output.width, output.height, scale);
+                keying.clone_from(&output.data);
+            }
+            if !key_job.is_active() {
+                return;
+            }
+
+            // The output may have been computed from the proxy
+            let input = proxy.select(input, input.data.len() != output.data.len());
+            let layout = cube_query.iter().last().map(|cube| cube.layout);
+            let selection = selection.volume.as_ref().zip(layout);
+            let finished = key_job.run(
+                keyer,
+                selection,
+                &input.data,
+                &mut keying,
+                settings.chunk_size,
+                &budget,
+            );
+            out_progress_events.send(key_job.progress());
+            if finished {
+                std::mem::swap(&mut keyed.0, &mut *keying);
+                out_render_events.send(RenderRequest);
+            }
+        }
+    }
+}
+
+/// Pixels of the output image to draw: its key with straight alpha once that
+/// is complete, or the transformed colors.
+pub fn displayed<'a>(keyer: &Keyer, keyed: &'a Keyed, output: &'a [Pixel]) -> Cow<'a, [Pixel]> {
+    if keyed.keys(keyer, output) {
+        unpremultiplied(keyer, &keyed.0)
+    } else {
+        Cow::Borrowed(output)
+    }
+}
+
+/// Pixels as `ImageData` expects them, with straight alpha.
+pub fn unpremultiplied<'a>(keyer: &Keyer, src: &'a [Pixel]) -> Cow<'a, [Pixel]> {
+    if !keyer.is_premultiplied() {
+        return Cow::Borrowed(src);
+    }
+    Cow::Owned(
+        src.iter()
+            .map(|c| {
+                if c[3] > 0.0 {
+                    [c[0] / c[3], c[1] / c[3], c[2] / c[3], c[3]]
+                } else {
+                    [0.0; 4]
+                }
+            })
+            .collect(),
+    )
+}
+
+pub fn set_keyer(
+    mut events: EventReader<SetKeyEvent>,
+    mut matte_events: EventReader<SetKeyMatteEvent>,
+    mut background_events: EventReader<SetKeyBackgroundEvent>,
+    mut query: Query<&mut Keyer>,
+    selection_query: Query<&Selection, Changed<Selection>>,
+    mut out_events: EventWriter<TransformImageEvent>,
+) {
+    let evts = events.iter().collect::<Vec<_>>();
+    let matte_evts = matte_events.iter().collect::<Vec<_>>();
+    let background_evts = background_events.iter().collect::<Vec<_>>();
+    if let Some(mut keyer) = query.iter_mut().last() {
+        // A selection key follows the selection
+        let mut changed =
+            matches!(keyer.key, Some(KeyVolume::Selection)) && selection_query.iter().count() > 0;
+        if let Some(evt) = evts.into_iter().last() {
+            keyer.key = evt.key.clone();
+            changed = true;
+        }
+        if let Some(evt) = matte_evts.into_iter().last() {
+            keyer.matte = evt.matte;
+            changed = true;
+        }
+        if let Some(evt) = background_evts.into_iter().last() {
+            keyer.background = evt.background.clone();
+            changed = true;
+        }
+        if changed {
+            out_events.send(TransformImageEvent { interactive: true });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::qualifier::QualifierShape;
+
+    fn green_screen() -> QualifierRegion {
+        QualifierRegion {
+            space: QualifierSpace::Rgb,
+            shape: QualifierShape::Ellipsoid,
+            center: Vec3::new(0.0, 1.0, 0.0),
+            extent: Vec3::splat(0.3),
+            softness: 0.0,
+        }
+    }
+
+    /// Keys the whole image, a few pixels per chunk.
+    fn key(keyer: &Keyer, src: &[Pixel], dst: &mut [Pixel], width: u32) -> KeyJob {
+        let mut job = KeyJob::default();
+        let height = src.len() as u32 / width;
+        job.start(keyer, width, height, 1.0);
+        let budget = FrameBudget::default();
+        while !job.run(keyer, None, src, dst, 3, &budget) {
+            assert!(job.is_active());
+        }
+        job
+    }
+
+    #[test]
+    fn choke_erodes_and_softness_blurs() {
+        // A strip with one keyed pixel in the middle
+        let alpha = [1.0, 1.0, 0.0, 1.0, 1.0];
+        let mut out = [0.0; 5];
+        min_filter_line(&alpha, 1, false, &mut out);
+        assert_eq!(out, [1.0, 0.0, 0.0, 0.0, 1.0]);
+        min_filter_line(&alpha, 1, true, &mut out);
+        assert_eq!(out, [1.0; 5]);
+        box_blur_line(&alpha, 1, &mut out);
+        assert_eq!(out[0], 1.0);
+        assert!((out[2] - 2.0 / 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn min_filter_matches_the_naive_one() {
+        let line = (0..37)
+            .map(|i| ((i * 7919) % 101) as f32 / 100.0)
+            .collect::<Vec<_>>();
+        let mut out = vec![0.0; line.len()];
+        for radius in [0, 1, 2, 5, 36, 64] {
+            for max in [false, true] {
+                min_filter_line(&line, radius, max, &mut out);
+                for (x, &v) in out.iter().enumerate() {
+                    let window = &line[x.saturating_sub(radius)..(x + radius + 1).min(line.len())];
+                    let expected = window
+                        .iter()
+                        .copied()
+                        .reduce(|a, b| if max { a.max(b) } else { a.min(b) })
+                        .unwrap();
+                    assert_eq!(v, expected, "radius {radius} at {x}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn spill_is_pulled_down_to_the_other_channels() {
+        let mut c = [0.5, 0.9, 0.3, 1.0];
+        suppress_spill(Vec3::new(0.0, 1.0, 0.0), 1.0, &mut c);
+        assert_eq!(c, [0.5, 0.5, 0.3, 1.0]);
+    }
+
+    #[test]
+    fn selections_key_their_colors() {
+        let volume = ColorVolume::Box {
+            min: Vec3::new(0.0, 0.5, 0.0),
+            max: Vec3::new(0.5, 1.0, 0.5),
+        };
+        let src = [[0.1, 0.9, 0.2, 1.0], [0.9, 0.2, 0.1, 1.0]];
+        let selection = Some((&volume, Layout::Rgb));
+        let alpha = src
+            .iter()
+            .map(|c| key_alpha(&KeyVolume::Selection, selection, c))
+            .collect::<Vec<_>>();
+        assert_eq!(alpha, vec![0.0, 1.0]);
+
+        let keyer = Keyer {
+            key: Some(KeyVolume::Selection),
+            ..Default::default()
+        };
+        let mut job = KeyJob::default();
+        job.start(&keyer, 2, 1, 1.0);
+        let mut dst = src;
+        while !job.run(
+            &keyer,
+            selection,
+            &src,
+            &mut dst,
+            1,
+            &FrameBudget::default(),
+        ) {}
+        assert_eq!(job.key_color, Some(Vec3::new(0.1, 0.9, 0.2)));
+    }
+
+    #[test]
+    fn keyed_pixels_are_premultiplied_or_composited() {
+        let src = [[0.0, 1.0, 0.0, 1.0], [1.0, 0.0, 0.0, 1.0]];
+        let mut keyer = Keyer {
+            key: Some(KeyVolume::Region(green_screen())),
+            matte: MatteSettings {
+                spill: 0.0,
+                choke: 0.0,
+                softness: 0.0,
+                premultiply: true,
+            },
+            background: KeyBackground::None,
+        };
+        let mut dst = src;
+        key(&keyer, &src, &mut dst, 2);
+        assert_eq!(dst, [[0.0, 0.0, 0.0, 0.0], [1.0, 0.0, 0.0, 1.0]]);
+        assert!(keyer.is_premultiplied());
+
+        keyer.background = KeyBackground::Color(Vec3::new(0.0, 0.0, 1.0));
+        let mut dst = src;
+        key(&keyer, &src, &mut dst, 2);
+        assert_eq!(dst, [[0.0, 0.0, 1.0, 1.0], [1.0, 0.0, 0.0, 1.0]]);
+    }
+
+    #[test]
+    fn matte_is_choked_across_frames() {
+        // A keyed column in a 4x3 image, dilated by one pixel
+        let keyer = Keyer {
+            key: Some(KeyVolume::Region(green_screen())),
+            matte: MatteSettings {
+                spill: 0.0,
+                choke: -1.0,
+                softness: 0.0,
+                premultiply: false,
+            },
+            background: KeyBackground::None,
+        };
+        let green = [0.0, 1.0, 0.0, 1.0];
+        let red = [1.0, 0.0, 0.0, 1.0];
+        let src = [red, green, red, red].repeat(3);
+        let mut dst = src.clone();
+        key(&keyer, &src, &mut dst, 4);
+        let alpha = dst.iter().map(|c| c[3]).collect::<Vec<_>>();
+        assert_eq!(alpha, [1.0, 1.0, 1.0, 1.0].repeat(3));
+
+        let keyer = Keyer {
+            matte: MatteSettings {
+                choke: 1.0,
+                ..keyer.matte
+            },
+            ..keyer
+        };
+        let mut dst = src.clone();
+        key(&keyer, &src, &mut dst, 4);
+        let alpha = dst.iter().map(|c| c[3]).collect::<Vec<_>>();
+        assert_eq!(alpha, [0.0, 0.0, 0.0, 1.0].repeat(3));
+    }
+}
@@ -0,0 +1,252 @@
+use bevy::prelude::*;
+
+use crate::clipping::Clipped;
+use crate::color_cube::{ColorCube, CubeSpace, MAX_RESOLUTION};
+use crate::image::ColorTransformation;
+use crate::kernels::Pixel;
+use crate::layout::Layout;
+use crate::qualifier::{self, Qualifier, QualifierRegion};
+use crate::render::{InstanceData, InstancedMesh};
+
+const DEFAULT_RESOLUTION: u32 = 9;
+/// Size of a lattice point, relative to the lattice spacing.
+const POINT_SCALE: f32 = 0.2;
+
+/// How the warped lattice is drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LatticeStyle {
+    Hidden,
+    /// A point at every lattice node.
+    Points,
+    /// Lines between neighbouring lattice nodes.
+    Grid,
+}
+
+impl LatticeStyle {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "off" => Some(LatticeStyle::Hidden),
+            "points" => Some(LatticeStyle::Points),
+            "grid" => Some(LatticeStyle::Grid),
+            _ => None,
+        }
+    }
+}
+
+/*
+ * Settings for showing the color transformation on a lattice of colors
+ */
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LatticeSettings {
+    pub style: LatticeStyle,
+    /// Number of nodes along each axis
+    pub resolution: u32,
+}
+
+impl Default for LatticeSettings {
+    fn default() -> Self {
+        LatticeSettings {
+            style: LatticeStyle::Hidden,
+            resolution: DEFAULT_RESOLUTION,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct LatticePoints;
+
+#[derive(Component)]
+pub struct LatticeGrid;
+
+#[derive(Clone, Debug)]
+pub struct SetLatticeEvent {
+    pub settings: LatticeSettings,
+}
+
+/// Evenly spaced colors spanning the unit cube, `resolution` along each axis,
+/// in the order of `lattice_index`.
+pub fn lattice(resolution: u32) -> Vec<Pixel> {
+    let r = resolution.max(2);
+    let step = 1.0 / (r - 1) as f32;
+    let mut colors = Vec::with_capacity((r * r * r) as usize);
+    for x in 0..r {
+        for y in 0..r {
+            for z in 0..r {
+                colors.push([x as f32 * step, y as f32 * step, z as f32 * step, 1.0]);
+            }
+        }
+    }
+    colors
+}
+
+pub fn lattice_index(x: u32, y: u32, z: u32, resolution: u32) -> usize {
+    ((x * resolution + y) * resolution + z) as usize
+}
+
+/// Pairs of neighbouring lattice nodes, each pair once.
+pub fn grid_edges(resolution: u32) -> Vec<(usize, usize)> {
+    let r = resolution.max(2);
+    let mut edges = vec![];
+    for x in 0..r {
+        for y in 0..r {
+            for z in 0..r {
+                let i = lattice_index(x, y, z, r);
+                if x + 1 < r {
+                    edges.push((i, lattice_index(x + 1, y, z, r)));
+                }
+                if y + 1 < r {
+                    edges.push((i, lattice_index(x, y + 1, z, r)));
+                }
+                if z + 1 < r {
+                    edges.push((i, lattice_index(x, y, z + 1, r)));
+                }
+            }
+        }
+    }
+    edges
+}
+
+pub fn create_lattice(
+    commands: &mut Commands,
+    mesh: Handle<Mesh>,
+    segment_mesh: Handle<Mesh>,
+    transform: Transform,
+) {
+    commands.spawn_bundle((
+        transform,
+        GlobalTransform::identity(),
+        mesh,
+        InstancedMesh(vec![]),
+        LatticePoints,
+        CubeSpace,
+        Clipped,
+        Visibility { is_visible: false },
+        ComputedVisibility::default(),
+    ));
+    commands.spawn_bundle((
+        transform,
+        GlobalTransform::identity(),
+        segment_mesh,
+        InstancedMesh(vec![]),
+        LatticeGrid,
+        CubeSpace,
+        Clipped,
+        Visibility { is_visible: false },
+        ComputedVisibility::default(),
+    ));
+}
+
+pub fn set_lattice(
+    mut events: EventReader<SetLatticeEvent>,
+    mut settings: ResMut<LatticeSettings>,
+    mut points_query: Query<&mut Visibility, (With<LatticePoints>, Without<LatticeGrid>)>,
+    mut grid_query: Query<&mut Visibility, (With<LatticeGrid>, Without<LatticePoints>)>,
+) {
+    let evts = events.iter().collect::<Vec<_>>();
+    if let Some(evt) = evts.into_iter().last() {
+        *settings = LatticeSettings {
+            resolution: evt.settings.resolution.clamp(2, MAX_RESOLUTION),
+            ..evt.settings
+        };
+        for mut visibility in points_query.iter_mut() {
+            visibility.is_visible = settings.style == LatticeStyle::Points;
+        }
+        for mut visibility in grid_query.iter_mut() {
+            visibility.is_visible = settings.style == LatticeStyle::Grid;
+        }
+    }
+}
+
+fn rgb(c: &Pixel) -> Vec3 {
+    Vec3::new(c[0], c[1], c[2])
+}
+
+/// Warps the lattice with the color transformation, through the same kernel
+/// and qualifier as the image. Grid lines are straight between the warped
+/// nodes, also in the layouts that would bend them.
+#[allow(clippy::type_complexity)]
+pub fn update_lattice(
+    mut last: Local<Option<(Quat, Option<QualifierRegion>, Layout, LatticeSettings)>>,
+    settings: Res<LatticeSettings>,
+    xform_query: Query<(&ColorTransformation, &Qualifier)>,
+    cube_query: Query<&ColorCube>,
+    mut points_query: Query<&mut InstancedMesh, (With<LatticePoints>, Without<LatticeGrid>)>,
+    mut grid_query: Query<&mut InstancedMesh, (With<LatticeGrid>, Without<LatticePoints>)>,
+) {
+    if settings.style == LatticeStyle::Hidden {
+        return;
+    }
+    if let Some((xform, qualifier)) = xform_query.iter().last() {
+        if let Some(cube) = cube_query.iter().last() {
+            let state = Some((xform.rotation, qualifier.region, cube.layout, *settings));
+            if *last == state {
+                return;
+            }
+            *last = state;
+
+            let r = settings.resolution;
+            let src = lattice(r);
+            let mut dst = vec![[0.0; 4]; src.len()];
+            let rotation = Mat3::from_quat(xform.rotation);
+            qualifier::transform(qualifier.region.as_ref(), &rotation, &src, &mut dst);
+            let layout = cube.layout;
+            let positions = dst
+                .iter()
+                .map(|c| layout.position(layout.coords(rgb(c))))
+                .collect::<Vec<_>>();
+            let color = |c: Vec3| Color::rgb(c.x, c.y, c.z).as_rgba_f32();
+
+            if let Some(mut mesh) = points_query.iter_mut().last() {
+                let scale = Vec3::splat(POINT_SCALE / (r - 1) as f32);
+                mesh.0 = src
+                    .iter()
+                    .zip(positions.iter())
+                    .map(|(c, &position)| InstanceData {
+                        position,
+                        scale,
+                        color: color(rgb(c)),
+                    })
+                    .collect();
+            }
+
+            if let Some(mut mesh) = grid_query.iter_mut().last() {
+                mesh.0 = grid_edges(r)
+                    .into_iter()
+                    .map(|(a, b)| InstanceData {
+                        position: positions[a],
+                        scale: positions[b] - positions[a],
+                        color: color((rgb(&src[a]) + rgb(&src[b])) / 2.0),
+                    })
+                    .collect();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lattice_spans_the_unit_cube() {
+        let colors = lattice(3);
+        assert_eq!(colors.len(), 27);
+        assert_eq!(colors[lattice_index(0, 0, 0, 3)], [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(colors[lattice_index(1, 2, 0, 3)], [0.5, 1.0, 0.0, 1.0]);
+        assert_eq!(colors[lattice_index(2, 2, 2, 3)], [1.0, 1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn grid_edges_connect_neighbours_once() {
+        let r = 4;
+        let colors = lattice(r);
+        let edges = grid_edges(r);
+        assert_eq!(edges.len(), (3 * r * r * (r - 1)) as usize);
+        let step = 1.0 / (r - 1) as f32;
+        for (a, b) in edges {
+            assert!(a < b);
+            let d = (rgb(&colors[b]) - rgb(&colors[a])).length();
+            assert!((d - step).abs() < 1e-6);
+        }
+    }
+}
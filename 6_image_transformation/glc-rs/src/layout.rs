@@ -0,0 +1,418 @@
+//! Color spaces the color cube can lay out its bins in.
+//!
+//! A layout maps non-linear sRGB colors to coordinates in the unit cube,
+//! which are binned as described in the `binning` module, and places those
+//! coordinates in the scene. HSV and HSL are drawn as a cylinder and a double
+//! cone around the vertical axis. CIELAB and OKLab use the same scale on all
+//! three axes, so that distances in the view are perceptual distances.
+
+use std::borrow::Cow;
+use std::f32::consts::TAU;
+
+use bevy::math::{Mat3, Vec3};
+
+use crate::kernels::Pixel;
+
+/// Extent of each CIELAB axis in the unit cube, wide enough for the a and b
+/// of every sRGB color.
+const LAB_EXTENT: f32 = 220.0;
+/// Extent of each OKLab axis in the unit cube, set by the lightness.
+const OKLAB_EXTENT: f32 = 1.0;
+
+/// CIE XYZ of the D65 white point.
+const D65: [f32; 3] = [0.950_47, 1.0, 1.088_83];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// The sRGB unit cube.
+    #[default]
+    Rgb,
+    /// Hue around, saturation out from and value up the vertical axis.
+    Hsv,
+    /// Hue around, saturation out from and lightness up the vertical axis.
+    Hsl,
+    /// CIELAB (D65), with lightness up the vertical axis.
+    Lab,
+    /// OKLab, with lightness up the vertical axis.
+    Oklab,
+}
+
+impl Layout {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rgb" => Some(Layout::Rgb),
+            "hsv" => Some(Layout::Hsv),
+            "hsl" => Some(Layout::Hsl),
+            "lab" => Some(Layout::Lab),
+            "oklab" => Some(Layout::Oklab),
+            _ => None,
+        }
+    }
+
+    /// Whether the first coordinate is a hue, which wraps around.
+    pub fn wraps_hue(self) -> bool {
+        matches!(self, Layout::Hsv | Layout::Hsl)
+    }
+
+    /// Normalized coordinates of a color, in the layout's own space.
+    pub fn coords(self, rgb: Vec3) -> Vec3 {
+        match self {
+            Layout::Rgb => rgb,
+            Layout::Hsv => rgb_to_hsv(rgb),
+            Layout::Hsl => rgb_to_hsl(rgb),
+            Layout::Lab => {
+                let lab = rgb_to_lab(rgb);
+                Vec3::new(
+                    lab.x / LAB_EXTENT,
+                    0.5 + lab.y / LAB_EXTENT,
+                    0.5 + lab.z / LAB_EXTENT,
+                )
+            }
+            Layout::Oklab => {
+                let lab = rgb_to_oklab(rgb);
+                Vec3::new(
+                    lab.x / OKLAB_EXTENT,
+                    0.5 + lab.y / OKLAB_EXTENT,
+                    0.5 + lab.z / OKLAB_EXTENT,
+                )
+            }
+        }
+    }
+
+    /// Color at normalized coordinates. Not every coordinate is a displayable
+    /// color, so the result may be out of the unit range.
+    pub fn to_rgb(self, coords: Vec3) -> Vec3 {
+        match self {
+            Layout::Rgb => coords,
+            Layout::Hsv => hsv_to_rgb(coords),
+            Layout::Hsl => hsl_to_rgb(coords),
+            Layout::Lab => lab_to_rgb(Vec3::new(
+                coords.x * LAB_EXTENT,
+                (coords.y - 0.5) * LAB_EXTENT,
+                (coords.z - 0.5) * LAB_EXTENT,
+            )),
+            Layout::Oklab => oklab_to_rgb(Vec3::new(
+                coords.x * OKLAB_EXTENT,
+                (coords.y - 0.5) * OKLAB_EXTENT,
+                (coords.z - 0.5) * OKLAB_EXTENT,
+            )),
+        }
+    }
+
+    /// Where normalized coordinates are drawn in the unit cube.
+    pub fn position(self, coords: Vec3) -> Vec3 {
+        match self {
+            Layout::Rgb => coords,
+            Layout::Hsv => polar(coords.x, 0.5 * coords.y, coords.z),
+            Layout::Hsl => {
+                let radius = 0.5 * coords.y * (1.0 - (2.0 * coords.z - 1.0).abs());
+                polar(coords.x, radius, coords.z)
+            }
+            Layout::Lab => {
+                // Lightness only spans part of the extent, so center it vertically
+                let offset = 0.5 - 50.0 / LAB_EXTENT;
+                Vec3::new(coords.y, coords.x + offset, coords.z)
+            }
+            Layout::Oklab => {
+                let offset = 0.5 - 0.5 / OKLAB_EXTENT;
+                Vec3::new(coords.y, coords.x + offset, coords.z)
+            }
+        }
+    }
+
+    /// Converts pixels to the layout's coordinates, keeping alpha. The RGB
+    /// layout borrows the pixels as they are.
+    pub fn convert<'a>(self, src: &'a [Pixel]) -> Cow<'a, [Pixel]> {
+        match self {
+            Layout::Rgb => Cow::Borrowed(src),
+            _ => Cow::Owned(src.iter().map(|c| self.convert_pixel(c)).collect()),
+        }
+    }
+
+    pub fn convert_pixel(self, c: &Pixel) -> Pixel {
+        let coords = self.coords(Vec3::new(c[0], c[1], c[2]));
+        [coords.x, coords.y, coords.z, c[3]]
+    }
+}
+
+/// Point at angle `turns` and `radius` around the vertical axis of the unit cube.
+fn polar(turns: f32, radius: f32, height: f32) -> Vec3 {
+    let (sin, cos) = (turns * TAU).sin_cos();
+    Vec3::new(0.5 + radius * cos, height, 0.5 + radius * sin)
+}
+
+/// Hue in turns, chroma, and the largest and smallest channel.
+fn hue_chroma(rgb: Vec3) -> (f32, f32, f32, f32) {
+    let max = rgb.max_element();
+    let min = rgb.min_element();
+    let chroma = max - min;
+    let hue = if chroma <= 0.0 {
+        0.0
+    } else if max == rgb.x {
+        ((rgb.y - rgb.z) / chroma).rem_euclid(6.0)
+    } else if max == rgb.y {
+        (rgb.z - rgb.x) / chroma + 2.0
+    } else {
+        (rgb.x - rgb.y) / chroma + 4.0
+    };
+    (hue / 6.0, chroma, max, min)
+}
+
+/// Color with the given hue in turns, chroma and smallest channel.
+fn from_hue_chroma(hue: f32, chroma: f32, min: f32) -> Vec3 {
+    let h = hue.rem_euclid(1.0) * 6.0;
+    let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+    let rgb = match h as u32 {
+        0 => Vec3::new(chroma, x, 0.0),
+        1 => Vec3::new(x, chroma, 0.0),
+        2 => Vec3::new(0.0, chroma, x),
+        3 => Vec3::new(0.0, x, chroma),
+        4 => Vec3::new(x, 0.0, chroma),
+        _ => Vec3::new(chroma, 0.0, x),
+    };
+    rgb + Vec3::splat(min)
+}
+
+pub fn rgb_to_hsv(rgb: Vec3) -> Vec3 {
+    let (hue, chroma, max, _) = hue_chroma(rgb);
+    let saturation = if max > 0.0 { chroma / max } else { 0.0 };
+    Vec3::new(hue, saturation, max)
+}
+
+pub fn hsv_to_rgb(hsv: Vec3) -> Vec3 {
+    let chroma = hsv.z * hsv.y;
+    from_hue_chroma(hsv.x, chroma, hsv.z - chroma)
+}
+
+pub fn rgb_to_hsl(rgb: Vec3) -> Vec3 {
+    let (hue, chroma, max, min) = hue_chroma(rgb);
+    let lightness = (max + min) / 2.0;
+    let denominator = 1.0 - (2.0 * lightness - 1.0).abs();
+    let saturation = if denominator > 0.0 {
+        chroma / denominator
+    } else {
+        0.0
+    };
+    Vec3::new(hue, saturation, lightness)
+}
+
+pub fn hsl_to_rgb(hsl: Vec3) -> Vec3 {
+    let chroma = (1.0 - (2.0 * hsl.z - 1.0).abs()) * hsl.y;
+    from_hue_chroma(hsl.x, chroma, hsl.z - chroma / 2.0)
+}
+
+fn srgb_to_linear(x: f32) -> f32 {
+    if x <= 0.040_45 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(x: f32) -> f32 {
+    if x <= 0.003_130_8 {
+        x * 12.92
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub fn to_linear(rgb: Vec3) -> Vec3 {
+    Vec3::new(
+        srgb_to_linear(rgb.x),
+        srgb_to_linear(rgb.y),
+        srgb_to_linear(rgb.z),
+    )
+}
+
+pub fn from_linear(rgb: Vec3) -> Vec3 {
+    Vec3::new(
+        linear_to_srgb(rgb.x),
+        linear_to_srgb(rgb.y),
+        linear_to_srgb(rgb.z),
+    )
+}
+
+/// Matrix from rows, which is how color space matrices are usually written.
+fn rows(m: [[f32; 3]; 3]) -> Mat3 {
+    Mat3::from_cols_array_2d(&m).transpose()
+}
+
+/// Linear sRGB to CIE XYZ, both relative to D65.
+pub fn srgb_to_xyz() -> Mat3 {
+    rows([
+        [0.412_456_4, 0.357_576_1, 0.180_437_5],
+        [0.212_672_9, 0.715_152_2, 0.072_175],
+        [0.019_333_9, 0.119_192, 0.950_304_1],
+    ])
+}
+
+fn xyz_to_srgb() -> Mat3 {
+    rows([
+        [3.240_454_2, -1.537_138_5, -0.498_531_4],
+        [-0.969_266, 1.876_010_8, 0.041_556],
+        [0.055_643_4, -0.204_025_9, 1.057_225_2],
+    ])
+}
+
+fn lab_f(t: f32) -> f32 {
+    const DELTA: f32 = 6.0 / 29.0;
+    if t > DELTA * DELTA * DELTA {
+        t.cbrt()
+    } else {
+        t / (3.0 * DELTA * DELTA) + 4.0 / 29.0
+    }
+}
+
+fn lab_f_inv(t: f32) -> f32 {
+    const DELTA: f32 = 6.0 / 29.0;
+    if t > DELTA {
+        t * t * t
+    } else {
+        3.0 * DELTA * DELTA * (t - 4.0 / 29.0)
+    }
+}
+
+/// CIE XYZ (D65) to CIELAB, with L in [0, 100].
+pub fn xyz_to_lab(xyz: Vec3) -> Vec3 {
+    let xyz = xyz / Vec3::from(D65);
+    let (fx, fy, fz) = (lab_f(xyz.x), lab_f(xyz.y), lab_f(xyz.z));
+    Vec3::new(116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+}
+
+pub fn lab_to_xyz(lab: Vec3) -> Vec3 {
+    let fy = (lab.x + 16.0) / 116.0;
+    let fx = fy + lab.y / 500.0;
+    let fz = fy - lab.z / 200.0;
+    Vec3::new(lab_f_inv(fx), lab_f_inv(fy), lab_f_inv(fz)) * Vec3::from(D65)
+}
+
+pub fn rgb_to_lab(rgb: Vec3) -> Vec3 {
+    xyz_to_lab(srgb_to_xyz() * to_linear(rgb))
+}
+
+pub fn lab_to_rgb(lab: Vec3) -> Vec3 {
+    from_linear(xyz_to_srgb() * lab_to_xyz(lab))
+}
+
+pub fn rgb_to_oklab(rgb: Vec3) -> Vec3 {
+    let lms = rows([
+        [0.412_221_47, 0.536_332_55, 0.051_445_995],
+        [0.211_903_5, 0.680_699_5, 0.107_396_96],
+        [0.088_302_46, 0.281_718_85, 0.629_978_7],
+    ]) * to_linear(rgb);
+    let lms = Vec3::new(lms.x.cbrt(), lms.y.cbrt(), lms.z.cbrt());
+    rows([
+        [0.210_454_26, 0.793_617_8, -0.004_072_047],
+        [1.977_998_5, -2.428_592_2, 0.450_593_7],
+        [0.025_904_037, 0.782_771_77, -0.808_675_77],
+    ]) * lms
+}
+
+pub fn oklab_to_rgb(lab: Vec3) -> Vec3 {
+    let lms = rows([
+        [1.0, 0.396_337_78, 0.215_803_76],
+        [1.0, -0.105_561_346, -0.063_854_17],
+        [1.0, -0.089_484_18, -1.291_485_5],
+    ]) * lab;
+    let lms = lms * lms * lms;
+    from_linear(
+        rows([
+            [4.076_741_7, -3.307_711_6, 0.230_969_94],
+            [-1.268_438, 2.609_757_4, -0.341_319_38],
+            [-0.004_196_086_3, -0.703_418_6, 1.707_614_7],
+        ]) * lms,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LAYOUTS: [Layout; 5] = [
+        Layout::Rgb,
+        Layout::Hsv,
+        Layout::Hsl,
+        Layout::Lab,
+        Layout::Oklab,
+    ];
+
+    fn test_colors() -> Vec<Vec3> {
+        let steps = [0.0, 0.1, 0.35, 0.5, 0.8, 1.0];
+        let mut colors = vec![];
+        for r in steps {
+            for g in steps {
+                for b in steps {
+                    colors.push(Vec3::new(r, g, b));
+                }
+            }
+        }
+        colors
+    }
+
+    fn assert_near(a: Vec3, b: Vec3, eps: f32) {
+        assert!((a - b).abs().max_element() < eps, "{a} != {b}");
+    }
+
+    #[test]
+    fn coords_round_trip() {
+        for layout in LAYOUTS {
+            for rgb in test_colors() {
+                assert_near(layout.to_rgb(layout.coords(rgb)), rgb, 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn srgb_colors_are_inside_the_unit_cube() {
+        for layout in LAYOUTS {
+            for rgb in test_colors() {
+                let coords = layout.coords(rgb);
+                assert!(
+                    coords.cmpge(Vec3::splat(-1e-4)).all(),
+                    "{layout:?} {coords}"
+                );
+                assert!(
+                    coords.cmple(Vec3::splat(1.0 + 1e-4)).all(),
+                    "{layout:?} {coords}"
+                );
+                let position = layout.position(coords);
+                assert!(
+                    position.cmpge(Vec3::splat(-1e-4)).all(),
+                    "{layout:?} {position}"
+                );
+                assert!(
+                    position.cmple(Vec3::splat(1.0 + 1e-4)).all(),
+                    "{layout:?} {position}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn white_and_black_lie_on_the_vertical_axis() {
+        for layout in LAYOUTS.into_iter().skip(1) {
+            let black = layout.position(layout.coords(Vec3::ZERO));
+            let white = layout.position(layout.coords(Vec3::ONE));
+            assert_near(
+                black * Vec3::new(1.0, 0.0, 1.0),
+                Vec3::new(0.5, 0.0, 0.5),
+                1e-3,
+            );
+            assert_near(
+                white * Vec3::new(1.0, 0.0, 1.0),
+                Vec3::new(0.5, 0.0, 0.5),
+                1e-3,
+            );
+            assert!(white.y > black.y);
+        }
+    }
+
+    #[test]
+    fn lab_reference_values() {
+        assert_near(rgb_to_lab(Vec3::ONE), Vec3::new(100.0, 0.0, 0.0), 1e-2);
+        assert_near(rgb_to_lab(Vec3::X), Vec3::new(53.24, 80.09, 67.20), 5e-2);
+        assert_near(rgb_to_oklab(Vec3::ONE), Vec3::new(1.0, 0.0, 0.0), 1e-3);
+        assert_near(rgb_to_oklab(Vec3::X), Vec3::new(0.628, 0.225, 0.126), 1e-3);
+    }
+}
@@ -1,16 +1,51 @@
+mod binning;
 mod camera;
+mod clipping;
 mod color_cube;
+mod comparison;
+mod gamut;
+mod gizmo;
+mod hull;
 mod image;
+mod isosurface;
+mod kernels;
+mod keyer;
+mod lattice;
+mod layout;
+mod normalization;
+mod parallel;
+mod picking;
+mod point_cloud;
+mod probe;
+mod processing;
+mod proxy;
+mod qualifier;
 mod render;
+mod resample;
 mod scene;
+mod selection;
+mod smoothing;
 mod utils;
 
-use bevy::ecs::event::Events;
+use bevy::ecs::event::{Events, ManualEventReader};
 use bevy::prelude::*;
+use bevy::utils::Duration;
 use itertools::Itertools;
 use wasm_bindgen::{prelude::*, JsCast};
 use web_sys::ImageData;
 
+// With the `threads` feature on wasm, JavaScript starts the Web Worker pool
+// by awaiting `startThreadPool(n)` before calling `Glc::set_thread_count`,
+// which keeps to one thread if the pool failed to start.
+#[cfg(all(feature = "threads", target_arch = "wasm32"))]
+#[wasm_bindgen(js_name = startThreadPool)]
+pub fn start_thread_pool(num_threads: usize) -> js_sys::Promise {
+    let started = Closure::once(|_: JsValue| parallel::set_pool_started());
+    let promise = wasm_bindgen_rayon::init_thread_pool(num_threads).then(&started);
+    started.forget();
+    promise
+}
+
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
 // allocator.
 #[cfg(feature = "wee_alloc")]
@@ -28,8 +63,38 @@ pub struct Glc {
     app: App,
     camera_events: Vec<camera::CameraMoveEvent>,
     image_events: Vec<image::SetInputImageEvent>,
+    region_events: Vec<image::SetInputRegionEvent>,
     xform_events: Vec<image::SetColorTransformationEvent>,
+    normalization_events: Vec<color_cube::SetNormalizationEvent>,
+    cube_resolution_events: Vec<color_cube::SetCubeResolutionEvent>,
+    cube_size_events: Vec<color_cube::SetCubeSizeEvent>,
+    bin_position_events: Vec<color_cube::SetBinPositionEvent>,
+    bin_color_events: Vec<color_cube::SetBinColorEvent>,
+    smoothing_events: Vec<color_cube::SetSmoothingEvent>,
+    layout_events: Vec<color_cube::SetLayoutEvent>,
+    view_mode_events: Vec<point_cloud::SetViewModeEvent>,
+    point_sampling_events: Vec<point_cloud::SetPointSamplingEvent>,
+    isosurface_events: Vec<isosurface::SetIsosurfaceLevelsEvent>,
+    comparison_events: Vec<comparison::SetComparisonEvent>,
+    lattice_events: Vec<lattice::SetLatticeEvent>,
+    gizmo_events: Vec<gizmo::SetGizmoEvent>,
+    clip_plane_events: Vec<clipping::SetClipPlanesEvent>,
+    slice_events: Vec<clipping::SetSliceEvent>,
+    hull_events: Vec<hull::SetGamutHullEvent>,
+    reference_gamut_events: Vec<gamut::SetReferenceGamutEvent>,
+    selection_events: Vec<selection::SetSelectionEvent>,
+    selection_source_events: Vec<selection::SetSelectionSourceEvent>,
+    qualifier_events: Vec<qualifier::SetQualifierEvent>,
+    matte_events: Vec<qualifier::SetShowMatteEvent>,
+    key_events: Vec<keyer::SetKeyEvent>,
+    key_matte_events: Vec<keyer::SetKeyMatteEvent>,
+    key_background_events: Vec<keyer::SetKeyBackgroundEvent>,
     output_events: Vec<image::SetOutputCanvasEvent>,
+    budget_events: Vec<processing::SetTimeBudgetEvent>,
+    proxy_size_events: Vec<proxy::SetProxyMaxPixelsEvent>,
+    quality_events: Vec<proxy::SetForceFullQualityEvent>,
+    progress_reader: ManualEventReader<processing::ProcessingProgressEvent>,
+    progress_callback: Option<js_sys::Function>,
 }
 
 #[wasm_bindgen]
@@ -56,20 +121,112 @@ impl Glc {
         })
         .add_plugins(DefaultPlugins)
         .add_plugin(render::GlcRenderingPlugin)
+        .init_resource::<processing::ProcessingSettings>()
+        .init_resource::<processing::FrameBudget>()
+        .init_resource::<proxy::ProxySettings>()
+        .init_resource::<proxy::ProxyState>()
+        .init_resource::<comparison::ComparisonSettings>()
+        .init_resource::<lattice::LatticeSettings>()
+        .init_resource::<gizmo::GizmoSettings>()
+        .init_resource::<clipping::Clipping>()
+        .init_resource::<hull::HullSettings>()
+        .init_resource::<hull::HullMetrics>()
+        .init_resource::<isosurface::IsosurfaceSettings>()
+        .init_resource::<picking::HoveredBin>()
+        .init_resource::<picking::BinIndices>()
+        .init_resource::<probe::ProbedColors>()
         .add_event::<camera::CameraMoveEvent>()
         .add_event::<image::SetInputImageEvent>()
+        .add_event::<image::SetInputRegionEvent>()
+        .add_event::<image::InputChangedEvent>()
         .add_event::<image::SetColorTransformationEvent>()
         .add_event::<image::SetOutputCanvasEvent>()
         .add_event::<color_cube::UpdateColorCubeEvent>()
+        .add_event::<color_cube::UpdateColorCubeRegionEvent>()
+        .add_event::<color_cube::HistogramUpdatedEvent>()
+        .add_event::<color_cube::SetNormalizationEvent>()
+        .add_event::<color_cube::SetCubeResolutionEvent>()
+        .add_event::<color_cube::SetCubeSizeEvent>()
+        .add_event::<color_cube::SetBinPositionEvent>()
+        .add_event::<color_cube::SetBinColorEvent>()
+        .add_event::<color_cube::SetSmoothingEvent>()
+        .add_event::<color_cube::SetLayoutEvent>()
+        .add_event::<point_cloud::SetViewModeEvent>()
+        .add_event::<point_cloud::SetPointSamplingEvent>()
+        .add_event::<isosurface::SetIsosurfaceLevelsEvent>()
+        .add_event::<comparison::SetComparisonEvent>()
+        .add_event::<lattice::SetLatticeEvent>()
+        .add_event::<gizmo::SetGizmoEvent>()
+        .add_event::<clipping::SetClipPlanesEvent>()
+        .add_event::<clipping::SetSliceEvent>()
+        .add_event::<hull::SetGamutHullEvent>()
+        .add_event::<gamut::SetReferenceGamutEvent>()
+        .add_event::<selection::SetSelectionEvent>()
+        .add_event::<selection::SetSelectionSourceEvent>()
+        .add_event::<qualifier::SetQualifierEvent>()
+        .add_event::<qualifier::SetShowMatteEvent>()
+        .add_event::<keyer::SetKeyEvent>()
+        .add_event::<keyer::SetKeyMatteEvent>()
+        .add_event::<keyer::SetKeyBackgroundEvent>()
+        .add_event::<keyer::KeyImageEvent>()
         .add_event::<image::TransformImageEvent>()
+        .add_event::<image::TransformRegionEvent>()
+        .add_event::<image::TransformStartedEvent>()
         .add_event::<image::RenderRequest>()
+        .add_event::<processing::SetTimeBudgetEvent>()
+        .add_event::<processing::ProcessingProgressEvent>()
+        .add_event::<proxy::SetProxyMaxPixelsEvent>()
+        .add_event::<proxy::SetForceFullQualityEvent>()
         .add_startup_system(scene::create_scene)
         .add_startup_system(camera::create_camera)
+        .add_system_to_stage(CoreStage::PreUpdate, processing::start_frame_budget)
+        // Spawned before `Update` so the new cube is there when it gets binned
+        .add_system_to_stage(CoreStage::PreUpdate, color_cube::set_cube_resolution)
         .add_system(camera::move_camera)
+        .add_system(processing::set_time_budget)
+        .add_system(proxy::set_proxy_settings)
+        .add_system(proxy::update_proxy)
+        .add_system(proxy::refine_full_resolution)
         .add_system(image::set_input_image)
+        .add_system(image::set_input_region)
         .add_system(image::set_color_transformation)
+        .add_system(qualifier::set_qualifier)
+        .add_system(keyer::set_keyer)
+        .add_system(keyer::key_image)
         .add_system(image::set_output_canvas)
-        .add_system(color_cube::update_color_cube)
+        .add_system(color_cube::update_color_cube.label("update_color_cube"))
+        .add_system(color_cube::set_normalization)
+        .add_system(color_cube::set_cube_size)
+        .add_system(color_cube::set_bin_position)
+        .add_system(color_cube::set_bin_color)
+        .add_system(color_cube::set_smoothing)
+        .add_system(color_cube::set_layout)
+        // Right after the rebinned histogram is laid out, so that its final
+        // positions are never drawn before the animation starts
+        .add_system(color_cube::animate_layout.after("update_color_cube"))
+        .add_system(point_cloud::set_view_mode)
+        .add_system(point_cloud::set_point_sampling)
+        .add_system(point_cloud::update_point_cloud)
+        .add_system(isosurface::set_isosurface_levels)
+        .add_system(isosurface::update_isosurfaces)
+        .add_system(comparison::set_comparison)
+        .add_system(comparison::update_comparison)
+        .add_system(lattice::set_lattice)
+        .add_system(lattice::update_lattice)
+        .add_system(gizmo::set_gizmo)
+        .add_system(gizmo::update_gizmo)
+        .add_system(clipping::set_clip_planes)
+        .add_system(clipping::set_slice)
+        .add_system(hull::set_gamut_hull)
+        .add_system(hull::update_gamut_hull)
+        .add_system(gamut::set_reference_gamut)
+        .add_system(gamut::update_gamut_outlines)
+        .add_system(picking::update_highlight)
+        .add_system(picking::refresh_bin_highlight)
+        .add_system(probe::update_probe_marker)
+        .add_system(selection::set_selection)
+        .add_system(selection::update_selection_outline)
+        .add_system(selection::refresh_selection)
         .add_system(image::transform_image)
         .add_system(image::render_image)
         .update();
@@ -78,53 +235,91 @@ impl Glc {
             app,
             camera_events: vec![],
             image_events: vec![],
+            region_events: vec![],
             xform_events: vec![],
+            normalization_events: vec![],
+            cube_resolution_events: vec![],
+            cube_size_events: vec![],
+            bin_position_events: vec![],
+            bin_color_events: vec![],
+            smoothing_events: vec![],
+            layout_events: vec![],
+            view_mode_events: vec![],
+            point_sampling_events: vec![],
+            isosurface_events: vec![],
+            comparison_events: vec![],
+            lattice_events: vec![],
+            gizmo_events: vec![],
+            clip_plane_events: vec![],
+            slice_events: vec![],
+            hull_events: vec![],
+            reference_gamut_events: vec![],
+            selection_events: vec![],
+            selection_source_events: vec![],
+            qualifier_events: vec![],
+            matte_events: vec![],
+            key_events: vec![],
+            key_matte_events: vec![],
+            key_background_events: vec![],
             output_events: vec![],
+            budget_events: vec![],
+            proxy_size_events: vec![],
+            quality_events: vec![],
+            progress_reader: Default::default(),
+            progress_callback: None,
         }
     }
 
     pub fn update(&mut self) {
         self.app.update();
 
-        let mut events = self
-            .app
-            .world
-            .get_resource_mut::<Events<camera::CameraMoveEvent>>()
-            .unwrap();
-        for evt in self.camera_events.iter() {
-            events.send(evt.clone());
-        }
-        self.camera_events.clear();
+        let world = &mut self.app.world;
+        send_events(world, &mut self.camera_events);
+        send_events(world, &mut self.image_events);
+        send_events(world, &mut self.region_events);
+        send_events(world, &mut self.xform_events);
+        send_events(world, &mut self.normalization_events);
+        send_events(world, &mut self.cube_resolution_events);
+        send_events(world, &mut self.cube_size_events);
+        send_events(world, &mut self.bin_position_events);
+        send_events(world, &mut self.bin_color_events);
+        send_events(world, &mut self.smoothing_events);
+        send_events(world, &mut self.layout_events);
+        send_events(world, &mut self.view_mode_events);
+        send_events(world, &mut self.point_sampling_events);
+        send_events(world, &mut self.isosurface_events);
+        send_events(world, &mut self.comparison_events);
+        send_events(world, &mut self.lattice_events);
+        send_events(world, &mut self.gizmo_events);
+        send_events(world, &mut self.clip_plane_events);
+        send_events(world, &mut self.slice_events);
+        send_events(world, &mut self.hull_events);
+        send_events(world, &mut self.reference_gamut_events);
+        send_events(world, &mut self.selection_events);
+        send_events(world, &mut self.selection_source_events);
+        send_events(world, &mut self.qualifier_events);
+        send_events(world, &mut self.matte_events);
+        send_events(world, &mut self.key_events);
+        send_events(world, &mut self.key_matte_events);
+        send_events(world, &mut self.key_background_events);
+        send_events(world, &mut self.output_events);
+        send_events(world, &mut self.budget_events);
+        send_events(world, &mut self.proxy_size_events);
+        send_events(world, &mut self.quality_events);
 
-        let mut events = self
-            .app
-            .world
-            .get_resource_mut::<Events<image::SetInputImageEvent>>()
+        let events = world
+            .get_resource::<Events<processing::ProcessingProgressEvent>>()
             .unwrap();
-        for evt in self.image_events.iter() {
-            events.send(evt.clone());
+        for evt in self.progress_reader.iter(events) {
+            if let Some(callback) = &self.progress_callback {
+                let _ = callback.call3(
+                    &JsValue::NULL,
+                    &JsValue::from_str(evt.stage.name()),
+                    &JsValue::from(evt.done as u32),
+                    &JsValue::from(evt.total as u32),
+                );
+            }
         }
-        self.image_events.clear();
-
-        let mut events = self
-            .app
-            .world
-            .get_resource_mut::<Events<image::SetColorTransformationEvent>>()
-            .unwrap();
-        for evt in self.xform_events.iter() {
-            events.send(evt.clone());
-        }
-        self.xform_events.clear();
-
-        let mut events = self
-            .app
-            .world
-            .get_resource_mut::<Events<image::SetOutputCanvasEvent>>()
-            .unwrap();
-        for evt in self.output_events.iter() {
-            events.send(evt.clone());
-        }
-        self.output_events.clear();
     }
 
     pub fn move_camera(&mut self, rx: f32, ry: f32, z: f32) {
@@ -138,22 +333,21 @@ impl Glc {
         self.image_events.push(image::SetInputImageEvent {
             width: image_data.width(),
             height: image_data.height(),
-            data: image_data
-                .data()
-                .0
-                .iter()
-                .map(|&x| x as f32 / 255.0)
-                .chunks(4)
-                .into_iter()
-                .map(|mut chunk| {
-                    Color::rgba(
-                        chunk.next().unwrap(),
-                        chunk.next().unwrap(),
-                        chunk.next().unwrap(),
-                        chunk.next().unwrap(),
-                    )
-                })
-                .collect(),
+            data: pixels(&image_data),
+        });
+    }
+
+    /// Replaces the part of the input image at (`x`, `y`) covered by `image_data`.
+    /// Only the affected output pixels and histogram bins are recomputed.
+    pub fn set_input_region(&mut self, x: u32, y: u32, image_data: ImageData) {
+        self.region_events.push(image::SetInputRegionEvent {
+            rect: image::PixelRect {
+                x,
+                y,
+                width: image_data.width(),
+                height: image_data.height(),
+            },
+            data: pixels(&image_data),
         });
     }
 
@@ -168,4 +362,506 @@ impl Glc {
             rotation: Quat::from_axis_angle(Vec3::Y, r.to_radians()),
         });
     }
+
+    /// Selects how bin counts are scaled in the color cube: "linear" (`param`
+    /// is the fraction of pixels that fills a bin), "log", "sqrt",
+    /// "percentile" (`param` is the percentile, 0-100) or "auto". Parameters
+    /// out of range are ignored, keeping the current mode.
+    pub fn set_normalization(&mut self, mode: &str, param: f32) {
+        match normalization::Normalization::from_name(mode, param) {
+            Some(normalization) => self
+                .normalization_events
+                .push(color_cube::SetNormalizationEvent { normalization }),
+            None if normalization::Normalization::from_name(mode, 1.0).is_none() => {
+                utils::log(&format!("Unknown normalization mode: {mode}"))
+            }
+            None => utils::log(&format!("Invalid normalization parameter: {param}")),
+        }
+    }
+
+    /// Sets the linear normalization threshold, the fraction of all pixels
+    /// that fills a bin.
+    pub fn set_threshold(&mut self, threshold: f32) {
+        self.set_normalization("linear", threshold);
+    }
+
+    /// Rebuilds the color cube with `rx * ry * rz` bins (1 to 128 per axis).
+    pub fn set_cube_resolution(&mut self, rx: u32, ry: u32, rz: u32) {
+        self.cube_resolution_events
+            .push(color_cube::SetCubeResolutionEvent {
+                resolution: UVec3::new(rx, ry, rz),
+            });
+    }
+
+    pub fn set_cube_size(&mut self, size: f32) {
+        self.cube_size_events
+            .push(color_cube::SetCubeSizeEvent { size });
+    }
+
+    /// Selects where bins are drawn: "center" or "center-of-mass" (the mean
+    /// color of the pixels in the bin).
+    pub fn set_bin_position(&mut self, position: &str) {
+        match binning::BinPosition::from_name(position) {
+            Some(position) => self
+                .bin_position_events
+                .push(color_cube::SetBinPositionEvent { position }),
+            None => utils::log(&format!("Unknown bin position: {position}")),
+        }
+    }
+
+    /// Selects how bins are colored: "lattice" (the color at the bin's
+    /// center), "mean" or "median" (of the pixels in the bin).
+    pub fn set_bin_color(&mut self, color: &str) {
+        match binning::BinColor::from_name(color) {
+            Some(color) => self
+                .bin_color_events
+                .push(color_cube::SetBinColorEvent { color }),
+            None => utils::log(&format!("Unknown bin color: {color}")),
+        }
+    }
+
+    /// Smooths the histogram before the bins are sized: "none", "gaussian" or
+    /// "epanechnikov". `bandwidth` is in bins, the standard deviation of the
+    /// Gaussian and the half width of the Epanechnikov kernel, and has to be
+    /// finite and positive.
+    pub fn set_smoothing(&mut self, kernel: &str, bandwidth: f32) {
+        match smoothing::Smoothing::from_name(kernel, bandwidth) {
+            Some(smoothing) => self
+                .smoothing_events
+                .push(color_cube::SetSmoothingEvent { smoothing }),
+            None if smoothing::Smoothing::from_name(kernel, 1.0).is_none() => {
+                utils::log(&format!("Unknown smoothing kernel: {kernel}"))
+            }
+            None => utils::log(&format!("Invalid smoothing bandwidth: {bandwidth}")),
+        }
+    }
+
+    /// Selects the color space bins are laid out in: "rgb" (cube), "hsv"
+    /// (cylinder), "hsl" (double cone), "lab" or "oklab". The bins move over
+    /// to the new layout in a short animation.
+    pub fn set_layout(&mut self, layout: &str) {
+        match layout::Layout::from_name(layout) {
+            Some(layout) => self
+                .layout_events
+                .push(color_cube::SetLayoutEvent { layout }),
+            None => utils::log(&format!("Unknown layout: {layout}")),
+        }
+    }
+
+    /// Selects what the 3D view shows: "bins" (the histogram), "points" (a
+    /// sample of individual pixels) or "isosurface" (nested surfaces of the
+    /// histogram density).
+    pub fn set_view_mode(&mut self, mode: &str) {
+        match point_cloud::ViewMode::from_name(mode) {
+            Some(mode) => self
+                .view_mode_events
+                .push(point_cloud::SetViewModeEvent { mode }),
+            None => utils::log(&format!("Unknown view mode: {mode}")),
+        }
+    }
+
+    /// Sets how many pixels the point cloud shows and how they are picked:
+    /// "stratified" (spread evenly over the image) or "random".
+    pub fn set_point_sampling(&mut self, sample_size: u32, sampling: &str) {
+        match point_cloud::Sampling::from_name(sampling) {
+            Some(sampling) => self
+                .point_sampling_events
+                .push(point_cloud::SetPointSamplingEvent {
+                    sample_size,
+                    sampling,
+                }),
+            None => utils::log(&format!("Unknown sampling: {sampling}")),
+        }
+    }
+
+    /// Sets the densities, relative to the densest bin and in (0, 1), at which
+    /// the "isosurface" view mode extracts its shells. At most four are kept.
+    pub fn set_isosurface_levels(&mut self, levels: &[f32]) {
+        self.isosurface_events
+            .push(isosurface::SetIsosurfaceLevelsEvent {
+                levels: levels.to_vec(),
+            });
+    }
+
+    /// Overlays the input histogram as translucent bins, and draws vectors
+    /// from each input bin to where the transformation moved its pixels.
+    pub fn set_comparison(&mut self, show_input: bool, show_vectors: bool) {
+        self.comparison_events.push(comparison::SetComparisonEvent {
+            show_input,
+            show_vectors,
+        });
+    }
+
+    /// Shows the color transformation applied to a lattice of `resolution`
+    /// colors per axis (2 to 128), as "points", as a "grid" of lines, or "off".
+    pub fn set_lattice(&mut self, style: &str, resolution: u32) {
+        match lattice::LatticeStyle::from_name(style) {
+            Some(style) => self.lattice_events.push(lattice::SetLatticeEvent {
+                settings: lattice::LatticeSettings { style, resolution },
+            }),
+            None => utils::log(&format!("Unknown lattice style: {style}")),
+        }
+    }
+
+    /// Shows the marks around the color cube: its bounding `frame`, the red,
+    /// green and blue `axes` from black, markers at the `corners` of the sRGB
+    /// cube and `ticks` along the axes.
+    pub fn set_gizmo(&mut self, frame: bool, axes: bool, corners: bool, ticks: bool) {
+        self.gizmo_events.push(gizmo::SetGizmoEvent {
+            settings: gizmo::GizmoSettings {
+                frame,
+                axes,
+                corners,
+                ticks,
+            },
+        });
+    }
+
+    /// Hides the bins outside the given planes, in the cube's unit space where
+    /// RGB colors are their own coordinates. `planes` holds four numbers per
+    /// plane, `nx, ny, nz, offset`, keeping the bins at `p` with
+    /// `dot(n, p) <= offset`. An empty array removes the clipping planes.
+    pub fn set_clip_planes(&mut self, planes: &[f32]) {
+        let planes = planes
+            .chunks_exact(4)
+            .filter_map(|p| {
+                let plane = clipping::ClipPlane::new(Vec3::new(p[0], p[1], p[2]), p[3]);
+                if plane.is_none() {
+                    utils::log("Ignoring clipping plane with a zero normal");
+                }
+                plane
+            })
+            .collect();
+        self.clip_plane_events
+            .push(clipping::SetClipPlanesEvent { planes });
+    }
+
+    /// Only shows the layer of bins at `p` with `min <= dot(n, p) <= max`.
+    /// For example, `set_slice(0, 0, 1, 0.4, 0.5)` shows the bins with blue in
+    /// [0.4, 0.5] in the RGB layout.
+    pub fn set_slice(&mut self, nx: f32, ny: f32, nz: f32, min: f32, max: f32) {
+        match clipping::Slice::new(Vec3::new(nx, ny, nz), min, max) {
+            Some(slice) => self
+                .slice_events
+                .push(clipping::SetSliceEvent { slice: Some(slice) }),
+            None => utils::log("Slice normal must not be zero"),
+        }
+    }
+
+    pub fn clear_slice(&mut self) {
+        self.slice_events
+            .push(clipping::SetSliceEvent { slice: None });
+    }
+
+    /// Draws the convex hull of the output colors in the cube, and computes
+    /// the gamut metrics of both images whenever the output is complete.
+    pub fn set_gamut_hull(&mut self, enabled: bool) {
+        self.hull_events.push(hull::SetGamutHullEvent { enabled });
+    }
+
+    /// Shows or hides the outline of a standard gamut, "srgb", "display-p3",
+    /// "rec2020" or "adobe-rgb", in the cube's layout. Colors of the image
+    /// outside of a gamut's outline would clip on a display with that gamut.
+    pub fn set_reference_gamut(&mut self, gamut: &str, visible: bool) {
+        match gamut::ReferenceGamut::from_name(gamut) {
+            Some(gamut) => self
+                .reference_gamut_events
+                .push(gamut::SetReferenceGamutEvent { gamut, visible }),
+            None => utils::log(&format!("Unknown gamut: {gamut}")),
+        }
+    }
+
+    /// Hull volumes in RGB and Lab, and coverages of sRGB, Display P3 and
+    /// Rec. 2020, of the "input" or "output" colors. `undefined` until the
+    /// hull is enabled and computed.
+    pub fn gamut_metrics(&self, image: &str) -> Option<hull::GamutMetrics> {
+        let metrics = self.app.world.get_resource::<hull::HullMetrics>()?;
+        match image {
+            "input" => metrics.input,
+            "output" => metrics.output,
+            _ => {
+                utils::log(&format!("Unknown image: {image}"));
+                None
+            }
+        }
+    }
+
+    /// Picks the bin of the color cube under the canvas point (`x`, `y`), in
+    /// pixels from its top left corner, and highlights it, dimming the other
+    /// pixels of the output canvas. Returns its index, color range, pixel count
+    /// and percentage, or `undefined` if there is none, which also clears the
+    /// highlight.
+    pub fn pick(&mut self, x: f32, y: f32) -> Option<picking::PickedBin> {
+        picking::pick(&mut self.app.world, x, y)
+    }
+
+    /// Probes the pixel of the output canvas at (`x`, `y`), in pixels of its
+    /// drawing buffer (`canvas.width` by `canvas.height`) from its top left
+    /// corner. Pointer offsets are in CSS pixels, and have to be scaled by
+    /// `canvas.width / canvas.clientWidth` first. Returns its input and output colors, as RGB,
+    /// hex, HSV and Lab, and marks their bins in the color cube with a segment
+    /// from the input color to where it moved. Returns `undefined` outside the
+    /// image, which also clears the marker.
+    pub fn probe(&mut self, x: f32, y: f32) -> Option<probe::ProbedPixel> {
+        probe::probe(&mut self.app.world, x, y)
+    }
+
+    pub fn clear_probe(&mut self) {
+        probe::clear_probe(&mut self.app.world);
+    }
+
+    /// Limits the color transformation to a region of colors: a "box" or
+    /// "ellipsoid" in "rgb" or "hsl" (all components in [0, 1], hue wrapping
+    /// around), given by its `center` and the half sizes or radii in `extent`.
+    /// Pixels outside fade out of the transformation over `softness`, relative
+    /// to the extent. For example, rotating only the greens:
+    /// `set_qualifier("hsl", "box", [0.33, 0.5, 0.5], [0.08, 0.5, 0.5], 0.5)`.
+    pub fn set_qualifier(
+        &mut self,
+        space: &str,
+        shape: &str,
+        center: &[f32],
+        extent: &[f32],
+        softness: f32,
+    ) {
+        if let Some(region) = qualifier_region(space, shape, center, extent, softness) {
+            self.qualifier_events.push(qualifier::SetQualifierEvent {
+                region: Some(region),
+            });
+        }
+    }
+
+    /// Applies the color transformation to every pixel again.
+    pub fn clear_qualifier(&mut self) {
+        self.qualifier_events
+            .push(qualifier::SetQualifierEvent { region: None });
+    }
+
+    /// Renders the qualifier's matte in grayscale instead of the output.
+    pub fn set_show_matte(&mut self, enabled: bool) {
+        self.matte_events
+            .push(qualifier::SetShowMatteEvent { enabled });
+    }
+
+    /// Keys the colors of a region of the input, given as for `set_qualifier`,
+    /// into transparency. For example, a green screen:
+    /// `set_key("rgb", "ellipsoid", [0.1, 0.8, 0.2], [0.3, 0.3, 0.3], 0.5)`.
+    pub fn set_key(
+        &mut self,
+        space: &str,
+        shape: &str,
+        center: &[f32],
+        extent: &[f32],
+        softness: f32,
+    ) {
+        if let Some(region) = qualifier_region(space, shape, center, extent, softness) {
+            self.key_events.push(keyer::SetKeyEvent {
+                key: Some(keyer::KeyVolume::Region(region)),
+            });
+        }
+    }
+
+    /// Keys the colors of the volume selected in the cube into transparency,
+    /// following the selection as it changes.
+    pub fn key_selection(&mut self) {
+        self.key_events.push(keyer::SetKeyEvent {
+            key: Some(keyer::KeyVolume::Selection),
+        });
+    }
+
+    pub fn clear_key(&mut self) {
+        self.key_events.push(keyer::SetKeyEvent { key: None });
+    }
+
+    /// Refines the key's matte: `spill` suppression from 0 to 1, `choke` in
+    /// pixels (negative values grow the matte), `softness` as a blur radius in
+    /// pixels, and whether the output is `premultiplied` by alpha.
+    pub fn set_key_matte(&mut self, spill: f32, choke: f32, softness: f32, premultiply: bool) {
+        self.key_matte_events.push(keyer::SetKeyMatteEvent {
+            matte: keyer::MatteSettings {
+                spill: spill.clamp(0.0, 1.0),
+                choke,
+                softness: softness.max(0.0),
+                premultiply,
+            },
+        });
+    }
+
+    /// Composites the keyed output over a solid color.
+    pub fn set_key_background_color(&mut self, r: f32, g: f32, b: f32) {
+        self.key_background_events
+            .push(keyer::SetKeyBackgroundEvent {
+                background: keyer::KeyBackground::Color(Vec3::new(r, g, b)),
+            });
+    }
+
+    /// Composites the keyed output over an image, stretched to its size.
+    pub fn set_key_background_image(&mut self, image_data: ImageData) {
+        self.key_background_events
+            .push(keyer::SetKeyBackgroundEvent {
+                background: keyer::KeyBackground::Image {
+                    width: image_data.width(),
+                    height: image_data.height(),
+                    data: pixels(&image_data),
+                },
+            });
+    }
+
+    /// Leaves the keyed output transparent.
+    pub fn clear_key_background(&mut self) {
+        self.key_background_events
+            .push(keyer::SetKeyBackgroundEvent {
+                background: keyer::KeyBackground::None,
+            });
+    }
+
+    /// Selects the colors in a box of the cube's unit space, where RGB colors
+    /// are their own coordinates. Their pixels are highlighted in the output.
+    pub fn select_box(&mut self, x0: f32, y0: f32, z0: f32, x1: f32, y1: f32, z1: f32) {
+        self.selection_events.push(selection::SetSelectionEvent {
+            shape: Some(selection::SelectionShape::Box {
+                min: Vec3::new(x0, y0, z0),
+                max: Vec3::new(x1, y1, z1),
+            }),
+        });
+    }
+
+    /// Selects the colors in a sphere of the cube's unit space.
+    pub fn select_sphere(&mut self, x: f32, y: f32, z: f32, radius: f32) {
+        self.selection_events.push(selection::SetSelectionEvent {
+            shape: Some(selection::SelectionShape::Sphere {
+                center: Vec3::new(x, y, z),
+                radius,
+            }),
+        });
+    }
+
+    /// Selects the colors drawn inside a polygon over the 3D view, through
+    /// its whole depth. `points` holds the `x, y` canvas pixels of each vertex.
+    pub fn select_lasso(&mut self, points: &[f32]) {
+        let points = points
+            .chunks_exact(2)
+            .map(|p| Vec2::new(p[0], p[1]))
+            .collect::<Vec<_>>();
+        if points.len() < 3 {
+            utils::log("A lasso needs at least 3 points");
+            return;
+        }
+        self.selection_events.push(selection::SetSelectionEvent {
+            shape: Some(selection::SelectionShape::Lasso { points }),
+        });
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selection_events
+            .push(selection::SetSelectionEvent { shape: None });
+    }
+
+    /// Selects whether the "input" or "output" color of each pixel is tested
+    /// against the selection.
+    pub fn set_selection_source(&mut self, source: &str) {
+        match selection::SelectionSource::from_name(source) {
+            Some(source) => self
+                .selection_source_events
+                .push(selection::SetSelectionSourceEvent { source }),
+            None => utils::log(&format!("Unknown selection source: {source}")),
+        }
+    }
+
+    /// Sets how many milliseconds per frame image processing may take.
+    pub fn set_time_budget(&mut self, ms: f32) {
+        match Duration::try_from_secs_f32(ms.max(0.0) / 1000.0) {
+            Ok(time_budget) => self
+                .budget_events
+                .push(processing::SetTimeBudgetEvent { time_budget }),
+            Err(_) => utils::log(&format!("Invalid time budget: {ms}")),
+        }
+    }
+
+    /// Caps the pixel count of the proxy used while the transformation is being edited.
+    pub fn set_proxy_max_pixels(&mut self, max_pixels: u32) {
+        self.proxy_size_events
+            .push(proxy::SetProxyMaxPixelsEvent { max_pixels });
+    }
+
+    /// Always process the full resolution input, even during interaction.
+    pub fn set_force_full_quality(&mut self, enabled: bool) {
+        self.quality_events
+            .push(proxy::SetForceFullQualityEvent { enabled });
+    }
+
+    /// Sets how many threads image processing may use. Returns the effective
+    /// count, which is always 1 in builds without the `threads` feature.
+    pub fn set_thread_count(&mut self, count: u32) -> u32 {
+        let count = parallel::set_thread_count(count as usize) as u32;
+        utils::log(&format!("Processing with {count} thread(s)"));
+        count
+    }
+
+    /// Registers `callback(stage, done, total)`, called as processing progresses.
+    pub fn on_progress(&mut self, callback: js_sys::Function) {
+        self.progress_callback = Some(callback);
+    }
+}
+
+fn send_events<T: Send + Sync + 'static>(world: &mut World, queue: &mut Vec<T>) {
+    let mut events = world.get_resource_mut::<Events<T>>().unwrap();
+    for evt in queue.drain(..) {
+        events.send(evt);
+    }
+}
+
+/// Parses the arguments shared by qualifiers and keys, logging what is wrong
+/// with them.
+fn qualifier_region(
+    space: &str,
+    shape: &str,
+    center: &[f32],
+    extent: &[f32],
+    softness: f32,
+) -> Option<qualifier::QualifierRegion> {
+    let space = match qualifier::QualifierSpace::from_name(space) {
+        Some(space) => space,
+        None => {
+            utils::log(&format!("Unknown qualifier space: {space}"));
+            return None;
+        }
+    };
+    let shape = match qualifier::QualifierShape::from_name(shape) {
+        Some(shape) => shape,
+        None => {
+            utils::log(&format!("Unknown qualifier shape: {shape}"));
+            return None;
+        }
+    };
+    if center.len() != 3 || extent.len() != 3 {
+        utils::log("Qualifier center and extent need 3 components each");
+        return None;
+    }
+    Some(qualifier::QualifierRegion {
+        space,
+        shape,
+        center: Vec3::from_slice(center),
+        extent: Vec3::from_slice(extent).abs(),
+        softness: softness.max(0.0),
+    })
+}
+
+fn pixels(image_data: &ImageData) -> Vec<kernels::Pixel> {
+    image_data
+        .data()
+        .0
+        .iter()
+        .map(|&x| x as f32 / 255.0)
+        .chunks(4)
+        .into_iter()
+        .map(|mut chunk| {
+            [
+                chunk.next().unwrap(),
+                chunk.next().unwrap(),
+                chunk.next().unwrap(),
+                chunk.next().unwrap(),
+            ]
+        })
+        .collect()
 }
@@ -0,0 +1,229 @@
+const DEFAULT_THRESHOLD: f32 = 0.001;
+const AUTO_DEVIATIONS: f64 = 2.0;
+
+/// How histogram bin densities map to instance sizes in the color cube.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Normalization {
+    /// Bins holding at least `threshold` of all pixels are drawn at full size.
+    Linear { threshold: f32 },
+    /// `log(1 + count)`, relative to the largest bin.
+    Log,
+    /// `sqrt(count)`, relative to the largest bin.
+    Sqrt,
+    /// Bins at or above the given percentile (0-100) of the non-empty bins
+    /// are drawn at full size.
+    Percentile { percentile: f32 },
+    /// Linear, with the full size count derived from the image: the mean plus
+    /// two standard deviations of the non-empty bins.
+    Auto,
+}
+
+impl Default for Normalization {
+    fn default() -> Self {
+        Normalization::Linear {
+            threshold: DEFAULT_THRESHOLD,
+        }
+    }
+}
+
+impl Normalization {
+    /// Parses a mode name as passed from JavaScript. `param` is the threshold
+    /// fraction for "linear", in (0, 1], and the percentile for "percentile",
+    /// in [0, 100]. `None` for unknown modes and out of range parameters.
+    pub fn from_name(name: &str, param: f32) -> Option<Self> {
+        match name {
+            "linear" if param > 0.0 && param <= 1.0 => {
+                Some(Normalization::Linear { threshold: param })
+            }
+            "log" => Some(Normalization::Log),
+            "sqrt" => Some(Normalization::Sqrt),
+            "percentile" if (0.0..=100.0).contains(&param) => {
+                Some(Normalization::Percentile { percentile: param })
+            }
+            "auto" => Some(Normalization::Auto),
+            _ => None,
+        }
+    }
+
+    /// Whether a bin's size depends only on its own count, in which case a
+    /// partial histogram update only needs to resize the bins it touched.
+    pub fn is_local(&self) -> bool {
+        matches!(self, Normalization::Linear { .. })
+    }
+
+    /// Scaling of the given bin densities, which are pixel counts, possibly
+    /// smoothed.
+    pub fn scaling(&self, densities: &[f32], num_pixels: u32) -> BinScaling {
+        let max = || densities.iter().copied().fold(0.0, f32::max);
+        match *self {
+            Normalization::Linear { threshold } => BinScaling {
+                curve: identity,
+                reference: threshold * num_pixels as f32,
+            },
+            Normalization::Log => BinScaling {
+                curve: f32::ln_1p,
+                reference: max().ln_1p(),
+            },
+            Normalization::Sqrt => BinScaling {
+                curve: f32::sqrt,
+                reference: max().sqrt(),
+            },
+            Normalization::Percentile { percentile } => {
+                let mut occupied = densities
+                    .iter()
+                    .copied()
+                    .filter(|&c| c > 0.0)
+                    .collect::<Vec<_>>();
+                let reference = if occupied.is_empty() {
+                    0.0
+                } else {
+                    let p = percentile.clamp(0.0, 100.0) / 100.0;
+                    let k = (p * (occupied.len() - 1) as f32).round() as usize;
+                    *occupied.select_nth_unstable_by(k, f32::total_cmp).1
+                };
+                BinScaling {
+                    curve: identity,
+                    reference,
+                }
+            }
+            Normalization::Auto => {
+                let (n, sum, sum_sq) = densities
+                    .iter()
+                    .filter(|&&c| c > 0.0)
+                    .fold((0.0, 0.0, 0.0), |(n, sum, sum_sq), &c| {
+                        (n + 1.0, sum + c as f64, sum_sq + (c as f64).powi(2))
+                    });
+                let reference = if n > 0.0 {
+                    let mean = sum / n;
+                    let variance = (sum_sq / n - mean * mean).max(0.0);
+                    (mean + AUTO_DEVIATIONS * variance.sqrt()) as f32
+                } else {
+                    0.0
+                };
+                BinScaling {
+                    curve: identity,
+                    reference,
+                }
+            }
+        }
+    }
+}
+
+fn identity(x: f32) -> f32 {
+    x
+}
+
+/// Maps bin densities to [0, 1] for one state of the histogram.
+pub struct BinScaling {
+    curve: fn(f32) -> f32,
+    reference: f32,
+}
+
+impl BinScaling {
+    pub fn apply(&self, density: f32) -> f32 {
+        if self.reference.is_nan() || self.reference <= 0.0 {
+            return 0.0;
+        }
+        ((self.curve)(density) / self.reference).clamp(0.0, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scales(normalization: Normalization, densities: &[f32], num_pixels: u32) -> Vec<f32> {
+        let scaling = normalization.scaling(densities, num_pixels);
+        densities.iter().map(|&d| scaling.apply(d)).collect()
+    }
+
+    #[test]
+    fn parameters_are_validated() {
+        for threshold in [0.0, -0.1, 1.5, f32::NAN, f32::INFINITY] {
+            assert_eq!(Normalization::from_name("linear", threshold), None);
+        }
+        for percentile in [-1.0, 100.5, f32::NAN, f32::NEG_INFINITY] {
+            assert_eq!(Normalization::from_name("percentile", percentile), None);
+        }
+        assert_eq!(
+            Normalization::from_name("linear", 1.0),
+            Some(Normalization::Linear { threshold: 1.0 })
+        );
+        assert_eq!(
+            Normalization::from_name("percentile", 0.0),
+            Some(Normalization::Percentile { percentile: 0.0 })
+        );
+        // Modes without a parameter ignore it
+        assert_eq!(
+            Normalization::from_name("log", f32::NAN),
+            Some(Normalization::Log)
+        );
+        assert_eq!(Normalization::from_name("cubic", 1.0), None);
+
+        let scaling = BinScaling {
+            curve: identity,
+            reference: f32::NAN,
+        };
+        assert_eq!(scaling.apply(10.0), 0.0);
+    }
+
+    #[test]
+    fn log_and_sqrt_keep_the_order_and_lift_small_bins() {
+        let densities = [0.0, 1.0, 10.0, 100.0];
+        let log = scales(Normalization::Log, &densities, 111);
+        let sqrt = scales(Normalization::Sqrt, &densities, 111);
+        let linear = densities.map(|d| d / 100.0);
+        for scales in [&log, &sqrt] {
+            assert!(scales.windows(2).all(|w| w[0] < w[1]), "{scales:?}");
+            assert_eq!(scales[0], 0.0);
+            assert!((scales[3] - 1.0).abs() < 1e-6);
+        }
+        for i in 1..3 {
+            assert!(log[i] > sqrt[i] && sqrt[i] > linear[i]);
+        }
+    }
+
+    #[test]
+    fn percentiles_clip_the_largest_bins() {
+        let densities = (0..=100).map(|d| d as f32).collect::<Vec<_>>();
+        let median = Normalization::Percentile { percentile: 50.0 };
+        let scales = scales(median, &densities, 5050);
+        // The empty bin doesn't count, so the median of 1..=100 is 50 or 51
+        assert!(scales[50] > 0.97);
+        assert_eq!(scales[60], 1.0);
+        assert_eq!(scales[100], 1.0);
+        assert!((scales[25] - 0.5).abs() < 0.02);
+
+        let max = Normalization::Percentile { percentile: 100.0 };
+        let scales = self::scales(max, &densities, 5050);
+        assert_eq!(scales[99], 0.99);
+    }
+
+    #[test]
+    fn auto_reference_sits_between_the_bulk_and_the_outliers() {
+        // Mostly small bins, with a few far larger ones
+        let mut densities = vec![2.0; 100];
+        densities.extend([500.0, 1000.0]);
+        let num_pixels = densities.iter().sum::<f32>() as u32;
+        let scaling = Normalization::Auto.scaling(&densities, num_pixels);
+        assert!(scaling.reference > 2.0 && scaling.reference < 500.0);
+        assert!(scaling.apply(2.0) < 0.05);
+        assert_eq!(scaling.apply(500.0), 1.0);
+        assert_eq!(scaling.apply(1000.0), 1.0);
+    }
+
+    #[test]
+    fn empty_histograms_have_empty_bins() {
+        let densities = [0.0; 8];
+        for normalization in [
+            Normalization::default(),
+            Normalization::Log,
+            Normalization::Sqrt,
+            Normalization::Percentile { percentile: 90.0 },
+            Normalization::Auto,
+        ] {
+            assert_eq!(scales(normalization, &densities, 0), [0.0; 8]);
+            assert!(scales(normalization, &[], 0).is_empty());
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! Optional multithreading for the pixel kernels.
+//!
+//! With the `threads` feature each call is split into one part per configured
+//! thread and run on the rayon pool. On wasm that pool lives in Web Workers
+//! sharing the module's memory, and has to be started from JavaScript with
+//! `startThreadPool(n)` before more than one thread is requested. Until it
+//! has started, or without the feature, everything runs on the calling
+//! thread, and requests are clamped to the pool's size after that.
+
+#[cfg(feature = "threads")]
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(not(feature = "threads"))]
+pub use crate::kernels::{bin_pixels, to_rgba8, transform, transform_blended};
+
+static THREAD_COUNT: AtomicUsize = AtomicUsize::new(1);
+
+/// Whether the rayon pool is running. Natively it starts on first use, but
+/// on wasm only once the workers are up.
+#[cfg(feature = "threads")]
+static POOL_STARTED: AtomicBool = AtomicBool::new(!cfg!(target_arch = "wasm32"));
+
+/// Records that the pool's workers have started.
+#[cfg(all(feature = "threads", target_arch = "wasm32"))]
+pub fn set_pool_started() {
+    POOL_STARTED.store(true, Ordering::Relaxed);
+}
+
+/// Threads in the pool, if it is running.
+#[cfg(feature = "threads")]
+fn pool_size() -> Option<usize> {
+    POOL_STARTED
+        .load(Ordering::Relaxed)
+        .then(rayon::current_num_threads)
+}
+
+#[cfg(not(feature = "threads"))]
+fn pool_size() -> Option<usize> {
+    None
+}
+
+/// Sets how many threads the kernels may use and returns the effective count.
+pub fn set_thread_count(count: usize) -> usize {
+    let count = clamp_thread_count(count, pool_size());
+    THREAD_COUNT.store(count, Ordering::Relaxed);
+    count
+}
+
+/// At least one thread, and no more than the pool has, or just the calling
+/// thread without a pool.
+fn clamp_thread_count(count: usize, pool_size: Option<usize>) -> usize {
+    pool_size.map_or(1, |size| count.clamp(1, size.max(1)))
+}
+
+pub fn thread_count() -> usize {
+    THREAD_COUNT.load(Ordering::Relaxed)
+}
+
+#[cfg(feature = "threads")]
+pub use self::threaded::{bin_pixels, to_rgba8, transform, transform_blended};
+
+#[cfg(feature = "threads")]
+mod threaded {
+    use bevy::math::{Mat3, UVec3};
+    use rayon::prelude::*;
+
+    use super::thread_count;
+    use crate::kernels::{self, Pixel};
+
+    /// Length of each of `parts` parts of `len` items.
+    fn part_len(len: usize, parts: usize) -> usize {
+        len.div_ceil(parts.max(1)).max(1)
+    }
+
+    pub fn transform(rotation: &Mat3, src: &[Pixel], dst: &mut [Pixel]) {
+        transform_parts(thread_count(), rotation, src, dst)
+    }
+
+    /// `transform`, split into `parts` parts run on the pool.
+    pub(super) fn transform_parts(parts: usize, rotation: &Mat3, src: &[Pixel], dst: &mut [Pixel]) {
+        if parts <= 1 {
+            return kernels::transform(rotation, src, dst);
+        }
+        let n = part_len(src.len(), parts);
+        src.par_chunks(n)
+            .zip(dst.par_chunks_mut(n))
+            .for_each(|(src, dst)| kernels::transform(rotation, src, dst));
+    }
+
+    pub fn transform_blended(
+        rotation: &Mat3,
+        src: &[Pixel],
+        dst: &mut [Pixel],
+        matte: &(impl Fn(&Pixel) -> f32 + Sync),
+    ) {
+        if thread_count() == 1 {
+            return kernels::transform_blended(rotation, src, dst, matte);
+        }
+        let n = part_len(src.len(), thread_count());
+        src.par_chunks(n)
+            .zip(dst.par_chunks_mut(n))
+            .for_each(|(src, dst)| kernels::transform_blended(rotation, src, dst, matte));
+    }
+
+    pub fn to_rgba8(src: &[Pixel], dst: &mut [u8]) {
+        if thread_count() == 1 {
+            return kernels::to_rgba8(src, dst);
+        }
+        let n = part_len(src.len(), thread_count());
+        src.par_chunks(n)
+            .zip(dst.par_chunks_mut(n * 4))
+            .for_each(|(src, dst)| kernels::to_rgba8(src, dst));
+    }
+
+    pub fn bin_pixels(src: &[Pixel], resolution: UVec3, counts: &mut [u32]) {
+        bin_pixels_parts(thread_count(), src, resolution, counts)
+    }
+
+    /// Bins each of up to `parts` parts into its own histogram and sums them
+    /// into `counts`. Clearing and summing a histogram costs about as much as
+    /// binning as many pixels as it has bins, so each part gets at least that
+    /// many, and chunks too small for two parts are binned on the calling
+    /// thread.
+    pub(super) fn bin_pixels_parts(
+        parts: usize,
+        src: &[Pixel],
+        resolution: UVec3,
+        counts: &mut [u32],
+    ) {
+        let num_bins = counts.len();
+        let parts = parts.min(src.len() / num_bins.max(1));
+        if parts <= 1 {
+            return kernels::bin_pixels(src, resolution, counts);
+        }
+        let partial = src
+            .par_chunks(part_len(src.len(), parts))
+            .map(|src| {
+                let mut counts = vec![0; num_bins];
+                kernels::bin_pixels(src, resolution, &mut counts);
+                counts
+            })
+            .reduce_with(|mut a, b| {
+                for (x, y) in a.iter_mut().zip(b) {
+                    *x += y;
+                }
+                a
+            });
+        if let Some(partial) = partial {
+            for (x, y) in counts.iter_mut().zip(partial) {
+                *x += y;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thread_counts_are_clamped_to_the_pool() {
+        assert_eq!(clamp_thread_count(8, None), 1);
+        assert_eq!(clamp_thread_count(0, None), 1);
+        assert_eq!(clamp_thread_count(0, Some(4)), 1);
+        assert_eq!(clamp_thread_count(3, Some(4)), 3);
+        assert_eq!(clamp_thread_count(16, Some(4)), 4);
+        assert_eq!(clamp_thread_count(2, Some(0)), 1);
+    }
+
+    /// Splits are run with explicit part counts, so that they don't depend on
+    /// the configured thread count or on the pool having started.
+    #[cfg(feature = "threads")]
+    #[test]
+    fn uneven_splits_match_the_kernels() {
+        use bevy::math::{Mat3, UVec3, Vec3};
+
+        use crate::kernels::{self, Pixel};
+
+        // Not a multiple of any of the part lengths
+        let src = (0..1003)
+            .map(|i| {
+                let f = i as f32 / 1003.0;
+                [f, (f * 5.0).fract(), (f * 11.0).fract(), 1.0]
+            })
+            .collect::<Vec<Pixel>>();
+        let rotation = Mat3::from_cols(Vec3::Y, -Vec3::X, Vec3::Z);
+        let mut expected = vec![[0.0; 4]; src.len()];
+        kernels::transform(&rotation, &src, &mut expected);
+        let resolution = UVec3::splat(4);
+        let mut expected_counts = vec![0; 64];
+        kernels::bin_pixels(&src, resolution, &mut expected_counts);
+
+        for parts in [1, 2, 3, 7] {
+            let mut dst = vec![[0.0; 4]; src.len()];
+            threaded::transform_parts(parts, &rotation, &src, &mut dst);
+            assert_eq!(dst, expected);
+            let mut counts = vec![0; 64];
+            threaded::bin_pixels_parts(parts, &src, resolution, &mut counts);
+            assert_eq!(counts, expected_counts);
+        }
+    }
+}
@@ -0,0 +1,368 @@
+use bevy::prelude::*;
+use bevy::render::camera::{ActiveCameras, Camera, CameraPlugin};
+use wasm_bindgen::prelude::*;
+
+use crate::binning;
+use crate::clipping::Clipping;
+use crate::color_cube::{self, ColorCube, CubeSpace, Histogram};
+use crate::image::{RenderRequest, TransformStartedEvent};
+use crate::kernels::Pixel;
+use crate::layout::Layout;
+use crate::render::{InstanceData, InstancedMesh};
+
+/// Size of the highlight, relative to the bin it surrounds.
+const HIGHLIGHT_MARGIN: f32 = 1.15;
+/// Brightness of the pixels outside the hovered bin, in the rendered output.
+const OTHER_BINS_DIM: f32 = 0.4;
+
+/// Bin under the pointer, as returned to JavaScript by `Glc::pick`.
+#[wasm_bindgen]
+#[derive(Clone, Debug)]
+pub struct PickedBin {
+    pub index: u32,
+    pub count: u32,
+    /// Smoothed count the bin is sized from, the count itself without
+    /// smoothing
+    pub density: f32,
+    /// Share of the binned pixels, 0-100
+    pub percentage: f32,
+    range_min: Vec3,
+    range_max: Vec3,
+    color: Vec3,
+    center: Vec3,
+}
+
+#[wasm_bindgen]
+impl PickedBin {
+    /// Lower corner of the bin, in the layout's coordinates normalized to
+    /// [0, 1]. These are the RGB values themselves in the RGB layout.
+    #[wasm_bindgen(getter)]
+    pub fn range_min(&self) -> Vec<f32> {
+        self.range_min.to_array().to_vec()
+    }
+
+    /// Upper corner of the bin, in the same coordinates as `range_min`.
+    #[wasm_bindgen(getter)]
+    pub fn range_max(&self) -> Vec<f32> {
+        self.range_max.to_array().to_vec()
+    }
+
+    /// RGB color at the center of the bin.
+    #[wasm_bindgen(getter)]
+    pub fn color(&self) -> Vec<f32> {
+        self.color.to_array().to_vec()
+    }
+
+    /// Where the bin is drawn, in the cube's unit space.
+    #[wasm_bindgen(getter)]
+    pub fn center(&self) -> Vec<f32> {
+        self.center.to_array().to_vec()
+    }
+}
+
+/*
+ * Bin under the pointer, outlined in the view
+ */
+#[derive(Default)]
+pub struct HoveredBin(pub Option<usize>);
+
+#[derive(Component)]
+pub struct Highlight;
+
+/*
+ * Bin of each output pixel, kept to highlight the pixels of the hovered bin
+ * without binning the image on every render
+ */
+#[derive(Default)]
+pub struct BinIndices {
+    indices: Vec<u32>,
+    /// Layout and resolution the indices were computed for, `None` once the
+    /// output has changed
+    key: Option<(Layout, UVec3)>,
+}
+
+impl BinIndices {
+    pub fn invalidate(&mut self) {
+        self.key = None;
+    }
+
+    /// Bin index of each of the `colors`, computed again only if they or the
+    /// cube's bins have changed since last time.
+    pub fn get(&mut self, colors: &[Pixel], cube: &ColorCube) -> &[u32] {
+        let key = Some((cube.layout, cube.resolution));
+        if self.key != key || self.indices.len() != colors.len() {
+            self.indices = cube
+                .layout
+                .convert(colors)
+                .iter()
+                .map(|x| binning::bin_index(x, cube.resolution) as u32)
+                .collect();
+            self.key = key;
+        }
+        &self.indices
+    }
+}
+
+/// Normalized device coordinates of a point of the canvas, given in pixels
+/// from its top left corner.
+pub fn canvas_to_ndc(canvas_size: Vec2, point: Vec2) -> Vec2 {
+    Vec2::new(
+        2.0 * point.x / canvas_size.x - 1.0,
+        1.0 - 2.0 * point.y / canvas_size.y,
+    )
+}
+
+/// Ray through a point of the canvas, given in pixels from its top left
+/// corner. Returns the origin and direction in world space.
+pub fn camera_ray(
+    camera: &Camera,
+    camera_transform: &GlobalTransform,
+    canvas_size: Vec2,
+    point: Vec2,
+) -> (Vec3, Vec3) {
+    let ndc = canvas_to_ndc(canvas_size, point);
+    let ndc_to_world = camera_transform.compute_matrix() * camera.projection_matrix.inverse();
+    // Depth is reversed, with the near plane at 1
+    let near = ndc_to_world.project_point3(ndc.extend(1.0));
+    let far = ndc_to_world.project_point3(ndc.extend(0.5));
+    (near, (far - near).normalize())
+}
+
+/// Distance along the ray to where it enters the box, or 0 if it starts
+/// inside. `None` if it misses.
+pub fn ray_box(origin: Vec3, direction: Vec3, min: Vec3, max: Vec3) -> Option<f32> {
+    let inv = direction.recip();
+    let t0 = (min - origin) * inv;
+    let t1 = (max - origin) * inv;
+    let enter = t0.min(t1).max_element().max(0.0);
+    let exit = t0.max(t1).min_element();
+    (enter <= exit).then_some(enter)
+}
+
+/// Nearest instance hit by the ray, drawn as a unit cube scaled around its
+/// position. Empty instances and clipped ones can't be picked.
+pub fn pick_instance(
+    origin: Vec3,
+    direction: Vec3,
+    instances: &[InstanceData],
+    clipping: &Clipping,
+) -> Option<usize> {
+    instances
+        .iter()
+        .enumerate()
+        .filter(|(_, instance)| instance.scale.min_element() > 0.0)
+        .filter(|(_, instance)| clipping.contains(instance.position))
+        .filter_map(|(i, instance)| {
+            let half = instance.scale / 2.0;
+            let min = instance.position - half;
+            let max = instance.position + half;
+            ray_box(origin, direction, min, max).map(|t| (i, t))
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(i, _)| i)
+}
+
+pub fn create_highlight(commands: &mut Commands, wire_mesh: Handle<Mesh>, transform: Transform) {
+    commands.spawn_bundle((
+        transform,
+        GlobalTransform::identity(),
+        wire_mesh,
+        InstancedMesh(vec![]),
+        Highlight,
+        CubeSpace,
+        Visibility { is_visible: false },
+        ComputedVisibility::default(),
+    ));
+}
+
+/// Finds the bin of the color cube under the canvas point (`x`, `y`) and
+/// highlights it. Nothing is picked while the cube is hidden.
+pub fn pick(world: &mut World, x: f32, y: f32) -> Option<PickedBin> {
+    let bin = find_bin(world, Vec2::new(x, y));
+    world.insert_resource(HoveredBin(bin.as_ref().map(|bin| bin.index as usize)));
+    bin
+}
+
+fn find_bin(world: &mut World, point: Vec2) -> Option<PickedBin> {
+    let mut cube_query = world.query::<(
+        &GlobalTransform,
+        &InstancedMesh,
+        &Histogram,
+        &ColorCube,
+        &Visibility,
+    )>();
+    let window = world.get_resource::<Windows>()?.get_primary()?;
+    let canvas_size = Vec2::new(window.width(), window.height());
+    let camera_entity = world
+        .get_resource::<ActiveCameras>()?
+        .get(CameraPlugin::CAMERA_3D)?
+        .entity?;
+    let camera = world.get::<Camera>(camera_entity)?;
+    let camera_transform = world.get::<GlobalTransform>(camera_entity)?;
+    let (origin, direction) = camera_ray(camera, camera_transform, canvas_size, point);
+    let clipping = world.get_resource::<Clipping>()?;
+
+    let (transform, mesh, histogram, cube, visibility) = cube_query.iter(world).last()?;
+    if !visibility.is_visible {
+        return None;
+    }
+    // Intersect in the cube's unit space, where the instances are
+    let to_local = transform.compute_matrix().inverse();
+    let index = pick_instance(
+        to_local.transform_point3(origin),
+        to_local.transform_vector3(direction),
+        &mesh.0,
+        clipping,
+    )?;
+
+    let count = *histogram.counts.get(index)?;
+    let total = histogram
+        .counts
+        .iter()
+        .map(|&c| c as u64)
+        .sum::<u64>()
+        .max(1);
+    let coords = binning::index_to_coords(index, cube.resolution);
+    let (range_min, range_max) = binning::bin_edges(coords, cube.resolution);
+    Some(PickedBin {
+        index: index as u32,
+        count,
+        density: histogram.density.get(index).copied().unwrap_or(0.0),
+        percentage: 100.0 * count as f32 / total as f32,
+        range_min,
+        range_max,
+        color: color_cube::lattice_color(coords, cube.resolution, cube.layout),
+        center: mesh.0[index].position,
+    })
+}
+
+/// Desaturates and darkens the 8 bit pixels that don't fall into `bin`.
+pub fn dim_other_bins(rgba: &mut [u8], indices: &[u32], bin: usize) {
+    for (pixel, &i) in rgba.chunks_exact_mut(4).zip(indices) {
+        if i as usize != bin {
+            let luma =
+                0.2126 * pixel[0] as f32 + 0.7152 * pixel[1] as f32 + 0.0722 * pixel[2] as f32;
+            for x in &mut pixel[..3] {
+                *x = (luma * OTHER_BINS_DIM) as u8;
+            }
+        }
+    }
+}
+
+/// Drops the bin indices once the output changes, and renders the output
+/// again when another bin is hovered. Dropping them renders again too, in case
+/// the output was rendered with the old ones.
+pub fn refresh_bin_highlight(
+    mut started_events: EventReader<TransformStartedEvent>,
+    mut cube_events: EventReader<color_cube::UpdateColorCubeEvent>,
+    mut region_events: EventReader<color_cube::UpdateColorCubeRegionEvent>,
+    hovered: Res<HoveredBin>,
+    mut previous: Local<Option<usize>>,
+    mut bin_indices: ResMut<BinIndices>,
+    mut out_events: EventWriter<RenderRequest>,
+) {
+    let changed =
+        started_events.iter().count() + cube_events.iter().count() + region_events.iter().count();
+    if changed > 0 {
+        bin_indices.invalidate();
+    }
+    if hovered.0 != *previous || (changed > 0 && hovered.0.is_some()) {
+        *previous = hovered.0;
+        out_events.send(RenderRequest);
+    }
+}
+
+/// Outlines the hovered bin, following it as the histogram changes.
+pub fn update_highlight(
+    hovered: Res<HoveredBin>,
+    cube_query: Query<&InstancedMesh, (With<ColorCube>, Without<Highlight>)>,
+    mut highlight_query: Query<(&mut InstancedMesh, &mut Visibility), With<Highlight>>,
+) {
+    let instance = hovered
+        .0
+        .and_then(|i| cube_query.iter().last()?.0.get(i).copied());
+    if let Some((mut mesh, mut visibility)) = highlight_query.iter_mut().last() {
+        visibility.is_visible = instance.is_some();
+        mesh.0 = instance
+            .map(|instance| InstanceData {
+                position: instance.position,
+                scale: instance.scale * HIGHLIGHT_MARGIN,
+                color: Color::WHITE.as_rgba_f32(),
+            })
+            .into_iter()
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instance(position: Vec3, scale: f32) -> InstanceData {
+        InstanceData {
+            position,
+            scale: Vec3::splat(scale),
+            color: [1.0; 4],
+        }
+    }
+
+    #[test]
+    fn pixels_of_other_bins_are_dimmed() {
+        let cube = ColorCube {
+            resolution: UVec3::splat(2),
+            layout: Layout::Rgb,
+            normalization: Default::default(),
+            position: Default::default(),
+            color: Default::default(),
+            smoothing: Default::default(),
+        };
+        let colors = [[0.1, 0.1, 0.1, 1.0], [0.9, 0.1, 0.1, 1.0]];
+        let mut bin_indices = BinIndices::default();
+        let indices = bin_indices.get(&colors, &cube).to_vec();
+        assert_eq!(indices, vec![0, 4]);
+
+        let mut rgba = [100, 100, 100, 255, 200, 0, 0, 255];
+        dim_other_bins(&mut rgba, &indices, 0);
+        assert_eq!(&rgba[..4], &[100, 100, 100, 255]);
+        // Red turns into a dark gray
+        assert!(rgba[4] == rgba[5] && rgba[5] == rgba[6] && rgba[4] < 20);
+    }
+
+    #[test]
+    fn ray_box_hits_front_face() {
+        let t = ray_box(Vec3::new(0.5, 0.5, -2.0), Vec3::Z, Vec3::ZERO, Vec3::ONE);
+        assert_eq!(t, Some(2.0));
+        assert_eq!(
+            ray_box(Vec3::new(2.0, 0.5, -2.0), Vec3::Z, Vec3::ZERO, Vec3::ONE),
+            None
+        );
+        // Boxes behind the origin are missed
+        assert_eq!(
+            ray_box(Vec3::new(0.5, 0.5, 2.0), Vec3::Z, Vec3::ZERO, Vec3::ONE),
+            None
+        );
+    }
+
+    #[test]
+    fn nearest_visible_instance_is_picked() {
+        let instances = [
+            instance(Vec3::new(0.5, 0.5, 0.75), 0.1),
+            instance(Vec3::new(0.5, 0.5, 0.25), 0.1),
+            // Empty bin in front of the others
+            instance(Vec3::new(0.5, 0.5, 0.1), 0.0),
+        ];
+        let origin = Vec3::new(0.5, 0.5, -1.0);
+        let mut clipping = Clipping::default();
+        assert_eq!(
+            pick_instance(origin, Vec3::Z, &instances, &clipping),
+            Some(1)
+        );
+
+        clipping.slice = crate::clipping::Slice::new(Vec3::Z, 0.5, 1.0);
+        assert_eq!(
+            pick_instance(origin, Vec3::Z, &instances, &clipping),
+            Some(0)
+        );
+        assert_eq!(pick_instance(origin, Vec3::X, &instances, &clipping), None);
+    }
+}
@@ -0,0 +1,233 @@
+use bevy::prelude::*;
+
+use crate::clipping::Clipped;
+use crate::color_cube::{self, ColorCube, CubeSpace};
+use crate::image;
+use crate::isosurface::Isosurface;
+use crate::render::{InstanceData, InstancedMesh};
+
+const DEFAULT_SAMPLE_SIZE: u32 = 20_000;
+/// Size of a point, relative to the unit cube.
+const POINT_SCALE: f32 = 0.006;
+/// Fixed seed, so that the same image always gives the same points.
+const SEED: u32 = 0x9e37_79b9;
+
+/// What the 3D view shows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViewMode {
+    /// The binned histogram.
+    Bins,
+    /// A sample of individual pixels, at their exact color coordinates.
+    Points,
+    /// Nested isosurfaces of the histogram density.
+    Isosurface,
+}
+
+impl ViewMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "bins" => Some(ViewMode::Bins),
+            "points" => Some(ViewMode::Points),
+            "isosurface" => Some(ViewMode::Isosurface),
+            _ => None,
+        }
+    }
+}
+
+/// How pixels are picked for the point cloud.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sampling {
+    /// One pixel from each of `sample_size` equal runs of the image.
+    Stratified,
+    /// Pixels picked uniformly at random.
+    Random,
+}
+
+impl Sampling {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "stratified" => Some(Sampling::Stratified),
+            "random" => Some(Sampling::Random),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct PointCloud {
+    pub sample_size: u32,
+    pub sampling: Sampling,
+    /// Whether the points are out of date with the output image.
+    stale: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct SetViewModeEvent {
+    pub mode: ViewMode,
+}
+
+#[derive(Clone, Debug)]
+pub struct SetPointSamplingEvent {
+    pub sample_size: u32,
+    pub sampling: Sampling,
+}
+
+/// Xorshift generator, good enough to scatter samples.
+struct Rng(u32);
+
+impl Rng {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    /// Uniform integer in `0..n`.
+    fn below(&mut self, n: usize) -> usize {
+        ((self.next() as u64 * n as u64) >> 32) as usize
+    }
+}
+
+/// Indices of the pixels to show out of `len`, in increasing order for
+/// stratified sampling. Every pixel is shown if there are at most `n`.
+pub fn sample_indices(len: usize, n: usize, sampling: Sampling) -> Vec<usize> {
+    if len <= n {
+        return (0..len).collect();
+    }
+    let mut rng = Rng(SEED);
+    match sampling {
+        Sampling::Stratified => (0..n)
+            .map(|i| {
+                let start = i * len / n;
+                let end = (i + 1) * len / n;
+                start + rng.below(end - start)
+            })
+            .collect(),
+        Sampling::Random => (0..n).map(|_| rng.below(len)).collect(),
+    }
+}
+
+pub fn create_point_cloud(commands: &mut Commands, mesh: Handle<Mesh>, transform: Transform) {
+    commands.spawn_bundle((
+        transform,
+        GlobalTransform::identity(),
+        mesh,
+        InstancedMesh(vec![]),
+        PointCloud {
+            sample_size: DEFAULT_SAMPLE_SIZE,
+            sampling: Sampling::Stratified,
+            stale: true,
+        },
+        CubeSpace,
+        Clipped,
+        Visibility { is_visible: false },
+        ComputedVisibility::default(),
+    ));
+}
+
+/// Shows either the color cube, the point cloud or the isosurfaces.
+#[allow(clippy::type_complexity)]
+pub fn set_view_mode(
+    mut events: EventReader<SetViewModeEvent>,
+    mut cube_query: Query<&mut Visibility, (With<ColorCube>, Without<PointCloud>)>,
+    mut cloud_query: Query<&mut Visibility, (With<PointCloud>, Without<ColorCube>)>,
+    mut isosurface_query: Query<
+        &mut Visibility,
+        (With<Isosurface>, Without<ColorCube>, Without<PointCloud>),
+    >,
+) {
+    let evts = events.iter().collect::<Vec<_>>();
+    if let Some(evt) = evts.into_iter().last() {
+        for mut visibility in cube_query.iter_mut() {
+            visibility.is_visible = evt.mode == ViewMode::Bins;
+        }
+        for mut visibility in cloud_query.iter_mut() {
+            visibility.is_visible = evt.mode == ViewMode::Points;
+        }
+        for mut visibility in isosurface_query.iter_mut() {
+            visibility.is_visible = evt.mode == ViewMode::Isosurface;
+        }
+    }
+}
+
+pub fn set_point_sampling(
+    mut events: EventReader<SetPointSamplingEvent>,
+    mut query: Query<&mut PointCloud>,
+) {
+    let evts = events.iter().collect::<Vec<_>>();
+    if let Some(evt) = evts.into_iter().last() {
+        for mut cloud in query.iter_mut() {
+            cloud.sample_size = evt.sample_size;
+            cloud.sampling = evt.sampling;
+            cloud.stale = true;
+        }
+    }
+}
+
+/// Resamples the output image whenever it or the layout changes. Hidden
+/// point clouds are only marked stale, and resampled once shown.
+pub fn update_point_cloud(
+    mut events: EventReader<color_cube::UpdateColorCubeEvent>,
+    mut region_events: EventReader<color_cube::UpdateColorCubeRegionEvent>,
+    image_query: Query<&image::Image, With<image::Output>>,
+    cube_query: Query<&ColorCube>,
+    mut cloud_query: Query<(&mut InstancedMesh, &mut PointCloud, &Visibility)>,
+) {
+    let changed = events.iter().count() + region_events.iter().count() > 0;
+    if let Some(image) = image_query.iter().last() {
+        if let Some(cube) = cube_query.iter().last() {
+            if let Some((mut mesh, mut cloud, visibility)) = cloud_query.iter_mut().last() {
+                cloud.stale |= changed;
+                if !cloud.stale || !visibility.is_visible {
+                    return;
+                }
+                cloud.stale = false;
+
+                let n = cloud.sample_size as usize;
+                mesh.0 = sample_indices(image.data.len(), n, cloud.sampling)
+                    .into_iter()
+                    .map(|i| {
+                        let c = image.data[i];
+                        let rgb = Vec3::new(c[0], c[1], c[2]);
+                        InstanceData {
+                            position: cube.layout.position(cube.layout.coords(rgb)),
+                            scale: Vec3::splat(POINT_SCALE),
+                            color: Color::rgb(c[0], c[1], c[2]).as_rgba_f32(),
+                        }
+                    })
+                    .collect();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_images_are_shown_whole() {
+        for sampling in [Sampling::Stratified, Sampling::Random] {
+            assert_eq!(sample_indices(5, 8, sampling), vec![0, 1, 2, 3, 4]);
+        }
+    }
+
+    #[test]
+    fn stratified_samples_one_pixel_per_run() {
+        let (len, n) = (1000, 64);
+        let indices = sample_indices(len, n, Sampling::Stratified);
+        assert_eq!(indices.len(), n);
+        for (i, index) in indices.into_iter().enumerate() {
+            assert!((i * len / n..(i + 1) * len / n).contains(&index));
+        }
+    }
+
+    #[test]
+    fn random_samples_are_in_range_and_repeatable() {
+        let indices = sample_indices(1000, 64, Sampling::Random);
+        assert_eq!(indices.len(), 64);
+        assert!(indices.iter().all(|&i| i < 1000));
+        assert_eq!(indices, sample_indices(1000, 64, Sampling::Random));
+    }
+}
@@ -0,0 +1,295 @@
+//! Probing a pixel of the output canvas, to link it with the color cube.
+//!
+//! The probed pixel's input and output colors are returned to JavaScript, and
+//! marked in the cube: their bins are outlined and a segment shows where the
+//! transformation moved the input color.
+
+use bevy::prelude::*;
+use wasm_bindgen::{prelude::*, JsCast};
+
+use crate::binning;
+use crate::color_cube::{ColorCube, CubeSpace};
+use crate::image::{Image, Input, Output};
+use crate::kernels::Pixel;
+use crate::keyer::{self, Keyed, Keyer};
+use crate::layout::{self, Layout};
+use crate::proxy::Proxy;
+use crate::render::{InstanceData, InstancedMesh};
+
+/// Size of the bin outlines, relative to the bin they surround.
+const MARKER_MARGIN: f32 = 1.3;
+const INPUT_MARKER_COLOR: Color = Color::rgb(0.6, 0.6, 0.6);
+const VECTOR_COLOR: Color = Color::YELLOW;
+
+/// A color of the probed pixel, in the notations shown to the user.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub struct ProbedColor {
+    rgb: Vec3,
+    pub alpha: f32,
+}
+
+#[wasm_bindgen]
+impl ProbedColor {
+    /// RGB components in [0, 1].
+    #[wasm_bindgen(getter)]
+    pub fn rgb(&self) -> Vec<f32> {
+        self.rgb.to_array().to_vec()
+    }
+
+    /// "#rrggbb", with the components clamped to [0, 1].
+    #[wasm_bindgen(getter)]
+    pub fn hex(&self) -> String {
+        to_hex(self.rgb)
+    }
+
+    /// Hue, saturation and value, all in [0, 1].
+    #[wasm_bindgen(getter)]
+    pub fn hsv(&self) -> Vec<f32> {
+        layout::rgb_to_hsv(self.rgb).to_array().to_vec()
+    }
+
+    /// CIELAB (D65), with L in [0, 100].
+    #[wasm_bindgen(getter)]
+    pub fn lab(&self) -> Vec<f32> {
+        layout::rgb_to_lab(self.rgb).to_array().to_vec()
+    }
+}
+
+/// Pixel under the pointer, as returned to JavaScript by `Glc::probe`.
+#[wasm_bindgen]
+#[derive(Clone, Copy, Debug)]
+pub struct ProbedPixel {
+    /// Column in the output image
+    pub x: u32,
+    /// Row in the output image
+    pub y: u32,
+    input: ProbedColor,
+    output: ProbedColor,
+}
+
+#[wasm_bindgen]
+impl ProbedPixel {
+    #[wasm_bindgen(getter)]
+    pub fn input(&self) -> ProbedColor {
+        self.input
+    }
+
+    /// Color after the transformation, keying included.
+    #[wasm_bindgen(getter)]
+    pub fn output(&self) -> ProbedColor {
+        self.output
+    }
+}
+
+/*
+ * Input and output RGB colors of the probed pixel, marked in the view
+ */
+#[derive(Default)]
+pub struct ProbedColors(pub Option<(Vec3, Vec3)>);
+
+/// Outlines of the bins of the probed colors.
+#[derive(Component)]
+pub struct ProbeMarker;
+
+/// Segment from where the probed input color is drawn to its output color.
+#[derive(Component)]
+pub struct ProbeVector;
+
+pub fn to_hex(rgb: Vec3) -> String {
+    let [r, g, b] = rgb
+        .clamp(Vec3::ZERO, Vec3::ONE)
+        .to_array()
+        .map(|x| (x * 255.0).round() as u8);
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+/// Pixel of an image drawn over the width of the canvas, keeping its aspect
+/// ratio as `render_image` does. `point` is in pixels of the canvas's drawing
+/// buffer, `canvas_width` wide, rather than CSS pixels, from the top left
+/// corner. `None` outside the image.
+pub fn canvas_to_image(point: Vec2, canvas_width: u32, image_size: UVec2) -> Option<UVec2> {
+    if canvas_width == 0 || point.x < 0.0 || point.y < 0.0 {
+        return None;
+    }
+    let scale = image_size.x as f32 / canvas_width as f32;
+    let pixel = (point * scale).floor();
+    (pixel.x < image_size.x as f32 && pixel.y < image_size.y as f32)
+        .then(|| UVec2::new(pixel.x as u32, pixel.y as u32))
+}
+
+/// Where a color is drawn in the cube's unit space.
+pub fn color_position(rgb: Vec3, layout: Layout) -> Vec3 {
+    layout.position(layout.coords(rgb))
+}
+
+fn rgb(c: &Pixel) -> Vec3 {
+    Vec3::new(c[0], c[1], c[2])
+}
+
+pub fn create_probe_marker(
+    commands: &mut Commands,
+    wire_mesh: Handle<Mesh>,
+    segment_mesh: Handle<Mesh>,
+    transform: Transform,
+) {
+    commands.spawn_bundle((
+        transform,
+        GlobalTransform::identity(),
+        wire_mesh,
+        InstancedMesh(vec![]),
+        ProbeMarker,
+        CubeSpace,
+        Visibility::default(),
+        ComputedVisibility::default(),
+    ));
+    commands.spawn_bundle((
+        transform,
+        GlobalTransform::identity(),
+        segment_mesh,
+        InstancedMesh(vec![]),
+        ProbeVector,
+        CubeSpace,
+        Visibility::default(),
+        ComputedVisibility::default(),
+    ));
+}
+
+/// Probes the output canvas at (`x`, `y`), in canvas pixels from its top left
+/// corner, and marks the pixel's colors in the cube. Outside the image, the
+/// marker is cleared.
+pub fn probe(world: &mut World, x: f32, y: f32) -> Option<ProbedPixel> {
+    let found = find_pixel(world, Vec2::new(x, y));
+    // The cube bins the transformed colors, before keying
+    world.insert_resource(ProbedColors(
+        found.map(|(pixel, transformed)| (pixel.input.rgb, transformed)),
+    ));
+    found.map(|(pixel, _)| pixel)
+}
+
+pub fn clear_probe(world: &mut World) {
+    world.insert_resource(ProbedColors(None));
+}
+
+/// The probed pixel, and its transformed color before keying.
+fn find_pixel(world: &mut World, point: Vec2) -> Option<(ProbedPixel, Vec3)> {
+    let mut output_query = world.query::<(&Image, &Output, &Keyer, &Keyed)>();
+    let mut input_query =
+        world.query_filtered::<(&Image, &Proxy), (With<Input>, Without<Output>)>();
+    let (image, output, keyer, keyed) = output_query.iter(world).last()?;
+    let canvas = web_sys::window()?
+        .document()?
+        .get_element_by_id(output.canvas_id.as_ref()?)?
+        .dyn_into::<web_sys::HtmlCanvasElement>()
+        .ok()?;
+    // The point is in buffer pixels, like the canvas's width
+    let pixel = canvas_to_image(point, canvas.width(), UVec2::new(image.width, image.height))?;
+    let i = (pixel.y * image.width + pixel.x) as usize;
+
+    let (input, proxy) = input_query.iter(world).last()?;
+    // The output may have been computed from the proxy
+    let input = proxy.select(input, input.data.len() != image.data.len());
+    let src = input.data.get(i)?;
+    let transformed = image.data.get(i)?;
+    let dst = if keyed.keys(keyer, &image.data) {
+        keyer::unpremultiplied(keyer, &keyed.0[i..i + 1])[0]
+    } else {
+        *transformed
+    };
+    let pixel = ProbedPixel {
+        x: pixel.x,
+        y: pixel.y,
+        input: ProbedColor {
+            rgb: rgb(src),
+            alpha: src[3],
+        },
+        output: ProbedColor {
+            rgb: rgb(&dst),
+            alpha: dst[3],
+        },
+    };
+    Some((pixel, rgb(transformed)))
+}
+
+/// Outlines the bins of the probed colors and links them, following the cube
+/// as its layout and histogram change.
+#[allow(clippy::type_complexity)]
+pub fn update_probe_marker(
+    probed: Res<ProbedColors>,
+    cube_query: Query<(&ColorCube, &InstancedMesh), (Without<ProbeMarker>, Without<ProbeVector>)>,
+    mut marker_query: Query<&mut InstancedMesh, (With<ProbeMarker>, Without<ProbeVector>)>,
+    mut vector_query: Query<&mut InstancedMesh, (With<ProbeVector>, Without<ProbeMarker>)>,
+) {
+    let (cube, cube_mesh) = match cube_query.iter().last() {
+        Some(cube) => cube,
+        None => return,
+    };
+    let (markers, vectors) = match probed.0 {
+        Some((input, output)) => {
+            let outline = |rgb: Vec3, color: Color| {
+                let coords = cube.layout.convert_pixel(&[rgb.x, rgb.y, rgb.z, 1.0]);
+                let index = binning::bin_index(&coords, cube.resolution);
+                let bin = cube_mesh.0.get(index);
+                // Empty bins are drawn with no size, so outline a whole bin
+                let scale = match bin {
+                    Some(bin) if bin.scale.min_element() > 0.0 => bin.scale,
+                    _ => binning::bin_width(cube.resolution),
+                };
+                InstanceData {
+                    position: bin
+                        .map_or_else(|| color_position(rgb, cube.layout), |bin| bin.position),
+                    scale: scale * MARKER_MARGIN,
+                    color: color.as_rgba_f32(),
+                }
+            };
+            let start = color_position(input, cube.layout);
+            let end = color_position(output, cube.layout);
+            (
+                vec![
+                    outline(input, INPUT_MARKER_COLOR),
+                    outline(output, Color::WHITE),
+                ],
+                vec![InstanceData {
+                    position: start,
+                    scale: end - start,
+                    color: VECTOR_COLOR.as_rgba_f32(),
+                }],
+            )
+        }
+        None => (vec![], vec![]),
+    };
+    for mut mesh in marker_query.iter_mut() {
+        mesh.0 = markers.clone();
+    }
+    for mut mesh in vector_query.iter_mut() {
+        mesh.0 = vectors.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canvas_points_map_through_the_scaling() {
+        let size = UVec2::new(400, 200);
+        // Drawn at 200 x 100
+        assert_eq!(
+            canvas_to_image(Vec2::new(10.5, 20.0), 200, size),
+            Some(UVec2::new(21, 40))
+        );
+        assert_eq!(
+            canvas_to_image(Vec2::new(199.9, 99.9), 200, size),
+            Some(UVec2::new(399, 199))
+        );
+        // Below the image
+        assert_eq!(canvas_to_image(Vec2::new(10.0, 100.0), 200, size), None);
+        assert_eq!(canvas_to_image(Vec2::new(-1.0, 10.0), 200, size), None);
+    }
+
+    #[test]
+    fn colors_are_written_in_hex() {
+        assert_eq!(to_hex(Vec3::new(1.0, 0.5, 0.0)), "#ff8000");
+        assert_eq!(to_hex(Vec3::new(1.5, -0.2, 0.2)), "#ff0033");
+    }
+}
@@ -0,0 +1,229 @@
+use std::ops::Range;
+
+use bevy::prelude::*;
+use bevy::utils::{Duration, Instant};
+
+use crate::parallel;
+
+const DEFAULT_TIME_BUDGET_MS: u64 = 8;
+const DEFAULT_CHUNK_SIZE: usize = 4096;
+
+/*
+ * Settings for spreading image processing across frames
+ */
+pub struct ProcessingSettings {
+    pub time_budget: Duration,
+    pub chunk_size: usize,
+}
+
+impl Default for ProcessingSettings {
+    fn default() -> Self {
+        ProcessingSettings {
+            time_budget: Duration::from_millis(DEFAULT_TIME_BUDGET_MS),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+}
+
+/// Deadline shared by every processing system running in the current frame.
+pub struct FrameBudget {
+    deadline: Instant,
+}
+
+impl Default for FrameBudget {
+    fn default() -> Self {
+        FrameBudget {
+            deadline: Instant::now(),
+        }
+    }
+}
+
+impl FrameBudget {
+    pub fn exhausted(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProcessingStage {
+    Transform,
+    Histogram,
+    Medians,
+    Smoothing,
+    Keying,
+    Comparison,
+}
+
+impl ProcessingStage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProcessingStage::Transform => "transform",
+            ProcessingStage::Histogram => "histogram",
+            ProcessingStage::Medians => "medians",
+            ProcessingStage::Smoothing => "smoothing",
+            ProcessingStage::Keying => "keying",
+            ProcessingStage::Comparison => "comparison",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ProcessingProgressEvent {
+    pub stage: ProcessingStage,
+    pub done: usize,
+    pub total: usize,
+}
+
+#[derive(Clone, Debug)]
+pub struct SetTimeBudgetEvent {
+    pub time_budget: Duration,
+}
+
+/// Cursor over a range of pixels that is processed a chunk at a time.
+#[derive(Default)]
+pub struct Job {
+    next: usize,
+    total: usize,
+    active: bool,
+}
+
+impl Job {
+    pub fn start(&mut self, total: usize) {
+        self.next = 0;
+        self.total = total;
+        self.active = true;
+    }
+
+    pub fn cancel(&mut self) {
+        self.active = false;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn progress(&self, stage: ProcessingStage) -> ProcessingProgressEvent {
+        ProcessingProgressEvent {
+            stage,
+            done: self.next,
+            total: self.total,
+        }
+    }
+
+    /// Hands out chunks of the remaining range until the job is done or the
+    /// frame budget runs out. At least one chunk is processed per call so
+    /// that the job always makes progress. Returns true once the job finishes.
+    pub fn run(
+        &mut self,
+        settings: &ProcessingSettings,
+        budget: &FrameBudget,
+        f: impl FnMut(Range<usize>),
+    ) -> bool {
+        // Each thread gets a full chunk's worth of pixels
+        let chunk_size = settings.chunk_size.max(1) * parallel::thread_count();
+        self.run_chunks(chunk_size, budget, f)
+    }
+
+    /// Like `run`, with chunks of `chunk_size` items, for work that isn't
+    /// split across threads or whose items aren't single pixels.
+    pub fn run_chunks(
+        &mut self,
+        chunk_size: usize,
+        budget: &FrameBudget,
+        mut f: impl FnMut(Range<usize>),
+    ) -> bool {
+        if !self.active {
+            return false;
+        }
+        let chunk_size = chunk_size.max(1);
+        loop {
+            let end = (self.next + chunk_size).min(self.total);
+            f(self.next..end);
+            self.next = end;
+            if self.next == self.total {
+                self.active = false;
+                return true;
+            }
+            if budget.exhausted() {
+                return false;
+            }
+        }
+    }
+}
+
+pub fn start_frame_budget(settings: Res<ProcessingSettings>, mut budget: ResMut<FrameBudget>) {
+    budget.deadline = Instant::now() + settings.time_budget;
+}
+
+pub fn set_time_budget(
+    mut events: EventReader<SetTimeBudgetEvent>,
+    mut settings: ResMut<ProcessingSettings>,
+) {
+    let evts = events.iter().collect::<Vec<_>>();
+    if let Some(evt) = evts.into_iter().last() {
+        settings.time_budget = evt.time_budget;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn budget(time: Duration) -> FrameBudget {
+        FrameBudget {
+            deadline: Instant::now() + time,
+        }
+    }
+
+    #[test]
+    fn jobs_stop_with_the_budget_and_resume_where_they_stopped() {
+        let mut job = Job::default();
+        job.start(10);
+        let mut seen = vec![];
+        // A spent budget still lets one chunk through per frame
+        let spent = budget(Duration::ZERO);
+        assert!(!job.run_chunks(3, &spent, |range| seen.push(range)));
+        assert!(!job.run_chunks(3, &spent, |range| seen.push(range)));
+        assert_eq!(seen, vec![0..3, 3..6]);
+
+        let ample = budget(Duration::from_secs(60));
+        assert!(job.run_chunks(3, &ample, |range| seen.push(range)));
+        assert_eq!(seen, vec![0..3, 3..6, 6..9, 9..10]);
+    }
+
+    #[test]
+    fn progress_is_complete_exactly_once() {
+        let mut job = Job::default();
+        job.start(7);
+        let spent = budget(Duration::ZERO);
+        let mut finished = vec![];
+        let mut done = vec![];
+        while job.is_active() {
+            finished.push(job.run_chunks(2, &spent, |_| {}));
+            let progress = job.progress(ProcessingStage::Histogram);
+            assert_eq!(progress.total, 7);
+            done.push(progress.done);
+        }
+        assert_eq!(finished, [false, false, false, true]);
+        assert_eq!(done, [2, 4, 6, 7]);
+
+        // A finished job doesn't finish again
+        assert!(!job.run_chunks(2, &spent, |_| panic!("ran a finished job")));
+        assert_eq!(job.progress(ProcessingStage::Histogram).done, 7);
+    }
+
+    #[test]
+    fn cancelled_jobs_do_nothing() {
+        let mut job = Job::default();
+        assert!(!job.is_active());
+        job.start(100);
+        assert!(job.is_active());
+        job.cancel();
+        assert!(!job.is_active());
+        let ample = budget(Duration::from_secs(60));
+        let mut called = false;
+        assert!(!job.run_chunks(10, &ample, |_| called = true));
+        assert!(!job.run(&ProcessingSettings::default(), &ample, |_| called = true));
+        assert!(!called);
+    }
+}
@@ -0,0 +1,162 @@
+use bevy::prelude::*;
+use bevy::utils::{Duration, Instant};
+
+use crate::image::{Image, Input, InputChangedEvent, TransformImageEvent};
+use crate::resample;
+
+const DEFAULT_MAX_PIXELS: u32 = 256 * 256;
+const DEFAULT_SETTLE_TIME_MS: u64 = 250;
+
+/*
+ * Settings for interactive previews on a downsampled copy of the input
+ */
+pub struct ProxySettings {
+    pub max_pixels: u32,
+    pub force_full_quality: bool,
+    pub settle_time: Duration,
+}
+
+impl Default for ProxySettings {
+    fn default() -> Self {
+        ProxySettings {
+            max_pixels: DEFAULT_MAX_PIXELS,
+            force_full_quality: false,
+            settle_time: Duration::from_millis(DEFAULT_SETTLE_TIME_MS),
+        }
+    }
+}
+
+/// Tracks whether the current output was computed from the proxy and still
+/// needs a full resolution pass.
+pub struct ProxyState {
+    pub pending_refine: bool,
+    pub last_interaction: Instant,
+}
+
+impl Default for ProxyState {
+    fn default() -> Self {
+        ProxyState {
+            pending_refine: false,
+            last_interaction: Instant::now(),
+        }
+    }
+}
+
+/// Downsampled copy of the input image, if the input is large enough to need one.
+#[derive(Component, Default)]
+pub struct Proxy(pub Option<Image>);
+
+impl Proxy {
+    /// Picks the image an interactive or full quality pass should read from.
+    pub fn select<'a>(&'a self, input: &'a Image, use_proxy: bool) -> &'a Image {
+        match &self.0 {
+            Some(proxy) if use_proxy => proxy,
+            _ => input,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SetProxyMaxPixelsEvent {
+    pub max_pixels: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct SetForceFullQualityEvent {
+    pub enabled: bool,
+}
+
+/// Size of the proxy for an image, or `None` if the image is already small enough.
+pub fn proxy_size(width: u32, height: u32, max_pixels: u32) -> Option<(u32, u32)> {
+    let num_pixels = width as u64 * height as u64;
+    if num_pixels <= max_pixels as u64 {
+        return None;
+    }
+    let scale = (max_pixels as f64 / num_pixels as f64).sqrt();
+    let proxy_width = ((width as f64 * scale).floor() as u32).max(1);
+    let proxy_height = ((height as f64 * scale).floor() as u32).max(1);
+    Some((proxy_width, proxy_height))
+}
+
+pub fn set_proxy_settings(
+    mut max_pixels_events: EventReader<SetProxyMaxPixelsEvent>,
+    mut quality_events: EventReader<SetForceFullQualityEvent>,
+    mut settings: ResMut<ProxySettings>,
+) {
+    let evts = max_pixels_events.iter().collect::<Vec<_>>();
+    if let Some(evt) = evts.into_iter().last() {
+        settings.max_pixels = evt.max_pixels.max(1);
+    }
+
+    let evts = quality_events.iter().collect::<Vec<_>>();
+    if let Some(evt) = evts.into_iter().last() {
+        settings.force_full_quality = evt.enabled;
+    }
+}
+
+pub fn update_proxy(
+    mut events: EventReader<InputChangedEvent>,
+    settings: Res<ProxySettings>,
+    mut max_pixels: Local<u32>,
+    mut query: Query<(&Image, &mut Proxy), With<Input>>,
+) {
+    let resized = *max_pixels != settings.max_pixels;
+    *max_pixels = settings.max_pixels;
+
+    if let Some((image, mut proxy)) = query.iter_mut().last() {
+        let mut regions = vec![];
+        let mut rebuild = resized;
+        for evt in events.iter() {
+            match evt.region {
+                Some(rect) => regions.push(rect),
+                None => rebuild = true,
+            }
+        }
+
+        if rebuild {
+            proxy.0 = proxy_size(image.width, image.height, settings.max_pixels)
+                .map(|(width, height)| resample::resample(image, width, height));
+        } else if let Some(proxy) = proxy.0.as_mut() {
+            for rect in regions {
+                resample::resample_region(image, proxy, rect);
+            }
+        }
+    }
+}
+
+/// Requests a full resolution pass once the user stops interacting.
+pub fn refine_full_resolution(
+    settings: Res<ProxySettings>,
+    mut state: ResMut<ProxyState>,
+    mut out_events: EventWriter<TransformImageEvent>,
+) {
+    if state.pending_refine
+        && (settings.force_full_quality || state.last_interaction.elapsed() >= settings.settle_time)
+    {
+        state.pending_refine = false;
+        out_events.send(TransformImageEvent { interactive: false });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proxies_keep_the_aspect_ratio_and_never_upscale() {
+        assert_eq!(proxy_size(256, 256, 256 * 256), None);
+        assert_eq!(proxy_size(100, 30, 256 * 256), None);
+
+        for (width, height) in [(4000, 3000), (1080, 1920), (5000, 700)] {
+            let (w, h) = proxy_size(width, height, 256 * 256).unwrap();
+            assert!(w <= width && h <= height);
+            assert!(w * h <= 256 * 256);
+            let aspect = width as f32 / height as f32;
+            assert!((w as f32 / h as f32 - aspect).abs() / aspect < 0.01);
+        }
+
+        // Sides are never cut below one pixel
+        let (w, h) = proxy_size(100_000, 1, 100).unwrap();
+        assert!(w <= 100_000 && h == 1);
+    }
+}
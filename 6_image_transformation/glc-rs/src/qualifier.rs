@@ -0,0 +1,208 @@
+//! Qualifiers, which limit the color transformation to a region of colors.
+//!
+//! A qualifier is a box or an ellipsoid in RGB or HSL, with a soft edge. Each
+//! pixel is transformed in proportion to its matte, 1 inside the region and
+//! fading to 0 over `softness` outside of it. In HSL the hue wraps around, so
+//! a box centered on a hue is a hue range.
+
+use bevy::prelude::*;
+
+use crate::image::{RenderRequest, TransformImageEvent};
+use crate::kernels::Pixel;
+use crate::layout;
+use crate::parallel;
+
+/// Color space of a qualifier region.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QualifierSpace {
+    Rgb,
+    /// Hue, saturation and lightness, all in [0, 1]
+    Hsl,
+}
+
+impl QualifierSpace {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rgb" => Some(QualifierSpace::Rgb),
+            "hsl" => Some(QualifierSpace::Hsl),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QualifierShape {
+    Box,
+    Ellipsoid,
+}
+
+impl QualifierShape {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "box" => Some(QualifierShape::Box),
+            "ellipsoid" => Some(QualifierShape::Ellipsoid),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QualifierRegion {
+    pub space: QualifierSpace,
+    pub shape: QualifierShape,
+    pub center: Vec3,
+    /// Half size of the box, or radii of the ellipsoid, along each axis
+    pub extent: Vec3,
+    /// Width of the falloff, relative to the extent
+    pub softness: f32,
+}
+
+impl QualifierRegion {
+    /// How much of the transformation applies to a color, from 0 to 1.
+    pub fn matte(&self, rgb: Vec3) -> f32 {
+        let p = match self.space {
+            QualifierSpace::Rgb => rgb,
+            QualifierSpace::Hsl => layout::rgb_to_hsl(rgb),
+        };
+        let mut offset = p - self.center;
+        if self.space == QualifierSpace::Hsl {
+            offset.x = (offset.x + 0.5).rem_euclid(1.0) - 0.5;
+        }
+        let d = (offset / self.extent.max(Vec3::splat(f32::EPSILON))).abs();
+        let distance = match self.shape {
+            QualifierShape::Box => d.max_element(),
+            QualifierShape::Ellipsoid => d.length(),
+        };
+        if distance <= 1.0 {
+            1.0
+        } else if distance >= 1.0 + self.softness {
+            0.0
+        } else {
+            let t = (distance - 1.0) / self.softness;
+            1.0 - t * t * (3.0 - 2.0 * t)
+        }
+    }
+}
+
+/// Limits the color transformation of the output image to a region. Kept
+/// next to the output image.
+#[derive(Component, Clone, Debug, Default)]
+pub struct Qualifier {
+    pub region: Option<QualifierRegion>,
+    /// Render the matte in grayscale instead of the output
+    pub show_matte: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct SetQualifierEvent {
+    pub region: Option<QualifierRegion>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SetShowMatteEvent {
+    pub enabled: bool,
+}
+
+/// Transforms the pixels in `src` into `dst` by `rotation`, each in
+/// proportion to its matte if there is a region.
+pub fn transform(
+    region: Option<&QualifierRegion>,
+    rotation: &Mat3,
+    src: &[Pixel],
+    dst: &mut [Pixel],
+) {
+    match region {
+        Some(region) => {
+            let matte = |c: &Pixel| region.matte(Vec3::new(c[0], c[1], c[2]));
+            parallel::transform_blended(rotation, src, dst, &matte);
+        }
+        None => parallel::transform(rotation, src, dst),
+    }
+}
+
+/// Grayscale matte of the pixels, keeping alpha.
+pub fn matte_pixels(region: &QualifierRegion, src: &[Pixel]) -> Vec<Pixel> {
+    src.iter()
+        .map(|c| {
+            let m = region.matte(Vec3::new(c[0], c[1], c[2]));
+            [m, m, m, c[3]]
+        })
+        .collect()
+}
+
+pub fn set_qualifier(
+    mut events: EventReader<SetQualifierEvent>,
+    mut matte_events: EventReader<SetShowMatteEvent>,
+    mut query: Query<&mut Qualifier>,
+    mut out_image_events: EventWriter<TransformImageEvent>,
+    mut out_render_events: EventWriter<RenderRequest>,
+) {
+    let evts = events.iter().collect::<Vec<_>>();
+    let matte_evts = matte_events.iter().collect::<Vec<_>>();
+    if let Some(mut qualifier) = query.iter_mut().last() {
+        if let Some(evt) = evts.into_iter().last() {
+            qualifier.region = evt.region;
+            out_image_events.send(TransformImageEvent { interactive: true });
+        }
+        if let Some(evt) = matte_evts.into_iter().last() {
+            qualifier.show_matte = evt.enabled;
+            out_render_events.send(RenderRequest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn greens() -> QualifierRegion {
+        QualifierRegion {
+            space: QualifierSpace::Hsl,
+            shape: QualifierShape::Box,
+            center: Vec3::new(1.0 / 3.0, 0.5, 0.5),
+            extent: Vec3::new(0.1, 0.5, 0.5),
+            softness: 0.5,
+        }
+    }
+
+    #[test]
+    fn hsl_box_selects_a_hue_range() {
+        let region = greens();
+        assert_eq!(region.matte(Vec3::new(0.2, 0.8, 0.2)), 1.0);
+        assert_eq!(region.matte(Vec3::new(0.8, 0.2, 0.2)), 0.0);
+        // Yellowish green is in the falloff
+        let m = region.matte(Vec3::new(0.6, 0.8, 0.2));
+        assert!(m > 0.0 && m < 1.0, "{m}");
+    }
+
+    #[test]
+    fn hue_wraps_around() {
+        let region = QualifierRegion {
+            center: Vec3::new(0.0, 0.5, 0.5),
+            ..greens()
+        };
+        // Red on both sides of hue 0
+        assert_eq!(region.matte(Vec3::new(0.8, 0.2, 0.25)), 1.0);
+        assert_eq!(region.matte(Vec3::new(0.8, 0.25, 0.2)), 1.0);
+    }
+
+    #[test]
+    fn transformation_is_blended_by_the_matte() {
+        let region = QualifierRegion {
+            space: QualifierSpace::Rgb,
+            shape: QualifierShape::Ellipsoid,
+            center: Vec3::ZERO,
+            extent: Vec3::splat(0.5),
+            softness: 0.0,
+        };
+        let src = [[0.1, 0.1, 0.1, 1.0], [0.9, 0.9, 0.9, 0.5]];
+        let mut dst = [[0.0; 4]; 2];
+        // Collapses every color to mid gray
+        transform(Some(&region), &Mat3::ZERO, &src, &mut dst);
+        assert_eq!(dst, [[0.5, 0.5, 0.5, 1.0], [0.9, 0.9, 0.9, 0.5]]);
+        assert_eq!(
+            matte_pixels(&region, &src),
+            vec![[1.0, 1.0, 1.0, 1.0], [0.0, 0.0, 0.0, 0.5]]
+        );
+    }
+}
@@ -4,7 +4,7 @@ use bevy::ecs::system::lifetimeless::{Read, SQuery, SRes};
 use bevy::ecs::system::SystemParamItem;
 use bevy::pbr::{MeshPipeline, MeshPipelineKey, SetMeshBindGroup, SetMeshViewBindGroup};
 use bevy::prelude::*;
-use bevy::render::mesh::GpuBufferInfo;
+use bevy::render::mesh::{GpuBufferInfo, Indices};
 use bevy::render::render_asset::RenderAssets;
 use bevy::render::render_component::{ExtractComponent, ExtractComponentPlugin};
 use bevy::render::render_phase::{
@@ -17,12 +17,15 @@ use bevy::render::view::ExtractedView;
 use bevy::render::{RenderApp, RenderStage};
 use bytemuck::{Pod, Zeroable};
 
+use crate::clipping::{Clipped, Clipping};
+
 pub struct GlcRenderingPlugin;
 
 impl Plugin for GlcRenderingPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugin(ExtractComponentPlugin::<InstancedMesh>::default());
+        app.add_plugin(ExtractComponentPlugin::<Translucent>::default());
         app.sub_app_mut(RenderApp)
+            .add_system_to_stage(RenderStage::Extract, extract_instanced_meshes)
             .add_render_command::<Transparent3d, GlcDraw>()
             .init_resource::<GlcRenderingPipeline>()
             .init_resource::<SpecializedPipelines<GlcRenderingPipeline>>()
@@ -36,23 +39,101 @@ impl Plugin for GlcRenderingPlugin {
  */
 #[derive(Component, Debug)]
 pub struct InstancedMesh(pub Vec<InstanceData>);
-impl ExtractComponent for InstancedMesh {
-    type Query = &'static InstancedMesh;
-    type Filter = ();
 
-    fn extract_component(item: QueryItem<Self::Query>) -> Self {
-        InstancedMesh(item.0.clone())
+/// Copies the instances to the render world, leaving out the ones of
+/// `Clipped` entities that fall outside the clipping planes.
+fn extract_instanced_meshes(
+    mut commands: Commands,
+    mut previous_len: Local<usize>,
+    clipping: Res<Clipping>,
+    query: Query<(Entity, &InstancedMesh, Option<&Clipped>)>,
+) {
+    let mut values = Vec::with_capacity(*previous_len);
+    for (entity, instanced_mesh, clipped) in query.iter() {
+        let instances = if clipped.is_some() && clipping.is_enabled() {
+            instanced_mesh
+                .0
+                .iter()
+                .filter(|instance| clipping.contains(instance.position))
+                .copied()
+                .collect()
+        } else {
+            instanced_mesh.0.clone()
+        };
+        values.push((entity, (InstancedMesh(instances),)));
     }
+    *previous_len = values.len();
+    commands.insert_or_spawn_batch(values);
 }
 
 #[derive(Clone, Copy, Debug, Pod, Zeroable)]
 #[repr(C)]
 pub struct InstanceData {
     pub position: Vec3,
-    pub scale: f32,
+    /// Scale along each axis. Negative scales mirror the mesh, which lets a
+    /// `segment_mesh` instance point in any direction.
+    pub scale: Vec3,
     pub color: [f32; 4],
 }
 
+/// Alpha blends the instances of an entity, without writing depth.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct Translucent;
+impl ExtractComponent for Translucent {
+    type Query = &'static Translucent;
+    type Filter = ();
+
+    fn extract_component(_item: QueryItem<Self::Query>) -> Self {
+        Translucent
+    }
+}
+
+/// Line from the origin to (1, 1, 1). Instanced with `scale` set to the
+/// difference of the end points, it draws a segment between them.
+pub fn segment_mesh() -> Mesh {
+    line_mesh(vec![Vec3::ZERO, Vec3::ONE], vec![0, 1])
+}
+
+/// Edges of a unit cube centered at the origin, the outline of a bin.
+pub fn wire_cube_mesh() -> Mesh {
+    let corners = (0..8)
+        .map(|i| Vec3::new((i & 1) as f32, ((i >> 1) & 1) as f32, (i >> 2) as f32) - 0.5)
+        .collect();
+    let mut indices = vec![];
+    for i in 0..8u32 {
+        for bit in [1, 2, 4] {
+            if i & bit == 0 {
+                indices.extend([i, i | bit]);
+            }
+        }
+    }
+    line_mesh(corners, indices)
+}
+
+/// Line list through `points`, two indices per line.
+pub fn line_mesh(points: Vec<Vec3>, indices: Vec<u32>) -> Mesh {
+    indexed_mesh(PrimitiveTopology::LineList, points, indices)
+}
+
+/// Triangle list through `points`, three indices per triangle, wound
+/// counterclockwise.
+pub fn triangle_mesh(points: Vec<Vec3>, indices: Vec<u32>) -> Mesh {
+    indexed_mesh(PrimitiveTopology::TriangleList, points, indices)
+}
+
+fn indexed_mesh(topology: PrimitiveTopology, points: Vec<Vec3>, indices: Vec<u32>) -> Mesh {
+    let mut mesh = Mesh::new(topology);
+    // The mesh pipeline expects normals and UVs, even though they go unused
+    let normals = vec![[0.0, 0.0, 0.0]; points.len()];
+    let uvs = vec![[0.0, 0.0]; points.len()];
+    let positions = points.into_iter().map(|p| p.to_array()).collect::<Vec<_>>();
+    mesh.set_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.set_attribute(Mesh::ATTRIBUTE_NORMAL, normals);
+    mesh.set_attribute(Mesh::ATTRIBUTE_UV_0, uvs);
+    mesh.set_indices(Some(Indices::U32(indices)));
+    mesh
+}
+
 /*
  * Custom rendering pipeline for simple instanced meshes
  */
@@ -93,15 +174,20 @@ impl SpecializedPipeline for GlcRenderingPipeline {
             attributes: vec![
                 // Position, Normal and UV take up locations 0-2
                 VertexAttribute {
-                    format: VertexFormat::Float32x4,
+                    format: VertexFormat::Float32x3,
                     offset: 0,
                     shader_location: 3,
                 },
                 VertexAttribute {
-                    format: VertexFormat::Float32x4,
-                    offset: VertexFormat::Float32x4.size(),
+                    format: VertexFormat::Float32x3,
+                    offset: VertexFormat::Float32x3.size(),
                     shader_location: 4,
                 },
+                VertexAttribute {
+                    format: VertexFormat::Float32x4,
+                    offset: 2 * VertexFormat::Float32x3.size(),
+                    shader_location: 5,
+                },
             ],
         });
         descriptor.fragment.as_mut().unwrap().shader = self.shader.clone();
@@ -145,7 +231,11 @@ impl EntityRenderCommand for GlcDrawmMeshInstanced {
         pass: &mut TrackedRenderPass<'w>,
     ) -> RenderCommandResult {
         let mesh_handle = mesh_query.get(item).unwrap();
-        let instance_buffer = instance_buffer_query.get(item).unwrap();
+        // Entities without instances have no buffer
+        let instance_buffer = match instance_buffer_query.get(item) {
+            Ok(instance_buffer) => instance_buffer,
+            Err(_) => return RenderCommandResult::Failure,
+        };
 
         let gpu_mesh = match meshes.into_inner().get(mesh_handle) {
             Some(gpu_mesh) => gpu_mesh,
@@ -164,7 +254,7 @@ impl EntityRenderCommand for GlcDrawmMeshInstanced {
                 pass.draw_indexed(0..*count, 0, 0..instance_buffer.length as u32);
             }
             GpuBufferInfo::NonIndexed { vertex_count } => {
-                pass.draw_indexed(0..*vertex_count, 0, 0..instance_buffer.length as u32);
+                pass.draw(0..*vertex_count, 0..instance_buffer.length as u32);
             }
         }
         RenderCommandResult::Success
@@ -180,12 +270,15 @@ fn prepare_instance_buffer(
     render_device: Res<RenderDevice>,
 ) {
     for (entity, instanced_mesh) in query.iter() {
+        commands.entity(entity).remove::<InstanceBuffer>();
+        if instanced_mesh.0.is_empty() {
+            continue;
+        }
         let buffer = render_device.create_buffer_with_data(&BufferInitDescriptor {
             label: Some("instance buffer"),
             contents: bytemuck::cast_slice(instanced_mesh.0.as_slice()),
             usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
         });
-        commands.entity(entity).remove::<InstanceBuffer>();
         commands.entity(entity).insert(InstanceBuffer {
             buffer,
             length: instanced_mesh.0.len(),
@@ -196,27 +289,38 @@ fn prepare_instance_buffer(
 /*
  * Custom render queue
  */
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
 fn glc_render_queue(
     draw_functions: Res<DrawFunctions<Transparent3d>>,
     rendering_pipeline: Res<GlcRenderingPipeline>,
     msaa: Res<Msaa>,
+    meshes: Res<RenderAssets<Mesh>>,
     mut pipelines: ResMut<SpecializedPipelines<GlcRenderingPipeline>>,
     mut pipeline_cache: ResMut<RenderPipelineCache>,
-    query: Query<Entity, (With<Handle<Mesh>>, With<InstancedMesh>)>,
+    query: Query<(Entity, &Handle<Mesh>, Option<&Translucent>), With<InstancedMesh>>,
     mut views: Query<(&ExtractedView, &mut RenderPhase<Transparent3d>)>,
 ) {
     let draw_function = draw_functions.read().get_id::<GlcDraw>().unwrap();
-    let key = MeshPipelineKey::from_msaa_samples(msaa.samples)
-        | MeshPipelineKey::from_primitive_topology(PrimitiveTopology::TriangleList);
-    let pipeline = pipelines.specialize(&mut pipeline_cache, &rendering_pipeline, key);
+    let msaa_key = MeshPipelineKey::from_msaa_samples(msaa.samples);
 
     for (_view, mut phase) in views.iter_mut() {
-        for entity in query.iter() {
+        for (entity, mesh_handle, translucent) in query.iter() {
+            let gpu_mesh = match meshes.get(mesh_handle) {
+                Some(gpu_mesh) => gpu_mesh,
+                None => continue,
+            };
+            let mut key =
+                msaa_key | MeshPipelineKey::from_primitive_topology(gpu_mesh.primitive_topology);
+            if translucent.is_some() {
+                key |= MeshPipelineKey::TRANSPARENT_MAIN_PASS;
+            }
+            let pipeline = pipelines.specialize(&mut pipeline_cache, &rendering_pipeline, key);
             phase.add(Transparent3d {
                 entity,
                 pipeline,
                 draw_function,
-                distance: 0.0,
+                // Translucent entities are drawn after the opaque ones
+                distance: if translucent.is_some() { 1.0 } else { 0.0 },
             });
         }
     }
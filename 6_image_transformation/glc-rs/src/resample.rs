@@ -0,0 +1,183 @@
+use std::ops::Range;
+
+use crate::image::{Image, PixelRect};
+
+const LANCZOS_LOBES: f32 = 3.0;
+
+fn lanczos(x: f32) -> f32 {
+    if x == 0.0 {
+        return 1.0;
+    }
+    if x.abs() >= LANCZOS_LOBES {
+        return 0.0;
+    }
+    let px = std::f32::consts::PI * x;
+    LANCZOS_LOBES * px.sin() * (px / LANCZOS_LOBES).sin() / (px * px)
+}
+
+/// Normalized filter weights for one destination sample along an axis.
+struct Taps {
+    start: usize,
+    weights: Vec<f32>,
+}
+
+fn compute_taps(src_len: u32, dst_len: u32) -> Vec<Taps> {
+    let scale = src_len as f32 / dst_len as f32;
+    // Widen the kernel when downsampling so that it also acts as a low-pass filter
+    let filter_scale = scale.max(1.0);
+    let support = LANCZOS_LOBES * filter_scale;
+
+    (0..dst_len)
+        .map(|i| {
+            let center = (i as f32 + 0.5) * scale;
+            let start = (center - support).floor().max(0.0) as usize;
+            let end = ((center + support).ceil() as usize).min(src_len as usize);
+            let mut weights = (start..end)
+                .map(|j| lanczos((j as f32 + 0.5 - center) / filter_scale))
+                .collect::<Vec<_>>();
+            let sum: f32 = weights.iter().sum();
+            if sum != 0.0 {
+                for w in weights.iter_mut() {
+                    *w /= sum;
+                }
+            }
+            Taps { start, weights }
+        })
+        .collect()
+}
+
+/// Destination samples whose taps overlap the `src` range.
+fn affected(taps: &[Taps], src: Range<usize>) -> Range<usize> {
+    let overlaps = |t: &Taps| t.start < src.end && src.start < t.start + t.weights.len();
+    match taps.iter().position(overlaps) {
+        Some(start) => start..taps.iter().rposition(overlaps).unwrap() + 1,
+        None => 0..0,
+    }
+}
+
+/// Resizes `image` with a separable Lanczos-3 filter.
+pub fn resample(image: &Image, width: u32, height: u32) -> Image {
+    let mut resized = Image {
+        width,
+        height,
+        data: vec![[0.0; 4]; width as usize * height as usize],
+    };
+    let region = PixelRect {
+        x: 0,
+        y: 0,
+        width: image.width,
+        height: image.height,
+    };
+    resample_region(image, &mut resized, region);
+    resized
+}
+
+/// Recomputes the pixels of `resized` that depend on `region` of `image`.
+pub fn resample_region(image: &Image, resized: &mut Image, region: PixelRect) {
+    let x_taps = compute_taps(image.width, resized.width);
+    let y_taps = compute_taps(image.height, resized.height);
+    let cols = affected(&x_taps, region.columns());
+    let rows = affected(&y_taps, region.rows());
+    if cols.is_empty() || rows.is_empty() {
+        return;
+    }
+    let last = &y_taps[rows.end - 1];
+    let src_rows = y_taps[rows.start].start..last.start + last.weights.len();
+    let src_width = image.width as usize;
+    let num_cols = cols.len();
+
+    // Horizontal pass over the source rows read by the affected destination rows
+    let mut tmp = Vec::with_capacity(src_rows.len() * num_cols);
+    for y in src_rows.clone() {
+        let row = &image.data[y * src_width..(y + 1) * src_width];
+        for taps in x_taps[cols.clone()].iter() {
+            let mut acc = [0.0; 4];
+            for (k, w) in taps.weights.iter().enumerate() {
+                let c = row[taps.start + k];
+                for (a, v) in acc.iter_mut().zip(c) {
+                    *a += w * v;
+                }
+            }
+            tmp.push(acc);
+        }
+    }
+
+    // Vertical pass into the affected destination pixels
+    let dst_width = resized.width as usize;
+    for (y, taps) in y_taps[rows.clone()].iter().enumerate() {
+        let y = rows.start + y;
+        for x in 0..num_cols {
+            let mut acc = [0.0; 4];
+            for (k, w) in taps.weights.iter().enumerate() {
+                let c = tmp[(taps.start + k - src_rows.start) * num_cols + x];
+                for (a, v) in acc.iter_mut().zip(c) {
+                    *a += w * v;
+                }
+            }
+            // Lanczos rings around sharp edges, so keep the result in range
+            resized.data[y * dst_width + cols.start + x] = acc.map(|x| x.clamp(0.0, 1.0));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_image(width: u32, height: u32, seed: u32) -> Image {
+        let data = (0..width * height)
+            .map(|i| {
+                let v = |k: u32| ((i * 7919 + k * 104_729 + seed) % 97) as f32 / 96.0;
+                [v(0), v(1), v(2), 1.0]
+            })
+            .collect();
+        Image {
+            width,
+            height,
+            data,
+        }
+    }
+
+    #[test]
+    fn taps_are_normalized_and_in_range() {
+        for (src_len, dst_len) in [(100, 37), (37, 100), (10, 10), (7, 3), (1, 5)] {
+            let taps = compute_taps(src_len, dst_len);
+            assert_eq!(taps.len(), dst_len as usize);
+            for t in taps.iter() {
+                let sum = t.weights.iter().sum::<f32>();
+                assert!((sum - 1.0).abs() < 1e-5, "{src_len} -> {dst_len}: {sum}");
+                assert!(t.start + t.weights.len() <= src_len as usize);
+            }
+        }
+    }
+
+    #[test]
+    fn regions_match_a_full_resample() {
+        let image = test_image(40, 30, 0);
+        let mut resized = resample(&image, 17, 11);
+
+        // Replace a block of the image, and resample only what it reaches
+        let region = PixelRect {
+            x: 12,
+            y: 20,
+            width: 9,
+            height: 6,
+        };
+        let mut edited = image;
+        let patch = test_image(region.width, region.height, 5);
+        for (span, row) in region
+            .spans(edited.width)
+            .zip(patch.data.chunks(region.width as usize))
+        {
+            edited.data[span].copy_from_slice(row);
+        }
+        resample_region(&edited, &mut resized, region);
+
+        let expected = resample(&edited, 17, 11);
+        for (a, b) in resized.data.iter().zip(&expected.data) {
+            for k in 0..4 {
+                assert!((a[k] - b[k]).abs() < 1e-6, "{a:?} {b:?}");
+            }
+        }
+    }
+}
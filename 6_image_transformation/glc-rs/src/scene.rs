@@ -1,21 +1,77 @@
 use bevy::prelude::*;
 
+use crate::binning::{BinColor, BinPosition};
 use crate::color_cube;
+use crate::comparison;
+use crate::gamut;
+use crate::gizmo;
+use crate::hull;
 use crate::image;
+use crate::isosurface;
+use crate::keyer;
+use crate::lattice;
+use crate::layout::Layout;
+use crate::normalization::Normalization;
+use crate::picking;
+use crate::point_cloud;
+use crate::probe;
+use crate::proxy;
+use crate::qualifier;
+use crate::render;
+use crate::selection;
+use crate::smoothing::Smoothing;
 
 const RESOLUTION: u32 = 32;
 const SIZE: f32 = 10.0;
-const THRESHOLD: f32 = 0.001;
 
 pub fn create_scene(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>) {
-    commands.spawn_bundle((image::Image::default(), image::Input));
+    commands.spawn_bundle((
+        image::Image::default(),
+        image::Input,
+        proxy::Proxy::default(),
+    ));
 
     commands.spawn_bundle((
         image::Image::default(),
         image::Output::default(),
         image::ColorTransformation::default(),
+        selection::Selection::default(),
+        qualifier::Qualifier::default(),
+        keyer::Keyer::default(),
+        keyer::Keyed::default(),
     ));
 
     let mesh = meshes.add(Mesh::from(shape::Cube { size: 1.0 }));
-    color_cube::create_color_cube(commands, RESOLUTION, mesh, SIZE, THRESHOLD);
+    let cube = color_cube::ColorCube {
+        resolution: UVec3::splat(RESOLUTION),
+        layout: Layout::default(),
+        normalization: Normalization::default(),
+        position: BinPosition::default(),
+        color: BinColor::default(),
+        smoothing: Smoothing::default(),
+    };
+    let transform = color_cube::cube_transform(SIZE);
+    point_cloud::create_point_cloud(&mut commands, mesh.clone(), transform);
+    let segment_mesh = meshes.add(render::segment_mesh());
+    comparison::create_comparison(&mut commands, mesh.clone(), segment_mesh.clone(), transform);
+    lattice::create_lattice(&mut commands, mesh.clone(), segment_mesh.clone(), transform);
+    let wire_mesh = meshes.add(render::wire_cube_mesh());
+    picking::create_highlight(&mut commands, wire_mesh.clone(), transform);
+    gizmo::create_gizmo(
+        &mut commands,
+        mesh.clone(),
+        wire_mesh.clone(),
+        segment_mesh.clone(),
+        transform,
+    );
+    probe::create_probe_marker(&mut commands, wire_mesh.clone(), segment_mesh, transform);
+    let sphere_mesh = meshes.add(Mesh::from(shape::Icosphere {
+        radius: 0.5,
+        subdivisions: 3,
+    }));
+    selection::create_selection_outline(&mut commands, wire_mesh, sphere_mesh, transform);
+    hull::create_gamut_hull(&mut commands, &mut meshes, transform);
+    gamut::create_gamut_outlines(&mut commands, &mut meshes, transform);
+    isosurface::create_isosurfaces(&mut commands, &mut meshes, transform);
+    color_cube::create_color_cube(commands, cube, mesh, transform, Visibility::default());
 }
@@ -0,0 +1,333 @@
+use bevy::prelude::*;
+use bevy::render::camera::{ActiveCameras, Camera, CameraPlugin};
+
+use crate::color_cube::{ColorCube, CubeSpace};
+use crate::image::RenderRequest;
+use crate::kernels::Pixel;
+use crate::layout::Layout;
+use crate::picking;
+use crate::render::{InstanceData, InstancedMesh, Translucent};
+
+/// Brightness of the pixels outside the selection, in the rendered output.
+const UNSELECTED_DIM: f32 = 0.25;
+const OUTLINE_ALPHA: f32 = 0.25;
+
+/// Region of the cube's unit space, where RGB colors are their own
+/// coordinates and other layouts are drawn as they appear in the view.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ColorVolume {
+    Box {
+        min: Vec3,
+        max: Vec3,
+    },
+    Sphere {
+        center: Vec3,
+        radius: f32,
+    },
+    /// Polygon drawn over the view, extruded along the view direction.
+    Lasso {
+        /// Vertices in normalized device coordinates
+        polygon: Vec<Vec2>,
+        /// From the cube's unit space to normalized device coordinates, as
+        /// the view was when the lasso was drawn
+        to_ndc: Mat4,
+    },
+}
+
+impl ColorVolume {
+    pub fn contains(&self, p: Vec3) -> bool {
+        match self {
+            ColorVolume::Box { min, max } => p.cmpge(*min).all() && p.cmple(*max).all(),
+            ColorVolume::Sphere { center, radius } => {
+                p.distance_squared(*center) <= radius * radius
+            }
+            ColorVolume::Lasso { polygon, to_ndc } => {
+                let clip = *to_ndc * p.extend(1.0);
+                // Points behind the camera don't project onto the view
+                clip.w > 0.0 && polygon_contains(polygon, clip.truncate().truncate() / clip.w)
+            }
+        }
+    }
+}
+
+/// Even-odd test of whether `p` is inside the polygon.
+pub fn polygon_contains(polygon: &[Vec2], p: Vec2) -> bool {
+    let mut inside = false;
+    for (i, a) in polygon.iter().enumerate() {
+        let b = polygon[(i + 1) % polygon.len()];
+        if (a.y > p.y) != (b.y > p.y) && p.x < a.x + (p.y - a.y) * (b.x - a.x) / (b.y - a.y) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/// Which color of each pixel is tested against the selection.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelectionSource {
+    Input,
+    Output,
+}
+
+impl SelectionSource {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "input" => Some(SelectionSource::Input),
+            "output" => Some(SelectionSource::Output),
+            _ => None,
+        }
+    }
+}
+
+/// Selected volume of colors, whose pixels are highlighted in the output
+/// canvas. Kept next to the output image.
+#[derive(Component, Clone, Debug)]
+pub struct Selection {
+    pub volume: Option<ColorVolume>,
+    pub source: SelectionSource,
+}
+
+impl Default for Selection {
+    fn default() -> Self {
+        Selection {
+            volume: None,
+            source: SelectionSource::Output,
+        }
+    }
+}
+
+/// Selection as requested from JavaScript.
+#[derive(Clone, Debug)]
+pub enum SelectionShape {
+    Box {
+        min: Vec3,
+        max: Vec3,
+    },
+    Sphere {
+        center: Vec3,
+        radius: f32,
+    },
+    /// Vertices in canvas pixels, from the top left corner
+    Lasso {
+        points: Vec<Vec2>,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct SetSelectionEvent {
+    pub shape: Option<SelectionShape>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SetSelectionSourceEvent {
+    pub source: SelectionSource,
+}
+
+#[derive(Component)]
+pub struct SelectionBox;
+
+#[derive(Component)]
+pub struct SelectionSphere;
+
+/// Darkens the pixels whose colors are outside the volume. `rgba` holds the
+/// 8 bit pixels being rendered, `colors` the colors to test for each.
+pub fn dim_unselected(rgba: &mut [u8], colors: &[Pixel], volume: &ColorVolume, layout: Layout) {
+    for (pixel, c) in rgba.chunks_exact_mut(4).zip(colors.iter()) {
+        let p = layout.position(layout.coords(Vec3::new(c[0], c[1], c[2])));
+        if !volume.contains(p) {
+            for x in &mut pixel[..3] {
+                *x = (*x as f32 * UNSELECTED_DIM) as u8;
+            }
+        }
+    }
+}
+
+pub fn create_selection_outline(
+    commands: &mut Commands,
+    wire_mesh: Handle<Mesh>,
+    sphere_mesh: Handle<Mesh>,
+    transform: Transform,
+) {
+    commands.spawn_bundle((
+        transform,
+        GlobalTransform::identity(),
+        wire_mesh,
+        InstancedMesh(vec![]),
+        SelectionBox,
+        CubeSpace,
+        Visibility::default(),
+        ComputedVisibility::default(),
+    ));
+    commands.spawn_bundle((
+        transform,
+        GlobalTransform::identity(),
+        sphere_mesh,
+        InstancedMesh(vec![]),
+        SelectionSphere,
+        Translucent,
+        CubeSpace,
+        Visibility::default(),
+        ComputedVisibility::default(),
+    ));
+}
+
+/// Sets the selection, projecting lassos into the cube's unit space through
+/// the current view.
+#[allow(clippy::too_many_arguments)]
+pub fn set_selection(
+    mut events: EventReader<SetSelectionEvent>,
+    mut source_events: EventReader<SetSelectionSourceEvent>,
+    windows: Res<Windows>,
+    active_cameras: Res<ActiveCameras>,
+    camera_query: Query<(&Camera, &GlobalTransform)>,
+    cube_query: Query<&GlobalTransform, With<ColorCube>>,
+    mut query: Query<&mut Selection>,
+    mut out_events: EventWriter<RenderRequest>,
+) {
+    let evts = events.iter().collect::<Vec<_>>();
+    let source_evts = source_events.iter().collect::<Vec<_>>();
+    if let Some(mut selection) = query.iter_mut().last() {
+        if let Some(evt) = source_evts.into_iter().last() {
+            selection.source = evt.source;
+            out_events.send(RenderRequest);
+        }
+        if let Some(evt) = evts.into_iter().last() {
+            selection.volume = match &evt.shape {
+                None => None,
+                Some(SelectionShape::Box { min, max }) => Some(ColorVolume::Box {
+                    min: min.min(*max),
+                    max: max.max(*min),
+                }),
+                Some(SelectionShape::Sphere { center, radius }) => Some(ColorVolume::Sphere {
+                    center: *center,
+                    radius: radius.abs(),
+                }),
+                Some(SelectionShape::Lasso { points }) => {
+                    let window = windows.get_primary();
+                    let camera = active_cameras
+                        .get(CameraPlugin::CAMERA_3D)
+                        .and_then(|camera| camera.entity)
+                        .and_then(|entity| camera_query.get(entity).ok());
+                    let cube_transform = cube_query.iter().last();
+                    match (window, camera, cube_transform) {
+                        (Some(window), Some((camera, camera_transform)), Some(cube_transform)) => {
+                            let canvas_size = Vec2::new(window.width(), window.height());
+                            Some(ColorVolume::Lasso {
+                                polygon: points
+                                    .iter()
+                                    .map(|&p| picking::canvas_to_ndc(canvas_size, p))
+                                    .collect(),
+                                to_ndc: camera.projection_matrix
+                                    * camera_transform.compute_matrix().inverse()
+                                    * cube_transform.compute_matrix(),
+                            })
+                        }
+                        _ => None,
+                    }
+                }
+            };
+            out_events.send(RenderRequest);
+        }
+    }
+}
+
+/// Draws boxes as a wireframe and spheres translucent. Lassos are only seen
+/// where they were drawn.
+pub fn update_selection_outline(
+    query: Query<&Selection, Changed<Selection>>,
+    mut box_query: Query<&mut InstancedMesh, (With<SelectionBox>, Without<SelectionSphere>)>,
+    mut sphere_query: Query<&mut InstancedMesh, (With<SelectionSphere>, Without<SelectionBox>)>,
+) {
+    if let Some(selection) = query.iter().last() {
+        let (boxes, spheres) = match selection.volume {
+            Some(ColorVolume::Box { min, max }) => (
+                vec![InstanceData {
+                    position: (min + max) / 2.0,
+                    scale: max - min,
+                    color: Color::WHITE.as_rgba_f32(),
+                }],
+                vec![],
+            ),
+            Some(ColorVolume::Sphere { center, radius }) => (
+                vec![],
+                vec![InstanceData {
+                    position: center,
+                    scale: Vec3::splat(2.0 * radius),
+                    color: Color::rgba(1.0, 1.0, 1.0, OUTLINE_ALPHA).as_rgba_f32(),
+                }],
+            ),
+            _ => (vec![], vec![]),
+        };
+        for mut mesh in box_query.iter_mut() {
+            mesh.0 = boxes.clone();
+        }
+        for mut mesh in sphere_query.iter_mut() {
+            mesh.0 = spheres.clone();
+        }
+    }
+}
+
+/// Highlights the selection again when the layout moves the colors around.
+pub fn refresh_selection(
+    cube_query: Query<&ColorCube, Changed<ColorCube>>,
+    query: Query<&Selection>,
+    mut out_events: EventWriter<RenderRequest>,
+) {
+    if cube_query.iter().count() > 0 && query.iter().any(|s| s.volume.is_some()) {
+        out_events.send(RenderRequest);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn box_and_sphere_contain_their_colors() {
+        let volume = ColorVolume::Box {
+            min: Vec3::new(0.0, 0.5, 0.0),
+            max: Vec3::new(0.5, 1.0, 0.5),
+        };
+        assert!(volume.contains(Vec3::new(0.25, 0.75, 0.5)));
+        assert!(!volume.contains(Vec3::new(0.75, 0.75, 0.25)));
+
+        let volume = ColorVolume::Sphere {
+            center: Vec3::splat(0.5),
+            radius: 0.25,
+        };
+        assert!(volume.contains(Vec3::new(0.5, 0.7, 0.5)));
+        assert!(!volume.contains(Vec3::new(0.7, 0.7, 0.5)));
+    }
+
+    #[test]
+    fn lasso_is_extruded_along_the_view() {
+        let triangle = vec![
+            Vec2::new(0.0, 0.0),
+            Vec2::new(1.0, 0.0),
+            Vec2::new(0.0, 1.0),
+        ];
+        assert!(polygon_contains(&triangle, Vec2::new(0.25, 0.25)));
+        assert!(!polygon_contains(&triangle, Vec2::new(0.75, 0.75)));
+
+        // Looking down -z, depth doesn't matter
+        let volume = ColorVolume::Lasso {
+            polygon: triangle,
+            to_ndc: Mat4::IDENTITY,
+        };
+        assert!(volume.contains(Vec3::new(0.25, 0.25, 0.0)));
+        assert!(volume.contains(Vec3::new(0.25, 0.25, 1.0)));
+        assert!(!volume.contains(Vec3::new(0.75, 0.75, 0.5)));
+    }
+
+    #[test]
+    fn unselected_pixels_are_dimmed() {
+        let volume = ColorVolume::Sphere {
+            center: Vec3::ZERO,
+            radius: 0.5,
+        };
+        let colors = [[0.1, 0.1, 0.1, 1.0], [1.0, 1.0, 1.0, 1.0]];
+        let mut rgba = [100, 100, 100, 255, 200, 200, 200, 255];
+        dim_unselected(&mut rgba, &colors, &volume, Layout::Rgb);
+        assert_eq!(rgba, [100, 100, 100, 255, 50, 50, 50, 255]);
+    }
+}
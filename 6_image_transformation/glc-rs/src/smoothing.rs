@@ -0,0 +1,463 @@
+//! Kernel density smoothing of the histogram.
+//!
+//! At high resolutions most bins hold few pixels, and their counts are noisy.
+//! Smoothing spreads each count over the neighbouring bins with a kernel, as a
+//! separable convolution: one pass along each axis of the layout's
+//! coordinates. Near the sides of the cube the kernel is cut and its weights
+//! renormalized, so that the bins there aren't thinned out. The hue axis of
+//! the polar layouts wraps around instead.
+//!
+//! Each pass runs over lines of bins along its axis. At the largest
+//! resolutions the passes take longer than a frame, so `DensityJob` runs them
+//! a chunk of lines at a time, and kernels are cut at `MAX_RADIUS` bins.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use bevy::math::UVec3;
+
+use crate::binning;
+use crate::processing::{FrameBudget, Job, ProcessingProgressEvent, ProcessingStage};
+
+/// Gaussian kernels are cut at this many standard deviations.
+const GAUSSIAN_RADIUS: f32 = 3.0;
+/// Kernels reach at most this many bins to each side, which bounds a pass over
+/// the largest cubes to a few dozen multiply-adds per bin.
+pub const MAX_RADIUS: u32 = 16;
+
+/// Kernel the histogram counts are smoothed with. Bandwidths are in bins.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Smoothing {
+    /// Raw counts.
+    #[default]
+    None,
+    /// Gaussian with `bandwidth` as its standard deviation.
+    Gaussian { bandwidth: f32 },
+    /// Epanechnikov (parabolic), reaching zero `bandwidth` bins away.
+    Epanechnikov { bandwidth: f32 },
+}
+
+impl Smoothing {
+    /// `None` for unknown kernels, and for bandwidths that aren't finite and
+    /// positive.
+    pub fn from_name(name: &str, bandwidth: f32) -> Option<Self> {
+        let valid = bandwidth.is_finite() && bandwidth > 0.0;
+        match name {
+            "none" => Some(Smoothing::None),
+            "gaussian" if valid => Some(Smoothing::Gaussian { bandwidth }),
+            "epanechnikov" if valid => Some(Smoothing::Epanechnikov { bandwidth }),
+            _ => None,
+        }
+    }
+
+    /// Weights of the 1D kernel, from `-radius` to `radius` bins, summing to 1.
+    /// The radius is at most `max_radius`, and `MAX_RADIUS`, cutting wider
+    /// kernels.
+    fn weights(self, max_radius: u32) -> Vec<f32> {
+        let max_radius = max_radius.min(MAX_RADIUS) as f32;
+        let weights = match self {
+            Smoothing::None => vec![1.0],
+            Smoothing::Gaussian { bandwidth } if bandwidth > 0.0 => {
+                let radius = (GAUSSIAN_RADIUS * bandwidth).ceil().min(max_radius) as i32;
+                (-radius..=radius)
+                    .map(|k| (-0.5 * (k as f32 / bandwidth).powi(2)).exp())
+                    .collect()
+            }
+            Smoothing::Epanechnikov { bandwidth } if bandwidth > 1.0 => {
+                let radius = (bandwidth.ceil() - 1.0).min(max_radius) as i32;
+                (-radius..=radius)
+                    .map(|k| 1.0 - (k as f32 / bandwidth).powi(2))
+                    .collect()
+            }
+            _ => vec![1.0],
+        };
+        let sum = weights.iter().sum::<f32>();
+        weights.into_iter().map(|w| w / sum).collect()
+    }
+
+    /// Kernel along `axis` of a cube, `None` if it leaves the counts alone.
+    fn axis_kernel(self, resolution: UVec3, axis: usize, wrap_hue: bool) -> Option<AxisKernel> {
+        let size = resolution[axis] as i32;
+        // Bins farther away than the side of the cube are never in range
+        let weights = self.weights(resolution[axis] - 1);
+        if weights.len() == 1 {
+            return None;
+        }
+        let mut kernel = AxisKernel {
+            weights,
+            totals: vec![],
+            size,
+            wraps: wrap_hue && axis == 0,
+        };
+        kernel.totals = (0..size)
+            .map(|n| {
+                (-kernel.radius()..=kernel.radius())
+                    .zip(&kernel.weights)
+                    .filter(|&(k, _)| kernel.neighbour(n, k).is_some())
+                    .map(|(_, &w)| w)
+                    .sum()
+            })
+            .collect();
+        Some(kernel)
+    }
+
+    /// Smoothed counts of a histogram, still in pixels. With `wrap_hue`, the
+    /// first axis is periodic. `DensityJob` does the same across frames.
+    pub fn density(self, counts: &[u32], resolution: UVec3, wrap_hue: bool) -> Vec<f32> {
+        let mut density = counts.iter().map(|&c| c as f32).collect::<Vec<_>>();
+        for axis in 0..3 {
+            if let Some(kernel) = self.axis_kernel(resolution, axis, wrap_hue) {
+                let src = density.clone();
+                let lines = 0..density.len() / resolution[axis] as usize;
+                kernel.smooth_lines(&src, line_stride(resolution, axis), lines, &mut density);
+            }
+        }
+        density
+    }
+
+    /// Change of the density for a sparse change of the counts, given as bins
+    /// and the change of their counts. Smoothing is linear, so adding it to
+    /// the density of the old counts gives the density of the new ones.
+    /// `None` once the change spreads over an eighth of the bins, where
+    /// smoothing all the counts again is cheaper.
+    pub fn spread(
+        self,
+        changes: &[(usize, f32)],
+        resolution: UVec3,
+        wrap_hue: bool,
+    ) -> Option<Vec<(usize, f32)>> {
+        let max_bins = binning::num_bins(resolution) / 8;
+        let mut spread = changes.to_vec();
+        for axis in 0..3 {
+            let kernel = match self.axis_kernel(resolution, axis, wrap_hue) {
+                Some(kernel) => kernel,
+                None => continue,
+            };
+            let radius = kernel.radius();
+            let mut next = HashMap::new();
+            for &(i, value) in spread.iter() {
+                let coords = binning::index_to_coords(i, resolution);
+                for (k, &w) in (-radius..=radius).zip(&kernel.weights) {
+                    if let Some(neighbour) = kernel.neighbour(coords[axis] as i32, k) {
+                        let mut p = coords;
+                        p[axis] = neighbour as u32;
+                        let j = binning::coords_to_index(p, resolution);
+                        *next.entry(j).or_insert(0.0) += w * value / kernel.totals[neighbour];
+                    }
+                }
+            }
+            if next.len() > max_bins {
+                return None;
+            }
+            spread = next.into_iter().collect();
+        }
+        Some(spread)
+    }
+}
+
+/// Distance between neighbouring bins along `axis`, in bin indices.
+fn line_stride(resolution: UVec3, axis: usize) -> usize {
+    (axis + 1..3).map(|a| resolution[a] as usize).product()
+}
+
+/// The 1D kernel along one axis of the cube, cut at its sides.
+struct AxisKernel {
+    weights: Vec<f32>,
+    /// Weight in range around each bin of the axis, which its density is
+    /// divided by
+    totals: Vec<f32>,
+    size: i32,
+    wraps: bool,
+}
+
+impl AxisKernel {
+    fn radius(&self) -> i32 {
+        (self.weights.len() / 2) as i32
+    }
+
+    /// Bin `k` bins away from `n`, wrapping around or `None` past the sides.
+    fn neighbour(&self, n: i32, k: i32) -> Option<usize> {
+        let neighbour = n + k;
+        if self.wraps {
+            Some(neighbour.rem_euclid(self.size) as usize)
+        } else {
+            (0..self.size)
+                .contains(&neighbour)
+                .then_some(neighbour as usize)
+        }
+    }
+
+    /// Smooths the given lines of bins along the axis, whose neighbours are
+    /// `stride` apart, from `src` into `dst`. Line `l` starts at the `l % stride`th
+    /// bin of the `l / stride`th block of `size` lines.
+    fn smooth_lines(&self, src: &[f32], stride: usize, lines: Range<usize>, dst: &mut [f32]) {
+        let size = self.size as usize;
+        let radius = self.radius();
+        for l in lines {
+            let base = (l / stride) * stride * size + l % stride;
+            for n in 0..self.size {
+                let mut sum = 0.0;
+                for (k, &w) in (-radius..=radius).zip(&self.weights) {
+                    if let Some(m) = self.neighbour(n, k) {
+                        sum += w * src[base + m * stride];
+                    }
+                }
+                dst[base + n as usize * stride] = sum / self.totals[n as usize];
+            }
+        }
+    }
+}
+
+/// Smoothing of the histogram counts into their density, spread across
+/// frames a chunk of lines at a time, one axis after the other.
+#[derive(Default)]
+pub struct DensityJob {
+    job: Job,
+    /// Axis being smoothed, `None` when there is nothing to smooth
+    axis: Option<usize>,
+    smoothing: Smoothing,
+    resolution: UVec3,
+    wrap_hue: bool,
+    /// Density before the pass over the current axis
+    src: Vec<f32>,
+}
+
+impl DensityJob {
+    /// Starts smoothing `counts` into `density`, which holds the raw counts
+    /// until the first pass.
+    pub fn start(
+        &mut self,
+        smoothing: Smoothing,
+        counts: &[u32],
+        resolution: UVec3,
+        wrap_hue: bool,
+        density: &mut Vec<f32>,
+    ) {
+        density.clear();
+        density.extend(counts.iter().map(|&c| c as f32));
+        self.smoothing = smoothing;
+        self.resolution = resolution;
+        self.wrap_hue = wrap_hue;
+        self.begin(0, density);
+    }
+
+    pub fn cancel(&mut self) {
+        self.axis = None;
+        self.job.cancel();
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.axis.is_some()
+    }
+
+    pub fn progress(&self) -> ProcessingProgressEvent {
+        self.job.progress(ProcessingStage::Smoothing)
+    }
+
+    /// Starts the pass over `axis`, or the first axis after it that the kernel
+    /// smooths.
+    fn begin(&mut self, axis: usize, density: &[f32]) {
+        self.axis = (axis..3).find(|&a| {
+            self.smoothing
+                .axis_kernel(self.resolution, a, self.wrap_hue)
+                .is_some()
+        });
+        if let Some(axis) = self.axis {
+            self.src.clear();
+            self.src.extend_from_slice(density);
+            self.job
+                .start(density.len() / self.resolution[axis] as usize);
+        }
+    }
+
+    /// Smooths `density` until the frame budget runs out, with `chunk_size`
+    /// bins between checks of the budget. Returns true once it is done.
+    pub fn run(&mut self, density: &mut [f32], chunk_size: usize, budget: &FrameBudget) -> bool {
+        if density.len() != self.src.len() {
+            self.cancel();
+            return false;
+        }
+        while let Some(axis) = self.axis {
+            // Rebuilt rather than kept, as it is a few dozen weights
+            let kernel = self
+                .smoothing
+                .axis_kernel(self.resolution, axis, self.wrap_hue)
+                .unwrap();
+            let stride = line_stride(self.resolution, axis);
+            let chunk = chunk_size / self.resolution[axis] as usize;
+            let src = &self.src;
+            let finished = self.job.run_chunks(chunk, budget, |lines| {
+                kernel.smooth_lines(src, stride, lines, density)
+            });
+            if !finished {
+                return false;
+            }
+            self.begin(axis + 1, density);
+            if self.axis.is_none() {
+                return true;
+            }
+            if budget.exhausted() {
+                return false;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KERNELS: [Smoothing; 2] = [
+        Smoothing::Gaussian { bandwidth: 1.5 },
+        Smoothing::Epanechnikov { bandwidth: 2.5 },
+    ];
+
+    #[test]
+    fn kernels_are_normalized_and_peaked() {
+        for kernel in KERNELS {
+            let w = kernel.weights(u32::MAX);
+            assert_eq!(w.len() % 2, 1);
+            assert!((w.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+            let center = w.len() / 2;
+            assert!(w.iter().all(|&x| x > 0.0 && x <= w[center]));
+        }
+        assert_eq!(
+            Smoothing::Epanechnikov { bandwidth: 2.5 }
+                .weights(u32::MAX)
+                .len(),
+            5
+        );
+        // Too narrow to reach the neighbours
+        let narrow = Smoothing::Epanechnikov { bandwidth: 1.0 };
+        assert_eq!(narrow.weights(u32::MAX), [1.0]);
+        assert_eq!(
+            Smoothing::Gaussian { bandwidth: 0.0 }.weights(u32::MAX),
+            [1.0]
+        );
+    }
+
+    #[test]
+    fn bandwidths_are_validated_and_cut_to_the_cube() {
+        for bandwidth in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert_eq!(Smoothing::from_name("gaussian", bandwidth), None);
+            assert_eq!(Smoothing::from_name("epanechnikov", bandwidth), None);
+        }
+        assert_eq!(
+            Smoothing::from_name("none", f32::NAN),
+            Some(Smoothing::None)
+        );
+
+        let wide = Smoothing::Gaussian { bandwidth: 1e30 };
+        assert_eq!(wide.weights(3).len(), 7);
+        assert_eq!(wide.weights(u32::MAX).len(), 2 * MAX_RADIUS as usize + 1);
+        let resolution = UVec3::new(4, 2, 1);
+        let density = wide.density(&[10; 8], resolution, true);
+        assert!(density.iter().all(|&d| (d - 10.0).abs() < 1e-3));
+    }
+
+    #[test]
+    fn interior_counts_are_spread_and_preserved() {
+        let resolution = UVec3::splat(16);
+        let mut counts = vec![0; binning::num_bins(resolution)];
+        let center = binning::coords_to_index(UVec3::splat(8), resolution);
+        counts[center] = 1000;
+        assert_eq!(
+            Smoothing::None.density(&counts, resolution, false),
+            counts.iter().map(|&c| c as f32).collect::<Vec<_>>()
+        );
+        for kernel in KERNELS {
+            let density = kernel.density(&counts, resolution, false);
+            let total = density.iter().sum::<f32>();
+            assert!((total - 1000.0).abs() < 1.0, "{kernel:?} {total}");
+            assert!(density[center] < 1000.0);
+            let next = binning::coords_to_index(UVec3::new(9, 8, 8), resolution);
+            assert!(density[next] > 0.0);
+        }
+    }
+
+    #[test]
+    fn jobs_smooth_across_frames_like_density() {
+        let resolution = UVec3::new(6, 5, 7);
+        let counts = (0..binning::num_bins(resolution))
+            .map(|i| (i * 37 % 11) as u32)
+            .collect::<Vec<_>>();
+        // The default budget is already spent, so each run takes one chunk
+        let budget = FrameBudget::default();
+        for kernel in KERNELS {
+            let expected = kernel.density(&counts, resolution, true);
+            let mut job = DensityJob::default();
+            let mut density = vec![];
+            job.start(kernel, &counts, resolution, true, &mut density);
+            let mut runs = 0;
+            while !job.run(&mut density, 12, &budget) {
+                assert!(job.is_active());
+                runs += 1;
+            }
+            assert!(!job.is_active());
+            assert!(runs > 3);
+            for (a, b) in density.iter().zip(&expected) {
+                assert!((a - b).abs() < 1e-4, "{kernel:?} {a} {b}");
+            }
+        }
+
+        // Without smoothing the density is the counts right away
+        let mut job = DensityJob::default();
+        let mut density = vec![];
+        job.start(Smoothing::None, &counts, resolution, true, &mut density);
+        assert!(!job.is_active());
+        assert_eq!(
+            density,
+            counts.iter().map(|&c| c as f32).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn spread_changes_match_a_full_smoothing() {
+        let resolution = UVec3::new(16, 32, 32);
+        let num_bins = binning::num_bins(resolution);
+        let before = (0..num_bins).map(|i| (i % 7) as u32).collect::<Vec<_>>();
+        let mut after = before.clone();
+        let mut changes = vec![];
+        for (coords, delta) in [(UVec3::new(0, 3, 5), 4), (UVec3::new(14, 31, 8), -2)] {
+            let i = binning::coords_to_index(coords, resolution);
+            after[i] = (after[i] as i32 + delta) as u32;
+            changes.push((i, delta as f32));
+        }
+        for kernel in [Smoothing::None, KERNELS[0], KERNELS[1]] {
+            for wrap_hue in [false, true] {
+                let mut density = kernel.density(&before, resolution, wrap_hue);
+                let spread = kernel.spread(&changes, resolution, wrap_hue).unwrap();
+                for (i, d) in spread {
+                    density[i] += d;
+                }
+                let expected = kernel.density(&after, resolution, wrap_hue);
+                for (a, b) in density.iter().zip(&expected) {
+                    assert!((a - b).abs() < 1e-4, "{kernel:?} {a} {b}");
+                }
+            }
+        }
+
+        // Changing every bin is left to a full smoothing
+        let everywhere = (0..num_bins).map(|i| (i, 1.0)).collect::<Vec<_>>();
+        assert!(KERNELS[0].spread(&everywhere, resolution, false).is_none());
+    }
+
+    #[test]
+    fn sides_keep_flat_densities_and_hue_wraps() {
+        let resolution = UVec3::new(8, 4, 4);
+        let flat = vec![10; binning::num_bins(resolution)];
+        for kernel in KERNELS {
+            let density = kernel.density(&flat, resolution, false);
+            assert!(density.iter().all(|&d| (d - 10.0).abs() < 1e-3));
+        }
+
+        // A count at hue 0 spreads to the last hues only when they wrap
+        let mut counts = vec![0; binning::num_bins(resolution)];
+        counts[binning::coords_to_index(UVec3::ZERO, resolution)] = 100;
+        let last = binning::coords_to_index(UVec3::new(7, 0, 0), resolution);
+        let kernel = Smoothing::Gaussian { bandwidth: 1.0 };
+        assert_eq!(kernel.density(&counts, resolution, false)[last], 0.0);
+        assert!(kernel.density(&counts, resolution, true)[last] > 0.0);
+    }
+}
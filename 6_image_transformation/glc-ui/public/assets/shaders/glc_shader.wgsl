@@ -34,8 +34,10 @@ struct Vertex {
     [[location(2)]]
     uv: vec2<f32>;
     [[location(3)]]
-    i_pos_scale: vec4<f32>;
+    i_position: vec3<f32>;
     [[location(4)]]
+    i_scale: vec3<f32>;
+    [[location(5)]]
     i_color: vec4<f32>;
 };
 
@@ -48,7 +50,7 @@ struct VertexOut {
 
 [[stage(vertex)]]
 fn vertex(vertex: Vertex) -> VertexOut {
-    let position = vertex.position * vertex.i_pos_scale.w + vertex.i_pos_scale.xyz;
+    let position = vertex.position * vertex.i_scale + vertex.i_position;
     let world_position = mesh.model * vec4<f32>(position, 1.0);
 
     var out: VertexOut;
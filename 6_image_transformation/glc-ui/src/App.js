@@ -1,18 +1,43 @@
 import React from "react";
 import CssBaseline from '@mui/material/CssBaseline';
-import { Container, Grid, List, ListItem } from "@mui/material";
+import { Container, Grid, List, ListItem, Typography } from "@mui/material";
 import Viewer from "./Viewer";
-import { Glc, init } from 'glc-wasm';
+import * as glcWasm from 'glc-wasm';
 import InputImage from "./InputImage";
 import ImageGallery from "./ImageGallery";
 import { blobToImageData } from "./utils";
 import ColorTransormation from "./ColorTransformation";
+import CubeSettings from "./CubeSettings";
+import CubeClipping from "./CubeClipping";
+import ColorSelection from "./ColorSelection";
+import Keyer from "./Keyer";
+import GamutHull from "./GamutHull";
 import OutputImage from "./OutputImage";
+import ProcessingProgress from "./ProcessingProgress";
+
+const { Glc, init } = glcWasm;
 
 init();
 
+// Threaded builds export startThreadPool, which needs a cross-origin isolated page
+function initThreads(glc) {
+    if (!glcWasm.startThreadPool || !window.crossOriginIsolated) {
+        return;
+    }
+    const numThreads = navigator.hardwareConcurrency;
+    glcWasm.startThreadPool(numThreads).then(() => glc.set_thread_count(numThreads));
+}
+
 export default function App() {
     const [inputImage, setInputImage] = React.useState(null);
+    const [progress, setProgress] = React.useState({stage: '', done: 0, total: 0});
+    const [pickedBin, setPickedBin] = React.useState(null);
+    const [probe, setProbe] = React.useState(null);
+    const [gamutHull, setGamutHull] = React.useState(false);
+    const [gamutMetrics, setGamutMetrics] = React.useState({input: null, output: null});
+    const [selectionMode, setSelectionMode] = React.useState('off');
+    const [selectionSize, setSelectionSize] = React.useState(0.2);
+    const [selectionCenter, setSelectionCenter] = React.useState(null);
     const glcRef = React.useRef();
     const requestRef = React.useRef();
 
@@ -29,6 +54,87 @@ export default function App() {
         }
     }
 
+    const handleHover = (x, y) => {
+        const bin = glcRef.current.pick(x, y);
+        if (!bin) {
+            setPickedBin(null);
+            return;
+        }
+        setPickedBin({
+            index: bin.index,
+            count: bin.count,
+            density: bin.density,
+            percentage: bin.percentage,
+            rangeMin: Array.from(bin.range_min),
+            rangeMax: Array.from(bin.range_max),
+        });
+        bin.free();
+    }
+
+    const handleProbe = (x, y) => {
+        const pixel = glcRef.current.probe(x, y);
+        if (!pixel) {
+            setProbe(null);
+            return;
+        }
+        const color = c => {
+            const probed = {hex: c.hex, rgb: Array.from(c.rgb), hsv: Array.from(c.hsv), lab: Array.from(c.lab)};
+            c.free();
+            return probed;
+        };
+        setProbe({x: pixel.x, y: pixel.y, input: color(pixel.input), output: color(pixel.output)});
+        pixel.free();
+    }
+
+    const applySelection = (mode, size, center) => {
+        if (mode === 'box' && center) {
+            const [x, y, z] = center;
+            const h = size / 2;
+            glcRef.current.select_box(x - h, y - h, z - h, x + h, y + h, z + h);
+        } else if (mode === 'sphere' && center) {
+            glcRef.current.select_sphere(...center, size / 2);
+        } else if (mode !== 'lasso') {
+            glcRef.current.clear_selection();
+        }
+    }
+
+    const handleSelectionMode = mode => {
+        setSelectionMode(mode);
+        if (mode === 'lasso') {
+            glcRef.current.clear_selection();
+        }
+        applySelection(mode, selectionSize, selectionCenter);
+    }
+
+    const handleSelectionSize = size => {
+        setSelectionSize(size);
+        applySelection(selectionMode, size, selectionCenter);
+    }
+
+    const handleCtrlClick = (x, y) => {
+        if (selectionMode !== 'box' && selectionMode !== 'sphere') {
+            return;
+        }
+        const bin = glcRef.current.pick(x, y);
+        if (!bin) {
+            return;
+        }
+        const center = Array.from(bin.center);
+        bin.free();
+        setSelectionCenter(center);
+        applySelection(selectionMode, selectionSize, center);
+    }
+
+    const handleLasso = points => {
+        if (selectionMode === 'lasso') {
+            glcRef.current.select_lasso(new Float32Array(points.flat()));
+        }
+    }
+
+    const handleSelectionSource = source => {
+        glcRef.current.set_selection_source(source);
+    }
+
     const handleSelectImage = ({file, url}) => {
         setInputImage(url);
         Promise.resolve(blobToImageData(file))
@@ -39,9 +145,146 @@ export default function App() {
         glcRef.current.rotate(r);
     }
 
+    const handleLattice = (style, resolution) => {
+        glcRef.current.set_lattice(style, resolution);
+    }
+
+    const handleQualifier = (space, shape, center, extent, softness) => {
+        if (space) {
+            glcRef.current.set_qualifier(space, shape, new Float32Array(center), new Float32Array(extent), softness);
+        } else {
+            glcRef.current.clear_qualifier();
+        }
+    }
+
+    const handleShowMatte = enabled => {
+        glcRef.current.set_show_matte(enabled);
+    }
+
+    const handleKey = (color, tolerance, falloff) => {
+        if (color === 'selection') {
+            glcRef.current.key_selection();
+        } else if (color) {
+            const extent = new Float32Array([tolerance, tolerance, tolerance]);
+            glcRef.current.set_key('rgb', 'ellipsoid', new Float32Array(color), extent, falloff);
+        } else {
+            glcRef.current.clear_key();
+        }
+    }
+
+    const handleKeyMatte = ({spill, choke, softness, premultiply}) => {
+        glcRef.current.set_key_matte(spill, choke, softness, premultiply);
+    }
+
+    const handleKeyBackground = color => {
+        if (color) {
+            glcRef.current.set_key_background_color(...color);
+        } else {
+            glcRef.current.clear_key_background();
+        }
+    }
+
+    const handleFullQuality = enabled => {
+        glcRef.current.set_force_full_quality(enabled);
+    }
+
+    const handleNormalization = (mode, param) => {
+        glcRef.current.set_normalization(mode, param);
+    }
+
+    const handleResolution = r => {
+        glcRef.current.set_cube_resolution(r, r, r);
+    }
+
+    const handleBinPosition = position => {
+        glcRef.current.set_bin_position(position);
+    }
+
+    const handleBinColor = color => {
+        glcRef.current.set_bin_color(color);
+    }
+
+    const handleSmoothing = (kernel, bandwidth) => {
+        glcRef.current.set_smoothing(kernel, bandwidth);
+    }
+
+    const handleGizmo = ({frame, axes, corners, ticks}) => {
+        glcRef.current.set_gizmo(frame, axes, corners, ticks);
+    }
+
+    const handleLayout = layout => {
+        glcRef.current.set_layout(layout);
+    }
+
+    const handleViewMode = mode => {
+        glcRef.current.set_view_mode(mode);
+    }
+
+    const handlePointSampling = (sampleSize, sampling) => {
+        glcRef.current.set_point_sampling(sampleSize, sampling);
+    }
+
+    const handleIsosurfaceLevels = levels => {
+        glcRef.current.set_isosurface_levels(new Float32Array(levels));
+    }
+
+    const handleGamutHull = enabled => {
+        setGamutHull(enabled);
+        glcRef.current.set_gamut_hull(enabled);
+    }
+
+    const handleReferenceGamut = (gamut, visible) => {
+        glcRef.current.set_reference_gamut(gamut, visible);
+    }
+
+    const readGamutMetrics = image => {
+        const m = glcRef.current.gamut_metrics(image);
+        if (!m) {
+            return null;
+        }
+        const metrics = {
+            rgbVolume: m.rgb_volume,
+            labVolume: m.lab_volume,
+            srgbCoverage: m.srgb_coverage,
+            p3Coverage: m.p3_coverage,
+            rec2020Coverage: m.rec2020_coverage,
+        };
+        m.free();
+        return metrics;
+    }
+
+    // The hull is computed in the background once the output is complete
+    React.useEffect(() => {
+        if (!gamutHull) {
+            return;
+        }
+        const id = setInterval(() => {
+            setGamutMetrics({input: readGamutMetrics('input'), output: readGamutMetrics('output')});
+        }, 500);
+        return () => clearInterval(id);
+    }, [gamutHull]);
+
+    const handleComparison = (showInput, showVectors) => {
+        glcRef.current.set_comparison(showInput, showVectors);
+    }
+
+    const handleClipPlanes = planes => {
+        glcRef.current.set_clip_planes(new Float32Array(planes));
+    }
+
+    const handleSlice = (normal, min, max) => {
+        if (normal) {
+            glcRef.current.set_slice(...normal, min, max);
+        } else {
+            glcRef.current.clear_slice();
+        }
+    }
+
     React.useEffect(() => {
         glcRef.current = Glc.new("glc-canvas");
         glcRef.current.set_output_canvas("glc-out-canvas");
+        glcRef.current.on_progress((stage, done, total) => setProgress({stage, done, total}));
+        initThreads(glcRef.current);
         requestRef.current = requestAnimationFrame(update);
 
         return () => {
@@ -53,7 +296,23 @@ export default function App() {
         <React.Fragment>
             <CssBaseline>
                 <Container>
-                    <Viewer canvasId='glc-canvas' onMouseMove={handleMouseMove}/>
+                    <Viewer
+                        canvasId='glc-canvas'
+                        onMouseMove={handleMouseMove}
+                        onHover={handleHover}
+                        onCtrlClick={handleCtrlClick}
+                        onLasso={handleLasso}
+                    >
+                        <Typography variant='caption' display='block' sx={{minHeight: '1.5em'}}>
+                            {pickedBin &&
+                                `Bin ${pickedBin.index}: ` +
+                                `[${pickedBin.rangeMin.map(x => x.toFixed(2)).join(', ')}] - ` +
+                                `[${pickedBin.rangeMax.map(x => x.toFixed(2)).join(', ')}], ` +
+                                `${pickedBin.count} pixels (${pickedBin.percentage.toFixed(2)}%)` +
+                                (pickedBin.density !== pickedBin.count ?
+                                    `, smoothed ${pickedBin.density.toFixed(1)}` : '')}
+                        </Typography>
+                    </Viewer>
                     <Grid 
                         container
                         spacing={1}
@@ -64,13 +323,55 @@ export default function App() {
                         <Grid item xs={4}>
                             <List>
                                 <ListItem>
-                                    <ColorTransormation onTransform={handleTransform}/>
+                                    <ColorTransormation
+                                        onTransform={handleTransform}
+                                        onFullQuality={handleFullQuality}
+                                        onLattice={handleLattice}
+                                        onQualifier={handleQualifier}
+                                        onShowMatte={handleShowMatte}
+                                    />
+                                </ListItem>
+                                <ListItem>
+                                    <CubeSettings
+                                        onNormalization={handleNormalization}
+                                        onResolution={handleResolution}
+                                        onBinPosition={handleBinPosition}
+                                        onBinColor={handleBinColor}
+                                        onSmoothing={handleSmoothing}
+                                        onLayout={handleLayout}
+                                        onViewMode={handleViewMode}
+                                        onPointSampling={handlePointSampling}
+                                        onIsosurfaceLevels={handleIsosurfaceLevels}
+                                        onComparison={handleComparison}
+                                        onGizmo={handleGizmo}
+                                    />
+                                </ListItem>
+                                <ListItem>
+                                    <GamutHull metrics={gamutMetrics} onGamutHull={handleGamutHull} onReferenceGamut={handleReferenceGamut} />
+                                </ListItem>
+                                <ListItem>
+                                    <CubeClipping onClipPlanes={handleClipPlanes} onSlice={handleSlice} />
+                                </ListItem>
+                                <ListItem>
+                                    <Keyer onKey={handleKey} onKeyMatte={handleKeyMatte} onKeyBackground={handleKeyBackground} />
+                                </ListItem>
+                                <ListItem>
+                                    <ColorSelection
+                                        mode={selectionMode}
+                                        size={selectionSize}
+                                        onMode={handleSelectionMode}
+                                        onSize={handleSelectionSize}
+                                        onSource={handleSelectionSource}
+                                    />
                                 </ListItem>
                                 <ListItem>
                                     <InputImage imageUrl={inputImage} />
                                 </ListItem>
                                 <ListItem>
-                                    <OutputImage canvasId='glc-out-canvas' />
+                                    <OutputImage canvasId='glc-out-canvas' probe={probe} onProbe={handleProbe} />
+                                </ListItem>
+                                <ListItem>
+                                    <ProcessingProgress progress={progress} />
                                 </ListItem>
                             </List>
                         </Grid>
@@ -0,0 +1,68 @@
+import { Container, FormControl, InputLabel, MenuItem, Select, Slider, Typography } from "@mui/material";
+import { Box } from "@mui/system";
+import React from "react";
+
+const selectionModes = {
+    off: {label: 'Off'},
+    box: {label: 'Box', hint: 'Ctrl+click a bin to place the box'},
+    sphere: {label: 'Sphere', hint: 'Ctrl+click a bin to place the sphere'},
+    lasso: {label: 'Lasso', hint: 'Ctrl+drag over the cube to draw'},
+};
+
+const selectionSources = {
+    output: 'Output colors',
+    input: 'Input colors',
+};
+
+export default function ColorSelection({mode, size, onMode, onSize, onSource}) {
+    const [source, setSource] = React.useState('output');
+
+    const handleSource = e => {
+        setSource(e.target.value);
+        onSource(e.target.value);
+    }
+
+    const hint = selectionModes[mode].hint;
+
+    return (
+        <Container>
+            <Box sx={{width: 200}}>
+                <FormControl fullWidth size='small' sx={{mb: 2}}>
+                    <InputLabel>Color selection</InputLabel>
+                    <Select value={mode} label='Color selection' onChange={e => onMode(e.target.value)}>
+                        {Object.entries(selectionModes).map(([key, {label}]) =>
+                            <MenuItem key={key} value={key}>{label}</MenuItem>
+                        )}
+                    </Select>
+                </FormControl>
+                {hint &&
+                    <Typography variant='caption' display='block' gutterBottom>
+                        {hint}
+                    </Typography>}
+                {(mode === 'box' || mode === 'sphere') &&
+                    <React.Fragment>
+                        <Typography gutterBottom>
+                            Size
+                        </Typography>
+                        <Slider
+                            value={size}
+                            step={0.01}
+                            min={0.02}
+                            max={1}
+                            valueLabelDisplay='auto'
+                            onChange={(e, v) => onSize(v)}
+                        />
+                    </React.Fragment>}
+                {mode !== 'off' &&
+                    <FormControl fullWidth size='small'>
+                        <InputLabel>Match</InputLabel>
+                        <Select value={source} label='Match' onChange={handleSource}>
+                            {Object.entries(selectionSources).map(([key, label]) =>
+                                <MenuItem key={key} value={key}>{label}</MenuItem>
+                            )}
+                        </Select>
+                    </FormControl>}
+            </Box>
+        </Container>
+    );
+}
@@ -1,13 +1,68 @@
-import { Container, Slider, Typography } from "@mui/material";
+import { Container, FormControl, FormControlLabel, InputLabel, MenuItem, Select, Slider, Switch, Typography } from "@mui/material";
 import { Box } from "@mui/system";
 import React from "react";
 
-export default function ColorTransormation({onTransform}) {
+const latticeStyles = {
+    off: 'Off',
+    points: 'Points',
+    grid: 'Grid',
+};
+
+export default function ColorTransormation({onTransform, onFullQuality, onLattice, onQualifier, onShowMatte}) {
+    const [qualified, setQualified] = React.useState(false);
+    const [hue, setHue] = React.useState(120);
+    const [hueWidth, setHueWidth] = React.useState(30);
+    const [softness, setSoftness] = React.useState(0.5);
+    const [latticeStyle, setLatticeStyle] = React.useState('off');
+    const [latticeResolution, setLatticeResolution] = React.useState(9);
 
     const handleChange = (e, v) => {
         onTransform(v);
     }
 
+    const handleFullQuality = (e, checked) => {
+        onFullQuality(checked);
+    }
+
+    const handleLatticeStyle = e => {
+        setLatticeStyle(e.target.value);
+        onLattice(e.target.value, latticeResolution);
+    }
+
+    const handleLatticeResolution = (e, v) => {
+        setLatticeResolution(v);
+        onLattice(latticeStyle, v);
+    }
+
+    // Hue ranges are boxes in HSL spanning every saturation and lightness
+    const applyQualifier = (enabled, hue, hueWidth, softness) => {
+        if (enabled) {
+            onQualifier('hsl', 'box', [hue / 360, 0.5, 0.5], [hueWidth / 720, 0.5, 0.5], softness);
+        } else {
+            onQualifier(null);
+        }
+    }
+
+    const handleQualified = (e, checked) => {
+        setQualified(checked);
+        applyQualifier(checked, hue, hueWidth, softness);
+    }
+
+    const handleHue = (e, v) => {
+        setHue(v);
+        applyQualifier(qualified, v, hueWidth, softness);
+    }
+
+    const handleHueWidth = (e, v) => {
+        setHueWidth(v);
+        applyQualifier(qualified, hue, v, softness);
+    }
+
+    const handleSoftness = (e, v) => {
+        setSoftness(v);
+        applyQualifier(qualified, hue, hueWidth, v);
+    }
+
     return (
         <Container>
             <Box sx={{width: 200}}>
@@ -21,6 +76,55 @@ export default function ColorTransormation({onTransform}) {
                     max={360}
                     onChange={handleChange}
                 />
+                <FormControlLabel
+                    control={<Switch onChange={handleFullQuality} />}
+                    label='Full quality'
+                />
+                <FormControl fullWidth size='small' sx={{mt: 2}}>
+                    <InputLabel>Warped lattice</InputLabel>
+                    <Select value={latticeStyle} label='Warped lattice' onChange={handleLatticeStyle}>
+                        {Object.entries(latticeStyles).map(([key, label]) =>
+                            <MenuItem key={key} value={key}>{label}</MenuItem>
+                        )}
+                    </Select>
+                </FormControl>
+                {latticeStyle !== 'off' &&
+                    <React.Fragment>
+                        <Typography gutterBottom>
+                            Lattice nodes per axis
+                        </Typography>
+                        <Slider
+                            value={latticeResolution}
+                            step={1}
+                            min={2}
+                            max={17}
+                            valueLabelDisplay='auto'
+                            onChange={handleLatticeResolution}
+                        />
+                    </React.Fragment>}
+                <FormControlLabel
+                    control={<Switch checked={qualified} onChange={handleQualified} />}
+                    label='Only a hue range'
+                />
+                {qualified &&
+                    <React.Fragment>
+                        <Typography gutterBottom>
+                            Hue
+                        </Typography>
+                        <Slider value={hue} step={1} min={0} max={360} valueLabelDisplay='auto' onChange={handleHue} />
+                        <Typography gutterBottom>
+                            Hue width
+                        </Typography>
+                        <Slider value={hueWidth} step={1} min={1} max={180} valueLabelDisplay='auto' onChange={handleHueWidth} />
+                        <Typography gutterBottom>
+                            Softness
+                        </Typography>
+                        <Slider value={softness} step={0.05} min={0} max={2} valueLabelDisplay='auto' onChange={handleSoftness} />
+                        <FormControlLabel
+                            control={<Switch onChange={(e, checked) => onShowMatte(checked)} />}
+                            label='Show matte'
+                        />
+                    </React.Fragment>}
             </Box>
         </Container>
     );
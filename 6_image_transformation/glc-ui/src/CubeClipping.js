@@ -0,0 +1,130 @@
+import { Container, FormControl, FormControlLabel, InputLabel, MenuItem, Select, Slider, Switch, Typography } from "@mui/material";
+import { Box } from "@mui/system";
+import React from "react";
+
+const axes = {
+    off: {label: 'Off'},
+    red: {label: 'Red', normal: [1, 0, 0]},
+    green: {label: 'Green', normal: [0, 1, 0]},
+    blue: {label: 'Blue', normal: [0, 0, 1]},
+};
+
+function AxisSelect({label, value, onChange}) {
+    return (
+        <FormControl fullWidth size='small' sx={{mb: 2}}>
+            <InputLabel>{label}</InputLabel>
+            <Select value={value} label={label} onChange={e => onChange(e.target.value)}>
+                {Object.entries(axes).map(([key, {label}]) =>
+                    <MenuItem key={key} value={key}>{label}</MenuItem>
+                )}
+            </Select>
+        </FormControl>
+    );
+}
+
+export default function CubeClipping({onClipPlanes, onSlice}) {
+    const [clipAxis, setClipAxis] = React.useState('off');
+    const [clipOffset, setClipOffset] = React.useState(0.5);
+    const [clipFlipped, setClipFlipped] = React.useState(false);
+    const [sliceAxis, setSliceAxis] = React.useState('off');
+    const [slicePosition, setSlicePosition] = React.useState(0.45);
+    const [sliceThickness, setSliceThickness] = React.useState(0.1);
+
+    const applyClipPlanes = (axis, offset, flipped) => {
+        const normal = axes[axis].normal;
+        if (!normal) {
+            onClipPlanes([]);
+        } else if (flipped) {
+            onClipPlanes([...normal.map(x => -x), -offset]);
+        } else {
+            onClipPlanes([...normal, offset]);
+        }
+    }
+
+    const applySlice = (axis, position, thickness) => {
+        const normal = axes[axis].normal;
+        onSlice(normal, position - thickness / 2, position + thickness / 2);
+    }
+
+    const handleClipAxis = axis => {
+        setClipAxis(axis);
+        applyClipPlanes(axis, clipOffset, clipFlipped);
+    }
+
+    const handleClipOffset = (e, v) => {
+        setClipOffset(v);
+        applyClipPlanes(clipAxis, v, clipFlipped);
+    }
+
+    const handleClipFlipped = e => {
+        setClipFlipped(e.target.checked);
+        applyClipPlanes(clipAxis, clipOffset, e.target.checked);
+    }
+
+    const handleSliceAxis = axis => {
+        setSliceAxis(axis);
+        applySlice(axis, slicePosition, sliceThickness);
+    }
+
+    const handleSlicePosition = (e, v) => {
+        setSlicePosition(v);
+        applySlice(sliceAxis, v, sliceThickness);
+    }
+
+    const handleSliceThickness = (e, v) => {
+        setSliceThickness(v);
+        applySlice(sliceAxis, slicePosition, v);
+    }
+
+    return (
+        <Container>
+            <Box sx={{width: 200}}>
+                <AxisSelect label='Clipping plane' value={clipAxis} onChange={handleClipAxis} />
+                {clipAxis !== 'off' &&
+                    <React.Fragment>
+                        <Typography gutterBottom>
+                            Clip above
+                        </Typography>
+                        <Slider
+                            value={clipOffset}
+                            step={0.01}
+                            min={0}
+                            max={1}
+                            valueLabelDisplay='auto'
+                            onChange={handleClipOffset}
+                        />
+                        <FormControlLabel
+                            control={<Switch checked={clipFlipped} onChange={handleClipFlipped} />}
+                            label='Clip below instead'
+                        />
+                    </React.Fragment>}
+                <AxisSelect label='Slice' value={sliceAxis} onChange={handleSliceAxis} />
+                {sliceAxis !== 'off' &&
+                    <React.Fragment>
+                        <Typography gutterBottom>
+                            Slice position
+                        </Typography>
+                        <Slider
+                            value={slicePosition}
+                            step={0.01}
+                            min={0}
+                            max={1}
+                            valueLabelDisplay='auto'
+                            onChange={handleSlicePosition}
+                        />
+                        <Typography gutterBottom>
+                            Slice thickness
+                        </Typography>
+                        <Slider
+                            value={sliceThickness}
+                            step={0.01}
+                            min={0.01}
+                            max={0.5}
+                            valueLabelDisplay='auto'
+                            onChange={handleSliceThickness}
+                        />
+                    </React.Fragment>}
+            </Box>
+        </Container>
+    );
+}
@@ -0,0 +1,270 @@
+import { Container, FormControl, FormControlLabel, InputLabel, MenuItem, Select, Slider, Switch, Typography } from "@mui/material";
+import { Box } from "@mui/system";
+import React from "react";
+
+const normalizationModes = {
+    linear: {label: 'Linear', param: {name: 'Threshold (%)', min: 0.01, max: 1, step: 0.01, value: 0.1, scale: 0.01}},
+    log: {label: 'Logarithmic'},
+    sqrt: {label: 'Square root'},
+    percentile: {label: 'Percentile', param: {name: 'Percentile', min: 50, max: 100, step: 0.5, value: 99, scale: 1}},
+    auto: {label: 'Auto threshold'},
+};
+
+const layouts = {
+    rgb: 'RGB cube',
+    hsv: 'HSV cylinder',
+    hsl: 'HSL double cone',
+    lab: 'CIELAB',
+    oklab: 'OKLab',
+};
+
+const viewModes = {
+    bins: 'Bins',
+    points: 'Pixel points',
+    isosurface: 'Density isosurfaces',
+};
+
+const smoothingKernels = {
+    none: 'None',
+    gaussian: 'Gaussian',
+    epanechnikov: 'Epanechnikov',
+};
+
+const gizmoParts = {
+    frame: 'Frame',
+    axes: 'RGB axes',
+    corners: 'Corner colors',
+    ticks: 'Axis ticks',
+};
+
+const binColors = {
+    lattice: 'Lattice',
+    mean: 'Mean of pixels',
+    median: 'Median of pixels',
+};
+
+export default function CubeSettings({onNormalization, onResolution, onBinPosition, onBinColor, onSmoothing, onLayout, onViewMode, onPointSampling, onIsosurfaceLevels, onComparison, onGizmo}) {
+    const [mode, setMode] = React.useState('linear');
+    const [resolution, setResolution] = React.useState(32);
+    const [viewMode, setViewMode] = React.useState('bins');
+    const [sampleSize, setSampleSize] = React.useState(20000);
+    const [randomSampling, setRandomSampling] = React.useState(false);
+    const [isoLevels, setIsoLevels] = React.useState([2, 10, 40]);
+    const [showInput, setShowInput] = React.useState(false);
+    const [showVectors, setShowVectors] = React.useState(false);
+    const [gizmo, setGizmo] = React.useState({frame: true, axes: true, corners: true, ticks: false});
+    const [layout, setLayout] = React.useState('rgb');
+    const [binColor, setBinColor] = React.useState('lattice');
+    const [smoothing, setSmoothing] = React.useState('none');
+    const [bandwidth, setBandwidth] = React.useState(1.5);
+    const [param, setParam] = React.useState(normalizationModes.linear.param.value);
+
+    const applyNormalization = (mode, value) => {
+        const handleSmoothing = e => {
+        setSmoothing(e.target.value);
+        onSmoothing(e.target.value, bandwidth);
+    }
+
+    const p = normalizationModes[mode].param;
+        onNormalization(mode, p ? value * p.scale : 0);
+    }
+
+    const handleMode = e => {
+        const newMode = e.target.value;
+        const p = normalizationModes[newMode].param;
+        const value = p ? p.value : 0;
+        setMode(newMode);
+        setParam(value);
+        applyNormalization(newMode, value);
+    }
+
+    const handleParam = (e, v) => {
+        setParam(v);
+        applyNormalization(mode, v);
+    }
+
+    const handleResolution = (e, v) => {
+        setResolution(v);
+    }
+
+    const handleCenterOfMass = e => {
+        onBinPosition(e.target.checked ? 'center-of-mass' : 'center');
+    }
+
+    const handleViewMode = e => {
+        setViewMode(e.target.value);
+        onViewMode(e.target.value);
+    }
+
+    const handleRandomSampling = e => {
+        setRandomSampling(e.target.checked);
+        onPointSampling(sampleSize, e.target.checked ? 'random' : 'stratified');
+    }
+
+    const handleShowInput = e => {
+        setShowInput(e.target.checked);
+        onComparison(e.target.checked, showVectors);
+    }
+
+    const handleShowVectors = e => {
+        setShowVectors(e.target.checked);
+        onComparison(showInput, e.target.checked);
+    }
+
+    const handleGizmo = part => e => {
+        const newGizmo = {...gizmo, [part]: e.target.checked};
+        setGizmo(newGizmo);
+        onGizmo(newGizmo);
+    }
+
+    const handleLayout = e => {
+        setLayout(e.target.value);
+        onLayout(e.target.value);
+    }
+
+    const handleBinColor = e => {
+        setBinColor(e.target.value);
+        onBinColor(e.target.value);
+    }
+
+    const p = normalizationModes[mode].param;
+
+    return (
+        <Container>
+            <Box sx={{width: 200}}>
+                <FormControl fullWidth size='small' sx={{mb: 2}}>
+                    <InputLabel>View</InputLabel>
+                    <Select value={viewMode} label='View' onChange={handleViewMode}>
+                        {Object.entries(viewModes).map(([key, label]) =>
+                            <MenuItem key={key} value={key}>{label}</MenuItem>
+                        )}
+                    </Select>
+                </FormControl>
+                {viewMode === 'points' &&
+                    <React.Fragment>
+                        <Typography gutterBottom>
+                            Sampled pixels
+                        </Typography>
+                        <Slider
+                            value={sampleSize}
+                            step={1000}
+                            min={1000}
+                            max={200000}
+                            valueLabelDisplay='auto'
+                            onChange={(e, v) => setSampleSize(v)}
+                            onChangeCommitted={(e, v) => onPointSampling(v, randomSampling ? 'random' : 'stratified')}
+                        />
+                        <FormControlLabel
+                            control={<Switch checked={randomSampling} onChange={handleRandomSampling} />}
+                            label='Random sampling'
+                        />
+                    </React.Fragment>}
+                {viewMode === 'isosurface' &&
+                    <React.Fragment>
+                        <Typography gutterBottom>
+                            Density levels (%)
+                        </Typography>
+                        <Slider
+                            value={isoLevels}
+                            min={1}
+                            max={99}
+                            valueLabelDisplay='auto'
+                            onChange={(e, v) => setIsoLevels(v)}
+                            onChangeCommitted={(e, v) => onIsosurfaceLevels(v.map(x => x / 100))}
+                        />
+                    </React.Fragment>}
+                <FormControlLabel
+                    control={<Switch checked={showInput} onChange={handleShowInput} />}
+                    label='Input histogram'
+                />
+                <FormControlLabel
+                    control={<Switch checked={showVectors} onChange={handleShowVectors} />}
+                    label='Displacement vectors'
+                />
+                {Object.entries(gizmoParts).map(([key, label]) =>
+                    <FormControlLabel
+                        key={key}
+                        control={<Switch checked={gizmo[key]} onChange={handleGizmo(key)} />}
+                        label={label}
+                    />
+                )}
+                <FormControl fullWidth size='small' sx={{mb: 2}}>
+                    <InputLabel>Layout</InputLabel>
+                    <Select value={layout} label='Layout' onChange={handleLayout}>
+                        {Object.entries(layouts).map(([key, label]) =>
+                            <MenuItem key={key} value={key}>{label}</MenuItem>
+                        )}
+                    </Select>
+                </FormControl>
+                <Typography gutterBottom>
+                    Bins per axis
+                </Typography>
+                <Slider
+                    value={resolution}
+                    step={1}
+                    min={2}
+                    max={64}
+                    valueLabelDisplay='auto'
+                    onChange={handleResolution}
+                    onChangeCommitted={(e, v) => onResolution(v)}
+                />
+                <FormControlLabel
+                    control={<Switch onChange={handleCenterOfMass} />}
+                    label='Center of mass'
+                />
+                <FormControl fullWidth size='small' sx={{mb: 2}}>
+                    <InputLabel>Bin color</InputLabel>
+                    <Select value={binColor} label='Bin color' onChange={handleBinColor}>
+                        {Object.entries(binColors).map(([key, label]) =>
+                            <MenuItem key={key} value={key}>{label}</MenuItem>
+                        )}
+                    </Select>
+                </FormControl>
+                <FormControl fullWidth size='small' sx={{mb: 2}}>
+                    <InputLabel>Smoothing</InputLabel>
+                    <Select value={smoothing} label='Smoothing' onChange={handleSmoothing}>
+                        {Object.entries(smoothingKernels).map(([key, label]) =>
+                            <MenuItem key={key} value={key}>{label}</MenuItem>
+                        )}
+                    </Select>
+                </FormControl>
+                {smoothing !== 'none' &&
+                    <React.Fragment>
+                        <Typography gutterBottom>
+                            Bandwidth (bins)
+                        </Typography>
+                        <Slider
+                            value={bandwidth}
+                            step={0.25}
+                            min={0.5}
+                            max={4}
+                            valueLabelDisplay='auto'
+                            onChange={(e, v) => setBandwidth(v)}
+                            onChangeCommitted={(e, v) => onSmoothing(smoothing, v)}
+                        />
+                    </React.Fragment>}
+                <FormControl fullWidth size='small'>
+                    <InputLabel>Bin scaling</InputLabel>
+                    <Select value={mode} label='Bin scaling' onChange={handleMode}>
+                        {Object.entries(normalizationModes).map(([key, {label}]) =>
+                            <MenuItem key={key} value={key}>{label}</MenuItem>
+                        )}
+                    </Select>
+                </FormControl>
+                {p &&
+                    <React.Fragment>
+                        <Typography gutterBottom>
+                            {p.name}
+                        </Typography>
+                        <Slider
+                            value={param}
+                            step={p.step}
+                            min={p.min}
+                            max={p.max}
+                            onChange={handleParam}
+                        />
+                    </React.Fragment>}
+            </Box>
+        </Container>
+    );
+}
@@ -0,0 +1,66 @@
+import { Checkbox, Container, FormControlLabel, FormGroup, Switch, Table, TableBody, TableCell, TableHead, TableRow } from "@mui/material";
+import { Box } from "@mui/system";
+import React from "react";
+
+const rows = [
+    ['RGB volume', m => m.rgbVolume.toFixed(3)],
+    ['Lab volume', m => Math.round(m.labVolume).toLocaleString()],
+    ['sRGB', m => `${m.srgbCoverage.toFixed(1)}%`],
+    ['Display P3', m => `${m.p3Coverage.toFixed(1)}%`],
+    ['Rec. 2020', m => `${m.rec2020Coverage.toFixed(1)}%`],
+];
+
+const referenceGamuts = {
+    'srgb': 'sRGB',
+    'display-p3': 'Display P3',
+    'rec2020': 'Rec. 2020',
+    'adobe-rgb': 'Adobe RGB',
+};
+
+export default function GamutHull({metrics, onGamutHull, onReferenceGamut}) {
+    const [enabled, setEnabled] = React.useState(false);
+
+    const handleEnabled = (e, checked) => {
+        setEnabled(checked);
+        onGamutHull(checked);
+    }
+
+    return (
+        <Container>
+            <Box sx={{width: 300}}>
+                <FormControlLabel
+                    control={<Switch checked={enabled} onChange={handleEnabled} />}
+                    label='Gamut hull'
+                />
+                <FormGroup row>
+                    {Object.entries(referenceGamuts).map(([key, label]) =>
+                        <FormControlLabel
+                            key={key}
+                            control={<Checkbox size='small' onChange={e => onReferenceGamut(key, e.target.checked)} />}
+                            label={label}
+                        />
+                    )}
+                </FormGroup>
+                {enabled && metrics.output &&
+                    <Table size='small'>
+                        <TableHead>
+                            <TableRow>
+                                <TableCell />
+                                <TableCell align='right'>Input</TableCell>
+                                <TableCell align='right'>Output</TableCell>
+                            </TableRow>
+                        </TableHead>
+                        <TableBody>
+                            {rows.map(([label, format]) =>
+                                <TableRow key={label}>
+                                    <TableCell>{label}</TableCell>
+                                    <TableCell align='right'>{metrics.input ? format(metrics.input) : '-'}</TableCell>
+                                    <TableCell align='right'>{format(metrics.output)}</TableCell>
+                                </TableRow>
+                            )}
+                        </TableBody>
+                    </Table>}
+            </Box>
+        </Container>
+    );
+}
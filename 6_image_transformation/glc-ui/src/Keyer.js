@@ -0,0 +1,143 @@
+import { Container, FormControlLabel, Slider, Switch, Typography } from "@mui/material";
+import { Box } from "@mui/system";
+import React from "react";
+
+function hexToRgb(hex) {
+    return [1, 3, 5].map(i => parseInt(hex.slice(i, i + 2), 16) / 255);
+}
+
+function LabeledSlider({label, ...props}) {
+    return (
+        <React.Fragment>
+            <Typography gutterBottom>
+                {label}
+            </Typography>
+            <Slider valueLabelDisplay='auto' {...props} />
+        </React.Fragment>
+    );
+}
+
+export default function Keyer({onKey, onKeyMatte, onKeyBackground}) {
+    const [enabled, setEnabled] = React.useState(false);
+    const [fromSelection, setFromSelection] = React.useState(false);
+    const [keyColor, setKeyColor] = React.useState('#00b140');
+    const [tolerance, setTolerance] = React.useState(0.3);
+    const [falloff, setFalloff] = React.useState(0.5);
+    const [matte, setMatte] = React.useState({spill: 0.5, choke: 0, softness: 1, premultiply: false});
+    const [composite, setComposite] = React.useState(false);
+    const [background, setBackground] = React.useState('#202020');
+
+    const applyKey = (enabled, keyColor, tolerance, falloff, selection = fromSelection) => {
+        if (enabled && selection) {
+            onKey('selection');
+        } else if (enabled) {
+            onKey(hexToRgb(keyColor), tolerance, falloff);
+        } else {
+            onKey(null);
+        }
+    }
+
+    const applyBackground = (composite, background) => {
+        onKeyBackground(composite ? hexToRgb(background) : null);
+    }
+
+    const handleEnabled = (e, checked) => {
+        setEnabled(checked);
+        applyKey(checked, keyColor, tolerance, falloff);
+    }
+
+    const handleFromSelection = (e, checked) => {
+        setFromSelection(checked);
+        applyKey(enabled, keyColor, tolerance, falloff, checked);
+    }
+
+    const handleKeyColor = e => {
+        setKeyColor(e.target.value);
+        applyKey(enabled, e.target.value, tolerance, falloff);
+    }
+
+    const handleTolerance = (e, v) => {
+        setTolerance(v);
+        applyKey(enabled, keyColor, v, falloff);
+    }
+
+    const handleFalloff = (e, v) => {
+        setFalloff(v);
+        applyKey(enabled, keyColor, tolerance, v);
+    }
+
+    const handleMatte = changes => {
+        const newMatte = {...matte, ...changes};
+        setMatte(newMatte);
+        onKeyMatte(newMatte);
+    }
+
+    const handleComposite = (e, checked) => {
+        setComposite(checked);
+        applyBackground(checked, background);
+    }
+
+    const handleBackground = e => {
+        setBackground(e.target.value);
+        applyBackground(composite, e.target.value);
+    }
+
+    return (
+        <Container>
+            <Box sx={{width: 200}}>
+                <FormControlLabel
+                    control={<Switch checked={enabled} onChange={handleEnabled} />}
+                    label='Chroma key'
+                />
+                {enabled &&
+                    <React.Fragment>
+                        <FormControlLabel
+                            control={<Switch checked={fromSelection} onChange={handleFromSelection} />}
+                            label='Key the cube selection'
+                        />
+                        {!fromSelection &&
+                            <React.Fragment>
+                                <Typography gutterBottom>
+                                    Key color <input type='color' value={keyColor} onChange={handleKeyColor} />
+                                </Typography>
+                                <LabeledSlider label='Tolerance' value={tolerance} step={0.01} min={0.01} max={1} onChange={handleTolerance} />
+                                <LabeledSlider label='Falloff' value={falloff} step={0.05} min={0} max={2} onChange={handleFalloff} />
+                            </React.Fragment>}
+                        <LabeledSlider
+                            label='Spill suppression'
+                            value={matte.spill}
+                            step={0.05}
+                            min={0}
+                            max={1}
+                            onChange={(e, v) => handleMatte({spill: v})}
+                        />
+                        <LabeledSlider
+                            label='Choke (px)'
+                            value={matte.choke}
+                            step={1}
+                            min={-10}
+                            max={10}
+                            onChange={(e, v) => handleMatte({choke: v})}
+                        />
+                        <LabeledSlider
+                            label='Softness (px)'
+                            value={matte.softness}
+                            step={1}
+                            min={0}
+                            max={20}
+                            onChange={(e, v) => handleMatte({softness: v})}
+                        />
+                        <FormControlLabel
+                            control={<Switch checked={matte.premultiply} onChange={(e, checked) => handleMatte({premultiply: checked})} />}
+                            label='Premultiplied alpha'
+                        />
+                        <FormControlLabel
+                            control={<Switch checked={composite} onChange={handleComposite} />}
+                            label='Composite over'
+                        />
+                        {composite && <input type='color' value={background} onChange={handleBackground} />}
+                    </React.Fragment>}
+            </Box>
+        </Container>
+    );
+}
@@ -2,7 +2,23 @@ import { Container, Typography } from "@mui/material";
 import { Box } from "@mui/system";
 import React from "react";
 
-export default function OutputImage({canvasId}) {
+function formatColor({hex, rgb, hsv, lab}) {
+    return `${hex} ` +
+        `RGB(${rgb.map(x => x.toFixed(3)).join(', ')}) ` +
+        `HSV(${(hsv[0] * 360).toFixed(0)}°, ${hsv.slice(1).map(x => x.toFixed(2)).join(', ')}) ` +
+        `Lab(${lab.map(x => x.toFixed(1)).join(', ')})`;
+}
+
+export default function OutputImage({canvasId, probe, onProbe}) {
+
+    // Probes take pixels of the canvas's drawing buffer, which differ from the
+    // CSS pixels of the offsets when the canvas is scaled
+    const handleClick = e => {
+        const canvas = e.target;
+        const x = e.nativeEvent.offsetX * canvas.width / canvas.clientWidth;
+        const y = e.nativeEvent.offsetY * canvas.height / canvas.clientHeight;
+        onProbe(x, y);
+    }
 
     return (
         <Container>
@@ -10,8 +26,14 @@ export default function OutputImage({canvasId}) {
                 Output
             </Typography>
             <Box sx={{width: 300}}>
-                <canvas id={canvasId} />
+                <canvas id={canvasId} onClick={handleClick} style={{cursor: 'crosshair'}} />
             </Box>
+            {probe &&
+                <Typography variant='caption' display='block'>
+                    Pixel ({probe.x}, {probe.y})<br />
+                    In: {formatColor(probe.input)}<br />
+                    Out: {formatColor(probe.output)}
+                </Typography>}
         </Container>
     );
 }
@@ -0,0 +1,18 @@
+import { Container, LinearProgress, Typography } from "@mui/material";
+import { Box } from "@mui/system";
+import React from "react";
+
+export default function ProcessingProgress({progress}) {
+    const value = progress.total > 0 ? 100 * progress.done / progress.total : 100;
+
+    return (
+        <Container>
+            <Box sx={{width: 300}}>
+                <Typography variant='caption'>
+                    {value < 100 ? `Processing ${progress.stage}...` : 'Done'}
+                </Typography>
+                <LinearProgress variant='determinate' value={value} />
+            </Box>
+        </Container>
+    );
+}
@@ -1,21 +1,42 @@
 import React from 'react';
 import { Grid } from '@mui/material';
 
-export default function Viewer({canvasId, onMouseMove}) {
+const width = 800;
+const height = 400;
+
+export default function Viewer({canvasId, onMouseMove, onHover, onCtrlClick, onLasso, children}) {
     const [dragging, setDragging] = React.useState(false);
     const [prevCoords, setPrevCoords] = React.useState([0.0, 0.0]);
+    const [lasso, setLasso] = React.useState(null);
 
     const handleMouseDown = evt => {
+        if (evt.ctrlKey) {
+            const point = [evt.nativeEvent.offsetX, evt.nativeEvent.offsetY];
+            setLasso([point]);
+            onCtrlClick(...point);
+            return;
+        }
         setDragging(true);
         setPrevCoords([evt.clientX, evt.clientY]);
     }
 
     const handleMouseUp = evt => {
         setDragging(false);
+        if (lasso) {
+            if (lasso.length > 2) {
+                onLasso(lasso);
+            }
+            setLasso(null);
+        }
     }
 
     const handleMouseMove = evt => {
+        if (lasso) {
+            setLasso([...lasso, [evt.nativeEvent.offsetX, evt.nativeEvent.offsetY]]);
+            return;
+        }
         if (!dragging) {
+            onHover(evt.nativeEvent.offsetX, evt.nativeEvent.offsetY);
             return;
         }
         const delta = [evt.clientX - prevCoords[0], evt.clientY - prevCoords[1]];
@@ -25,16 +46,30 @@ export default function Viewer({canvasId, onMouseMove}) {
 
     return (
         <Grid container justifyContent={"center"}>
-            <Grid item>
+            <Grid item sx={{position: 'relative'}}>
                 <canvas 
                     id={canvasId}
-                    width='800px'
-                    height='400px'
+                    width={`${width}px`}
+                    height={`${height}px`}
                     onMouseDown={handleMouseDown}
                     onMouseUp={handleMouseUp}
                     onMouseMove={handleMouseMove}
+                    onMouseLeave={() => onHover(-1, -1)}
                 />
+                {lasso &&
+                    <svg
+                        width={width}
+                        height={height}
+                        style={{position: 'absolute', left: 0, top: 0, pointerEvents: 'none'}}
+                    >
+                        <polygon
+                            points={lasso.map(p => p.join(',')).join(' ')}
+                            fill='rgba(255, 255, 255, 0.2)'
+                            stroke='white'
+                        />
+                    </svg>}
+                {children}
             </Grid>
         </Grid>
     );
-}
\ No newline at end of file
+}
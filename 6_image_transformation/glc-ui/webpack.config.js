@@ -9,7 +9,12 @@ module.exports = {
   },
   devServer: {
     port: 3000,
-    watchFiles: ['src/**/*', '../glc-rs/pkg/*'] 
+    watchFiles: ['src/**/*', '../glc-rs/pkg/*'],
+    // Cross-origin isolation enables SharedArrayBuffer for threaded builds
+    headers: {
+      'Cross-Origin-Opener-Policy': 'same-origin',
+      'Cross-Origin-Embedder-Policy': 'require-corp'
+    }
   },
   module: {
     rules: [